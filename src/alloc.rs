@@ -0,0 +1,203 @@
+use crate::holders;
+
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use nix::pty::{OpenptyResult, Winsize};
+use nix::unistd::{Gid, Group};
+
+use std::ffi::{CStr, OsStr};
+use std::fs::OpenOptions;
+use std::io::Error as IoError;
+use std::os::fd::{AsRawFd as _, FromRawFd as _, IntoRawFd as _, OwnedFd, RawFd};
+use std::os::unix::ffi::OsStrExt as _;
+use std::os::unix::fs::{MetadataExt as _, OpenOptionsExt as _};
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Strategy {
+    Openpty,
+    PosixOpenpt,
+    // forkpty(3) is openpty, fork and login_tty. The fork stays with Command so that the
+    // other spawn options keep working; the child side does what login_tty does.
+    Forkpty,
+}
+
+impl Strategy {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "openpty" => Some(Self::Openpty),
+            "posix_openpt" => Some(Self::PosixOpenpt),
+            "forkpty" => Some(Self::Forkpty),
+            _ => None,
+        }
+    }
+}
+
+pub fn open(
+    strategy: Strategy,
+    winsize: Option<&Winsize>,
+) -> Result<OpenptyResult, Box<dyn std::error::Error>> {
+    match strategy {
+        Strategy::Openpty | Strategy::Forkpty => {
+            let pty = nix::pty::openpty(winsize, None)?;
+            let slave = nix::unistd::ttyname(pty.slave.as_raw_fd())?;
+            println!(
+                "PTY openpty master fd {} slave fd {} {}",
+                pty.master.as_raw_fd(),
+                pty.slave.as_raw_fd(),
+                slave.display()
+            );
+            if strategy == Strategy::Forkpty {
+                println!("PTY forkpty child will setsid and TIOCSCTTY on the slave fd");
+            }
+            Ok(pty)
+        }
+        Strategy::PosixOpenpt => posix_openpt(winsize),
+    }
+}
+
+fn posix_openpt(winsize: Option<&Winsize>) -> Result<OpenptyResult, Box<dyn std::error::Error>> {
+    let master = nix::pty::posix_openpt(OFlag::O_RDWR | OFlag::O_NOCTTY)?;
+    println!("PTY posix_openpt master fd {}", master.as_raw_fd());
+
+    let res = nix::pty::grantpt(&master);
+    println!("PTY grantpt {}", result(&res));
+    res?;
+    let res = nix::pty::unlockpt(&master);
+    println!("PTY unlockpt {}", result(&res));
+    res?;
+    let path = ptsname(master.as_raw_fd())?;
+    println!("PTY ptsname {}", path.display());
+
+    let slave = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(&path)
+        .map_err(|e| format!("{}: {e}", path.display()))?;
+    println!("PTY slave fd {}", slave.as_raw_fd());
+
+    let master = unsafe { OwnedFd::from_raw_fd(master.into_raw_fd()) };
+    if let Some(winsize) = winsize {
+        if unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, winsize) } == -1 {
+            return Err(IoError::last_os_error().into());
+        }
+    }
+
+    Ok(OpenptyResult {
+        master,
+        slave: slave.into(),
+    })
+}
+
+#[cfg(target_os = "linux")]
+pub fn ptsname(master: RawFd) -> Result<PathBuf, IoError> {
+    let mut buf = [0; 64];
+    match unsafe { libc::ptsname_r(master, buf.as_mut_ptr(), buf.len()) } {
+        0 => Ok(path(unsafe { CStr::from_ptr(buf.as_ptr()) })),
+        e => Err(IoError::from_raw_os_error(e)),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn ptsname(master: RawFd) -> Result<PathBuf, IoError> {
+    // Not reentrant, but only the main thread opens ptys.
+    let name = unsafe { libc::ptsname(master) };
+    if name.is_null() {
+        return Err(IoError::last_os_error());
+    }
+    Ok(path(unsafe { CStr::from_ptr(name) }))
+}
+
+fn path(name: &CStr) -> PathBuf {
+    PathBuf::from(OsStr::from_bytes(name.to_bytes()))
+}
+
+fn result<T>(res: &nix::Result<T>) -> String {
+    match res {
+        Ok(_) => "ok".to_string(),
+        Err(e) => format!("failed: {e}"),
+    }
+}
+
+// Every step of posix_openpt on its own, carrying on past failures to show as much as
+// possible of where devpts permissions go wrong.
+pub fn diagnose() -> Result<(), Box<dyn std::error::Error>> {
+    println!(
+        "PTY running as uid {} gid {}",
+        nix::unistd::getuid(),
+        nix::unistd::getgid()
+    );
+
+    let master = nix::pty::posix_openpt(OFlag::O_RDWR | OFlag::O_NOCTTY)
+        .map_err(|e| format!("posix_openpt failed: {e} (check /dev/ptmx and devpts)"))?;
+    println!(
+        "PTY posix_openpt master fd {} {}",
+        master.as_raw_fd(),
+        fd_flags(master.as_raw_fd())
+    );
+
+    let path = ptsname(master.as_raw_fd()).map_err(|e| format!("ptsname failed: {e}"))?;
+    println!("PTY ptsname {}", path.display());
+
+    println!("PTY before grantpt {}", ownership(&path));
+    let res = nix::pty::grantpt(&master);
+    println!("PTY grantpt {}", result(&res));
+    println!("PTY after grantpt {}", ownership(&path));
+
+    let res = nix::pty::unlockpt(&master);
+    println!("PTY unlockpt {}", result(&res));
+
+    match OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(&path)
+    {
+        Ok(slave) => println!(
+            "PTY slave fd {} {}",
+            slave.as_raw_fd(),
+            fd_flags(slave.as_raw_fd())
+        ),
+        Err(e) => {
+            let hint = match e.raw_os_error() {
+                Some(libc::EIO) => " (still locked: unlockpt did not take)",
+                Some(libc::EACCES) => " (the owner or mode above keeps us out)",
+                _ => "",
+            };
+            println!("PTY opening the slave failed: {e}{hint}");
+        }
+    }
+
+    Ok(())
+}
+
+fn ownership(path: &Path) -> String {
+    match std::fs::metadata(path) {
+        Ok(meta) => {
+            let group = Group::from_gid(Gid::from_raw(meta.gid()))
+                .ok()
+                .flatten()
+                .map(|group| group.name)
+                .unwrap_or_default();
+            format!(
+                "uid {} gid {} ({group}) mode {:o}",
+                meta.uid(),
+                meta.gid(),
+                meta.mode() & 0o7777
+            )
+        }
+        Err(e) => format!("unavailable: {e}"),
+    }
+}
+
+fn fd_flags(fd: RawFd) -> String {
+    let cloexec = match fcntl(fd, FcntlArg::F_GETFD) {
+        Ok(flags) if flags & libc::FD_CLOEXEC != 0 => "FD_CLOEXEC on",
+        Ok(_) => "FD_CLOEXEC off",
+        Err(_) => "FD_CLOEXEC unknown",
+    };
+    match fcntl(fd, FcntlArg::F_GETFL) {
+        Ok(flags) => format!("{} {cloexec}", holders::describe_flags(flags)),
+        Err(e) => format!("flags unavailable ({e}) {cloexec}"),
+    }
+}
@@ -0,0 +1,91 @@
+use std::os::unix::fs::PermissionsExt as _;
+use std::path::Path;
+
+// What an attached client may do, each level with everything below it.
+#[derive(Clone, Copy, PartialEq, PartialOrd, Debug)]
+pub enum Level {
+    // Sees the output.
+    Observe,
+    // Types into the program.
+    Write,
+    // Resizes the pty.
+    Control,
+    // Takes over a session that is not shared.
+    Admin,
+}
+
+impl Level {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "observe" => Some(Self::Observe),
+            "write" => Some(Self::Write),
+            "control" => Some(Self::Control),
+            "admin" => Some(Self::Admin),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Observe => "observe",
+            Self::Write => "write",
+            Self::Control => "control",
+            Self::Admin => "admin",
+        }
+    }
+}
+
+pub struct Tokens {
+    tokens: Vec<(Vec<u8>, Level)>,
+}
+
+impl Tokens {
+    // One `LEVEL TOKEN` per line, with # comments.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        if let Ok(meta) = std::fs::metadata(path) {
+            if meta.permissions().mode() & 0o077 != 0 {
+                tracing::warn!("{} can be read by other users", path.display());
+            }
+        }
+
+        let mut tokens = Vec::new();
+        for (num, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let entry = line
+                .split_once(char::is_whitespace)
+                .and_then(|(level, token)| Some((Level::parse(level)?, token.trim())));
+            match entry {
+                Some((level, token)) if !token.is_empty() => {
+                    tokens.push((token.as_bytes().to_vec(), level))
+                }
+                _ => {
+                    return Err(format!(
+                        "{}:{}: expected LEVEL TOKEN",
+                        path.display(),
+                        num + 1
+                    ))
+                }
+            }
+        }
+        if tokens.is_empty() {
+            return Err(format!("{}: no tokens", path.display()));
+        }
+        Ok(Self { tokens })
+    }
+
+    pub fn check(&self, token: &[u8]) -> Option<Level> {
+        self.tokens
+            .iter()
+            .find(|(known, _)| same(known, token))
+            .map(|&(_, level)| level)
+    }
+}
+
+// Takes as long whatever the first difference, so the time does not give the token away.
+fn same(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
@@ -0,0 +1,137 @@
+use crate::flags;
+use crate::input;
+
+use termios::{tcflag_t, Termios};
+
+use std::time::{Duration, Instant};
+
+struct Rule {
+    name: String,
+    field: usize,
+    flag: tcflag_t,
+    set: bool,
+    grace: Duration,
+    since: Option<Instant>,
+    reported: bool,
+}
+
+impl Rule {
+    // FLAG or -FLAG, optionally followed by :DURATION for how long it may be otherwise.
+    fn parse(spec: &str) -> Result<Self, String> {
+        let (flag, grace) = match spec.split_once(':') {
+            Some((flag, grace)) => (
+                flag,
+                input::parse_duration(grace)
+                    .ok_or_else(|| format!("invalid duration in baseline {spec:?}"))?,
+            ),
+            None => (spec, Duration::ZERO),
+        };
+        let (set, name) = match flag.strip_prefix('-') {
+            Some(name) => (false, name),
+            None => (true, flag.strip_prefix('+').unwrap_or(flag)),
+        };
+        let name = name.to_ascii_uppercase();
+
+        let tables = [flags::IFLAGS, flags::OFLAGS, flags::CFLAGS, flags::LFLAGS];
+        let (field, flag) = tables
+            .iter()
+            .enumerate()
+            .find_map(|(field, table)| {
+                let &(flag, _) = table.iter().find(|(_, flag)| *flag == name)?;
+                Some((field, flag))
+            })
+            .ok_or_else(|| format!("unknown termios flag in baseline {spec:?}"))?;
+
+        Ok(Self {
+            name,
+            field,
+            flag,
+            set,
+            grace,
+            since: None,
+            reported: false,
+        })
+    }
+
+    fn state(set: bool) -> &'static str {
+        if set {
+            "on"
+        } else {
+            "off"
+        }
+    }
+}
+
+pub struct Baseline {
+    rules: Vec<Rule>,
+    violations: usize,
+}
+
+impl Baseline {
+    pub fn parse(specs: &[String]) -> Result<Self, String> {
+        Ok(Self {
+            rules: specs
+                .iter()
+                .map(|spec| Rule::parse(spec))
+                .collect::<Result<_, _>>()?,
+            violations: 0,
+        })
+    }
+
+    // Returns the lines to log, so that the caller can also put them in the capture.
+    pub fn check(&mut self, term: &Termios) -> Vec<String> {
+        let fields = [term.c_iflag, term.c_oflag, term.c_cflag, term.c_lflag];
+        let mut notes = Vec::new();
+        for rule in &mut self.rules {
+            let set = fields[rule.field] & rule.flag != 0;
+            if set == rule.set {
+                if let Some(since) = rule.since.take() {
+                    if rule.reported {
+                        notes.push(format!(
+                            "BASELINE {} {} again after {:.1?}",
+                            rule.name,
+                            Rule::state(set),
+                            since.elapsed()
+                        ));
+                    }
+                }
+                rule.reported = false;
+                continue;
+            }
+
+            let since = *rule.since.get_or_insert_with(Instant::now);
+            if !rule.reported && since.elapsed() >= rule.grace {
+                notes.push(format!(
+                    "BASELINE {} {} for {:.1?}, expected {}",
+                    rule.name,
+                    Rule::state(set),
+                    since.elapsed(),
+                    Rule::state(rule.set)
+                ));
+                rule.reported = true;
+                self.violations += 1;
+            }
+        }
+        notes
+    }
+
+    // A flag left wrong when the session ends is what a program that does not restore the
+    // terminal looks like, so it counts even within its grace period.
+    pub fn finish(&mut self) -> bool {
+        for rule in &mut self.rules {
+            if rule.since.is_some() {
+                println!(
+                    "BASELINE {} still {} at exit, expected {}",
+                    rule.name,
+                    Rule::state(!rule.set),
+                    Rule::state(rule.set)
+                );
+                if !rule.reported {
+                    self.violations += 1;
+                }
+            }
+        }
+        println!("BASELINE {} violations", self.violations);
+        self.violations > 0
+    }
+}
@@ -0,0 +1,43 @@
+use crate::cli::BenchArgs;
+use crate::stats::Stats;
+use crate::{build_cmd, rawio, setup_pty, Args};
+
+use nix::errno::Errno;
+
+use std::os::fd::AsRawFd as _;
+use std::time::Instant;
+
+pub fn run(bench: &BenchArgs, args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    let (pty, _) = setup_pty(args)?;
+    let master = pty.master.as_raw_fd();
+
+    let env = [("SHELL".to_string(), args.shell.clone())];
+    let mut cmd = build_cmd(&args.shell, pty.slave.as_raw_fd(), args.alloc, env)?;
+    cmd.arg("-c")
+        .arg(format!("head -c {} /dev/zero", bench.bytes));
+    let mut child = cmd.spawn()?;
+    drop(pty.slave);
+
+    let mut stats = Stats::new();
+    let mut buf = vec![0; 1 << 16];
+    let start = Instant::now();
+    loop {
+        match rawio::read(master, &mut buf) {
+            Ok(0) | Err(Errno::EIO) => break,
+            Ok(num_bytes) => stats.record_read(num_bytes),
+            Err(e) => return Err(e.into()),
+        }
+    }
+    let elapsed = start.elapsed();
+    let status = child.wait()?;
+
+    let (read, _) = stats.totals();
+    println!(
+        "BENCH {read} bytes in {elapsed:?}, {:.1} MiB/s ({status})",
+        read as f64 / elapsed.as_secs_f64() / (1 << 20) as f64
+    );
+    println!();
+    stats.report();
+
+    Ok(())
+}
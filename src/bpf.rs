@@ -0,0 +1,82 @@
+use crate::input;
+use crate::procfs;
+
+use std::io::{BufRead as _, BufReader, ErrorKind as IoErrorKind};
+use std::process::{Command, Stdio};
+
+// pty_write() sees every write on either end: a write on the master (subtype 1) is input
+// to the program on the slave, one on the slave (subtype 2) is its output.
+const SCRIPT: &str = r#"
+kprobe:pty_write
+/((struct tty_struct *)arg0)->index == INDEX/
+{
+    printf("%d %d %d %r\n", ((struct tty_struct *)arg0)->driver->subtype, arg2, pid,
+        buf(arg1, arg2));
+}
+"#;
+
+const PTY_TYPE_MASTER: u32 = 1;
+
+// bpftrace compiles and loads the probe, so this needs root and a kernel with BTF, but no
+// ptrace: the traced processes do not notice. Payloads longer than BPFTRACE_MAX_STRLEN
+// are cut, their full length is still reported.
+pub fn run(tty: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let index: u32 = tty
+        .trim_start_matches("/dev/pts/")
+        .parse()
+        .map_err(|_| format!("expected /dev/pts/N or N, got {tty}"))?;
+
+    let mut child = Command::new("bpftrace")
+        .arg("-e")
+        .arg(SCRIPT.replace("INDEX", &index.to_string()))
+        .env("BPFTRACE_MAX_STRLEN", "200")
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            IoErrorKind::NotFound => "bpftrace is not installed".to_string(),
+            _ => format!("could not start bpftrace: {e}"),
+        })?;
+    println!("BPF tracing /dev/pts/{index}");
+    println!();
+
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+    for line in stdout.lines() {
+        let line = line?;
+        let mut fields = line.splitn(4, ' ');
+        let (Some(subtype), Some(len), Some(pid), Some(data)) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            // bpftrace's own messages, like "Attaching 1 probe...".
+            println!("{line}");
+            continue;
+        };
+        let (Ok(subtype), Ok(len), Ok(pid)) = (
+            subtype.parse::<u32>(),
+            len.parse::<usize>(),
+            pid.parse::<u32>(),
+        ) else {
+            println!("{line}");
+            continue;
+        };
+
+        let comm = procfs::parse(pid).map(|stat| stat.comm).unwrap_or_default();
+        let direction = if subtype == PTY_TYPE_MASTER {
+            "input"
+        } else {
+            "output"
+        };
+        let buf = input::unescape(data);
+        let cut = if buf.len() < len { " (cut)" } else { "" };
+        crate::print_chunk(
+            &format!("BPF {direction} {len} bytes by {pid} ({comm}){cut}"),
+            &buf,
+        );
+        println!();
+    }
+
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(format!("bpftrace failed: {status}").into());
+    }
+    Ok(())
+}
@@ -0,0 +1,565 @@
+use crate::cast;
+use crate::flags;
+use crate::mmap;
+use crate::utf8;
+
+use nix::sys::signal::Signal;
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead as _, BufReader, BufWriter, Error as IoError, Seek as _, Write as _};
+use std::os::unix::process::ExitStatusExt as _;
+use std::path::Path;
+use std::process::ExitStatus;
+use std::time::{Duration, Instant};
+
+#[derive(Serialize, Deserialize)]
+pub struct Record {
+    pub t: f64,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Event {
+    Header {
+        version: String,
+        shell: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        slave: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reading: Option<Reading>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        meta: Option<Box<Metadata>>,
+    },
+    Output {
+        #[serde(with = "base64")]
+        data: Vec<u8>,
+    },
+    Input {
+        #[serde(with = "base64")]
+        data: Vec<u8>,
+        // Who typed it, when several terminals share a session.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        client: Option<String>,
+    },
+    Note {
+        text: String,
+    },
+    Marker {
+        label: String,
+    },
+    // Where the capture was paused, and how much traffic it left out.
+    Gap {
+        from: f64,
+        duration: f64,
+        bytes: usize,
+    },
+    // The last event, once the program is gone.
+    Exit {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        code: Option<i32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        signal: Option<i32>,
+        duration: f64,
+    },
+}
+
+// What it takes to make sense of a capture long after: what ran, in what environment, on
+// what terminal, and what the user said it was.
+#[derive(Serialize, Deserialize)]
+pub struct Metadata {
+    pub command: Vec<String>,
+    pub env: Vec<(String, String)>,
+    // The names of the variables left out of env.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub redacted: Vec<String>,
+    pub termios: Option<flags::Snapshot>,
+    pub winsize: Option<(u16, u16)>,
+    pub platform: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+}
+
+// How the output was read, which decides how it was split into events.
+#[derive(Serialize, Deserialize)]
+pub struct Reading {
+    pub buffer: usize,
+    pub strategy: String,
+}
+
+mod base64 {
+    use crate::input;
+
+    use serde::de::Error as _;
+    use serde::{Deserialize as _, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&input::encode_base64(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let data = String::deserialize(deserializer)?;
+        input::decode_base64(&data).map_err(D::Error::custom)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    Jsonl,
+    Mmap,
+    Cast,
+}
+
+impl Format {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "jsonl" => Some(Self::Jsonl),
+            "mmap" => Some(Self::Mmap),
+            "cast" => Some(Self::Cast),
+            _ => None,
+        }
+    }
+}
+
+enum Sink {
+    Lines(BufWriter<File>),
+    Mmap(mmap::Writer),
+    Cast(cast::Writer),
+}
+
+pub struct Recorder {
+    sink: Sink,
+    started: Instant,
+    failed: bool,
+    // When the pause started, and how many bytes and markers or notes went by since.
+    paused: Option<(f64, usize, usize)>,
+    // Set when the events come with times of their own rather than as they happen.
+    clock: Option<f64>,
+}
+
+impl Recorder {
+    pub fn create(
+        path: &Path,
+        format: Format,
+        shell: &str,
+        slave: Option<&Path>,
+        reading: Option<Reading>,
+        meta: Option<Metadata>,
+    ) -> Result<Self, IoError> {
+        let mut recorder = Self {
+            sink: match format {
+                Format::Jsonl => Sink::Lines(BufWriter::new(File::create(path)?)),
+                Format::Mmap => Sink::Mmap(mmap::Writer::create(path)?),
+                Format::Cast => Sink::Cast(cast::Writer::create(path)?),
+            },
+            started: Instant::now(),
+            failed: false,
+            paused: None,
+            clock: None,
+        };
+        recorder.write(Event::Header {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            shell: shell.to_string(),
+            slave: slave.map(|slave| slave.display().to_string()),
+            reading,
+            meta: meta.map(Box::new),
+        });
+        Ok(recorder)
+    }
+
+    // Only a cast keeps the size as more than a note.
+    pub fn resize(&mut self, rows: u16, cols: u16) {
+        let t = self.now();
+        if let Sink::Cast(writer) = &mut self.sink {
+            let result = writer.resize(t, rows, cols);
+            self.check(result);
+        }
+    }
+
+    pub fn set_time(&mut self, t: f64) {
+        self.clock = Some(t);
+    }
+
+    fn now(&self) -> f64 {
+        self.clock
+            .unwrap_or_else(|| self.started.elapsed().as_secs_f64())
+    }
+
+    // While paused the traffic is left out, and so are the markers and notes, which tell of
+    // it: the commands typed, the titles set. Resuming leaves a gap event, so that the
+    // timeline shows where something is missing.
+    pub fn set_paused(&mut self, paused: bool) {
+        match (paused, self.paused) {
+            (true, None) => {
+                self.note("CAPTURE paused");
+                self.paused = Some((self.now(), 0, 0));
+            }
+            (false, Some((from, bytes, dropped))) => {
+                self.paused = None;
+                let duration = self.now() - from;
+                self.write(Event::Gap {
+                    from,
+                    duration,
+                    bytes,
+                });
+                if dropped > 0 {
+                    self.note(format!("CAPTURE left out {dropped} markers and notes"));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.is_some()
+    }
+
+    pub fn output(&mut self, data: &[u8]) {
+        if let Some((_, bytes, _)) = &mut self.paused {
+            *bytes += data.len();
+            return;
+        }
+        let t = self.now();
+        if let Sink::Mmap(writer) = &mut self.sink {
+            let result = writer.output(t, data);
+            self.check(result);
+            return;
+        }
+        self.write(Event::Output {
+            data: data.to_vec(),
+        });
+    }
+
+    pub fn input(&mut self, data: &[u8]) {
+        if let Some((_, bytes, _)) = &mut self.paused {
+            *bytes += data.len();
+            return;
+        }
+        let t = self.now();
+        if let Sink::Mmap(writer) = &mut self.sink {
+            let result = writer.input(t, data);
+            self.check(result);
+            return;
+        }
+        self.write(Event::Input {
+            data: data.to_vec(),
+            client: None,
+        });
+    }
+
+    pub fn mark(&mut self, label: impl Into<String>) {
+        if let Some((_, _, dropped)) = &mut self.paused {
+            *dropped += 1;
+            return;
+        }
+        self.write(Event::Marker {
+            label: label.into(),
+        });
+    }
+
+    // Input from one of several clients goes in with who sent it, as an event of its own
+    // also in an mmap capture.
+    pub fn input_from(&mut self, client: &str, data: &[u8]) {
+        if let Some((_, bytes, _)) = &mut self.paused {
+            *bytes += data.len();
+            return;
+        }
+        self.write(Event::Input {
+            data: data.to_vec(),
+            client: Some(client.to_string()),
+        });
+    }
+
+    pub fn exit(&mut self, status: &ExitStatus) {
+        let duration = self.now();
+        self.write(Event::Exit {
+            code: status.code(),
+            signal: status.signal(),
+            duration,
+        });
+    }
+
+    pub fn note(&mut self, text: impl Into<String>) {
+        if let Some((_, _, dropped)) = &mut self.paused {
+            *dropped += 1;
+            return;
+        }
+        self.write(Event::Note { text: text.into() });
+    }
+
+    fn write(&mut self, event: Event) {
+        let record = Record {
+            t: self.now(),
+            event,
+        };
+        let result = match &mut self.sink {
+            Sink::Lines(file) => serde_json::to_writer(&mut *file, &record)
+                .map_err(IoError::from)
+                .and_then(|()| writeln!(file))
+                .and_then(|()| file.flush()),
+            Sink::Mmap(writer) => writer.event(record.t, &record.event),
+            Sink::Cast(writer) => writer.event(record.t, &record.event),
+        };
+        self.check(result);
+    }
+
+    // The JSON lines are flushed as they go; this is the final sync point of an mmap capture,
+    // and writes the header of a cast that never got an event.
+    pub fn finish(&mut self) {
+        let result = match &mut self.sink {
+            Sink::Lines(_) => Ok(()),
+            Sink::Mmap(writer) => writer.sync(),
+            Sink::Cast(writer) => writer.finish(),
+        };
+        self.check(result);
+    }
+
+    // Reported once; the session goes on without a complete capture.
+    fn check(&mut self, result: Result<(), IoError>) {
+        if let Err(e) = result {
+            if !std::mem::replace(&mut self.failed, true) {
+                tracing::warn!("could not write the capture: {e}");
+            }
+        }
+    }
+}
+
+type Records<'a> = Box<dyn Iterator<Item = Result<Record, Box<dyn std::error::Error>>> + 'a>;
+
+// Any of the formats, read as it is played.
+pub fn records(path: &Path) -> Result<Records<'_>, Box<dyn std::error::Error>> {
+    if mmap::is_mmap(path)? {
+        let (records, recovered) = mmap::read(path)?;
+        if recovered > 0 {
+            println!("CAPTURE recovered {recovered} records written after the last sync point");
+        }
+        return Ok(Box::new(records.into_iter().map(Ok)));
+    }
+
+    let mut file = BufReader::new(File::open(path)?);
+    let mut first = String::new();
+    file.read_line(&mut first)?;
+    file.rewind()?;
+    if cast::is_header(&first) {
+        return Ok(Box::new(file.lines().enumerate().filter_map(
+            move |(n, line)| {
+                let record = line
+                    .map_err(|e| e.to_string())
+                    .and_then(|line| cast::parse(&line));
+                record
+                    .map_err(|e| format!("{}:{}: {e}", path.display(), n + 1).into())
+                    .transpose()
+            },
+        )));
+    }
+    Ok(Box::new(file.lines().enumerate().map(|(n, line)| {
+        let record = serde_json::from_str(&line?)
+            .map_err(|e| format!("{}:{}: {e}", path.display(), n + 1))?;
+        Ok(record)
+    })))
+}
+
+pub fn markers(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let mut count = 0;
+    for record in records(path)? {
+        let record = record?;
+        if let Event::Marker { label } = record.event {
+            count += 1;
+            println!("MARK {count} at {:.3}s {label:?}", record.t);
+        }
+    }
+    if count == 0 {
+        println!("No markers in {}", path.display());
+    }
+    Ok(())
+}
+
+// With `from`, a marker's label or its number, everything before that marker is skipped.
+pub fn replay(
+    path: &Path,
+    speed: f64,
+    delay: bool,
+    from: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if speed.is_nan() || speed <= 0.0 {
+        return Err(format!("invalid speed {speed}").into());
+    }
+    let records = records(path)?;
+    let start = Instant::now();
+    let mut decoder = utf8::Decoder::new();
+    let mut skipping = from.is_some();
+    let mut offset = 0.0;
+    let mut count = 0;
+
+    for record in records {
+        let record = record?;
+
+        if let Event::Marker { label } = &record.event {
+            count += 1;
+            if skipping && from.is_some_and(|from| from == label || from == count.to_string()) {
+                skipping = false;
+                offset = record.t;
+            }
+        }
+        if skipping && !matches!(record.event, Event::Header { .. }) {
+            continue;
+        }
+
+        if delay {
+            let due = Duration::from_secs_f64((record.t - offset).max(0.0) / speed);
+            std::thread::sleep(due.saturating_sub(start.elapsed()));
+        }
+
+        print_event(record.event, &mut decoder, count);
+    }
+
+    match from {
+        Some(from) if skipping => Err(format!("no marker {from:?} in {}", path.display()).into()),
+        _ => Ok(()),
+    }
+}
+
+fn print_event(event: Event, decoder: &mut utf8::Decoder, markers: usize) {
+    match event {
+        Event::Header {
+            version,
+            shell,
+            slave,
+            reading,
+            meta,
+        } => {
+            match slave {
+                Some(slave) => println!("CAPTURE of {shell} on {slave} by debug-pty {version}"),
+                None => println!("CAPTURE of {shell} by debug-pty {version}"),
+            }
+            if let Some(reading) = reading {
+                println!(
+                    "CAPTURE read in {}-byte buffers, {}",
+                    reading.buffer, reading.strategy
+                );
+            }
+            if let Some(meta) = meta {
+                print_meta(&meta);
+            }
+            println!();
+        }
+        Event::Output { data } => {
+            crate::print_text("READ", &decoder.decode(&data), &data);
+            println!();
+        }
+        Event::Input {
+            data,
+            client: Some(client),
+        } => println!("> from {client} {data:02x?}"),
+        Event::Input { data, .. } => println!("> {data:02x?}"),
+        Event::Note { text } => println!("{text}"),
+        Event::Exit {
+            code,
+            signal,
+            duration,
+        } => print_exit(code, signal, duration),
+        Event::Marker { label } => println!("MARK {markers} {label:?}"),
+        Event::Gap {
+            from,
+            duration,
+            bytes,
+        } => println!("GAP of {duration:.3}s from {from:.3}s, {bytes} bytes left out"),
+    }
+}
+
+const FOLLOW_POLL: Duration = Duration::from_millis(200);
+
+// Shows the events of a capture another instance is still writing as they come, until
+// the program's exit.
+pub fn follow(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let mut decoder = utf8::Decoder::new();
+    let mut markers = 0;
+    let mut show = |record: Record| {
+        if matches!(record.event, Event::Marker { .. }) {
+            markers += 1;
+        }
+        let exit = matches!(record.event, Event::Exit { .. });
+        print_event(record.event, &mut decoder, markers);
+        exit
+    };
+
+    if mmap::is_mmap(path)? {
+        // The writer only ever appends, so each look reads it again and shows what is new.
+        let mut seen = 0;
+        loop {
+            let (records, _) = mmap::read(path)?;
+            let total = records.len();
+            for record in records.into_iter().skip(seen) {
+                if show(record) {
+                    return Ok(());
+                }
+            }
+            seen = total;
+            std::thread::sleep(FOLLOW_POLL);
+        }
+    }
+
+    let mut file = BufReader::new(File::open(path)?);
+    let mut line = String::new();
+    let mut is_cast = None;
+    loop {
+        // A line without its newline is still being written and waits for the rest.
+        if file.read_line(&mut line)? == 0 || !line.ends_with('\n') {
+            std::thread::sleep(FOLLOW_POLL);
+            continue;
+        }
+        let record = if *is_cast.get_or_insert_with(|| cast::is_header(&line)) {
+            cast::parse(&line)?
+        } else {
+            Some(serde_json::from_str(&line)?)
+        };
+        line.clear();
+        if record.is_some_and(&mut show) {
+            return Ok(());
+        }
+    }
+}
+
+fn print_meta(meta: &Metadata) {
+    if let Some(title) = &meta.title {
+        println!("CAPTURE title {title:?}");
+    }
+    for (name, value) in &meta.labels {
+        println!("CAPTURE label {name}={value}");
+    }
+    println!("CAPTURE command {:?} on {}", meta.command, meta.platform);
+    if let Some(unit) = &meta.unit {
+        println!("CAPTURE unit {unit}");
+    }
+    if let Some((rows, cols)) = meta.winsize {
+        println!("CAPTURE winsize {rows}x{cols}");
+    }
+    if let Some(term) = &meta.termios {
+        let flags = [&term.iflag, &term.oflag, &term.cflag, &term.lflag];
+        let flags: Vec<&str> = flags.into_iter().flatten().map(String::as_str).collect();
+        println!("CAPTURE termios {}", flags.join(" "));
+    }
+    for (name, value) in &meta.env {
+        println!("CAPTURE env {name}={value}");
+    }
+}
+
+fn print_exit(code: Option<i32>, signal: Option<i32>, duration: f64) {
+    let outcome = match (code, signal) {
+        (Some(code), _) => format!("code {code}"),
+        (None, Some(signal)) => match Signal::try_from(signal) {
+            Ok(name) => format!("signal {signal} ({name})"),
+            Err(_) => format!("signal {signal}"),
+        },
+        (None, None) => "unknown status".to_string(),
+    };
+    println!("EXIT {outcome} after {duration:.3}s");
+}
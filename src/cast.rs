@@ -0,0 +1,257 @@
+use crate::capture::{Event, Record};
+use crate::utf8;
+
+use serde_json::json;
+
+use std::fs::File;
+use std::io::{BufWriter, Error as IoError, Write as _};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const DEFAULT_SERVER: &str = "https://asciinema.org";
+
+// asciicast v2: a header line, then one `[time, code, data]` array per event. Output and
+// input must be text, so split characters wait for the rest of their bytes; notes become
+// markers. The header waits for the first event, by which time the size is known.
+pub struct Writer {
+    file: BufWriter<File>,
+    header: Option<serde_json::Value>,
+    size: (u16, u16),
+    output: utf8::Decoder,
+    input: utf8::Decoder,
+}
+
+impl Writer {
+    pub fn create(path: &Path) -> Result<Self, IoError> {
+        Ok(Self {
+            file: BufWriter::new(File::create(path)?),
+            header: None,
+            size: (24, 80),
+            output: utf8::Decoder::new(),
+            input: utf8::Decoder::new(),
+        })
+    }
+
+    pub fn resize(&mut self, t: f64, rows: u16, cols: u16) -> Result<(), IoError> {
+        self.size = (rows, cols);
+        if self.header.is_some() {
+            return Ok(());
+        }
+        self.line(json!([t, "r", format!("{cols}x{rows}")]))
+    }
+
+    pub fn event(&mut self, t: f64, event: &Event) -> Result<(), IoError> {
+        match event {
+            Event::Header { shell, meta, .. } => {
+                let timestamp = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |since| since.as_secs());
+                let mut header = json!({
+                    "version": 2,
+                    "timestamp": timestamp,
+                    "command": shell,
+                });
+                if let Some(meta) = meta {
+                    if let Some(title) = &meta.title {
+                        header["title"] = title.as_str().into();
+                    }
+                    // v2 keeps SHELL and TERM in env, and the labels have nowhere else to go.
+                    let env: serde_json::Map<_, _> = meta
+                        .env
+                        .iter()
+                        .filter(|(name, _)| name == "SHELL" || name == "TERM")
+                        .map(|(name, value)| (name, value))
+                        .chain(&meta.labels)
+                        .map(|(name, value)| (name.clone(), value.as_str().into()))
+                        .collect();
+                    if !env.is_empty() {
+                        header["env"] = env.into();
+                    }
+                }
+                self.header = Some(header);
+                Ok(())
+            }
+            Event::Output { data } => {
+                let text = self.output.decode(data);
+                self.text(t, "o", text)
+            }
+            Event::Input { data, .. } => {
+                let text = self.input.decode(data);
+                self.text(t, "i", text)
+            }
+            Event::Note { text } | Event::Marker { label: text } => {
+                self.line(json!([t, "m", text]))
+            }
+            Event::Gap {
+                duration, bytes, ..
+            } => self.line(json!([
+                t,
+                "m",
+                format!("GAP of {duration:.3}s, {bytes} bytes left out")
+            ])),
+            // v2 has no event for it, so it becomes a marker like the notes.
+            Event::Exit { code, signal, .. } => {
+                let text = match (code, signal) {
+                    (Some(code), _) => format!("EXIT code {code}"),
+                    (None, Some(signal)) => format!("EXIT signal {signal}"),
+                    (None, None) => "EXIT".to_string(),
+                };
+                self.line(json!([t, "m", text]))
+            }
+        }
+    }
+
+    fn text(&mut self, t: f64, code: &str, text: String) -> Result<(), IoError> {
+        if text.is_empty() {
+            return Ok(());
+        }
+        self.line(json!([t, code, text]))
+    }
+
+    fn line(&mut self, value: serde_json::Value) -> Result<(), IoError> {
+        self.write_header()?;
+        serde_json::to_writer(&mut self.file, &value)?;
+        writeln!(self.file)?;
+        self.file.flush()
+    }
+
+    fn write_header(&mut self) -> Result<(), IoError> {
+        if let Some(mut header) = self.header.take() {
+            header["height"] = self.size.0.into();
+            header["width"] = self.size.1.into();
+            serde_json::to_writer(&mut self.file, &header)?;
+            writeln!(self.file)?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> Result<(), IoError> {
+        self.write_header()?;
+        self.file.flush()
+    }
+}
+
+// The token is the install id the asciinema client keeps, unless one is given.
+pub fn upload(path: &Path, server: &str, token: Option<&str>) -> Result<String, String> {
+    let token = match token {
+        Some(token) => token.to_string(),
+        None => {
+            let id = install_id().ok_or("no upload token, and no asciinema install id found")?;
+            std::fs::read_to_string(&id)
+                .map_err(|e| format!("{}: {e}", id.display()))?
+                .trim()
+                .to_string()
+        }
+    };
+    let user = std::env::var("USER").unwrap_or_else(|_| "debug-pty".to_string());
+    // The credentials go in a config on stdin, since the arguments are there for anyone to
+    // read in /proc.
+    let mut curl = Command::new("curl")
+        .args([
+            "--silent",
+            "--show-error",
+            "--fail-with-body",
+            "--config",
+            "-",
+        ])
+        .args(["--header", "Accept: application/json"])
+        .arg("--form")
+        .arg(format!("asciicast=@{}", path.display()))
+        .arg(format!("{}/api/asciicasts", server.trim_end_matches('/')))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("could not run curl: {e}"))?;
+    let config = format!("user = \"{}\"\n", curl_quote(&format!("{user}:{token}")));
+    if let Some(mut stdin) = curl.stdin.take() {
+        stdin
+            .write_all(config.as_bytes())
+            .map_err(|e| format!("could not pass the token to curl: {e}"))?;
+    }
+    let output = curl
+        .wait_with_output()
+        .map_err(|e| format!("could not run curl: {e}"))?;
+    let body = String::from_utf8_lossy(&output.stdout);
+    if !output.status.success() {
+        let error = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("upload failed: {} {}", error.trim(), body.trim()));
+    }
+    // Older servers answer with the link as plain text.
+    match serde_json::from_str::<serde_json::Value>(&body) {
+        Ok(reply) => reply["url"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| format!("no link in the reply {body:?}")),
+        Err(_) => Ok(body.trim().to_string()),
+    }
+}
+
+// Inside double quotes in a curl config, backslash escapes are interpreted.
+fn curl_quote(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn install_id() -> Option<PathBuf> {
+    let dir = match std::env::var_os("ASCIINEMA_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => match std::env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) => PathBuf::from(dir).join("asciinema"),
+            None => PathBuf::from(std::env::var_os("HOME")?).join(".config/asciinema"),
+        },
+    };
+    Some(dir.join("install-id")).filter(|path| path.exists())
+}
+
+pub fn is_header(line: &str) -> bool {
+    serde_json::from_str::<serde_json::Value>(line).is_ok_and(|header| header["version"] == 2)
+}
+
+// A line of a cast as the event it stands for; markers come back as markers, since the
+// notes cannot be told apart from them.
+pub fn parse(line: &str) -> Result<Option<Record>, String> {
+    if line.trim().is_empty() {
+        return Ok(None);
+    }
+    let value: serde_json::Value = serde_json::from_str(line).map_err(|e| e.to_string())?;
+    if value.is_object() {
+        let shell = value["command"].as_str().unwrap_or("a shell").to_string();
+        return Ok(Some(Record {
+            t: 0.0,
+            event: Event::Header {
+                version: "asciicast v2".to_string(),
+                shell,
+                slave: None,
+                reading: None,
+                meta: None,
+            },
+        }));
+    }
+
+    let (Some(t), Some(code), Some(data)) =
+        (value[0].as_f64(), value[1].as_str(), value[2].as_str())
+    else {
+        return Err("expected [TIME, CODE, DATA]".to_string());
+    };
+    let event = match code {
+        "o" => Event::Output {
+            data: data.as_bytes().to_vec(),
+        },
+        "i" => Event::Input {
+            data: data.as_bytes().to_vec(),
+            client: None,
+        },
+        "m" => Event::Marker {
+            label: data.to_string(),
+        },
+        "r" => match data.split_once('x') {
+            Some((cols, rows)) => Event::Note {
+                text: format!("WINSIZE {rows}x{cols}"),
+            },
+            None => return Err(format!("invalid size {data:?}")),
+        },
+        _ => return Ok(None),
+    };
+    Ok(Some(Record { t, event }))
+}
@@ -0,0 +1,118 @@
+use crate::capture::{self, Event, Record};
+use crate::cli::SplitArgs;
+use crate::utf8;
+
+use regex::Regex;
+
+use std::fs::File;
+use std::io::{BufWriter, Write as _};
+
+const MAX_LINE: usize = 1024;
+
+struct Chapter {
+    label: String,
+    start: f64,
+    records: Vec<Record>,
+}
+
+// Cuts a capture at its markers, or after each output that ends in a prompt, and lists
+// the chapters or writes each as a capture of its own with the times starting over.
+pub fn run(args: &SplitArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let prompt = match args.at.as_str() {
+        "prompt" => Some(Regex::new(&args.prompt).map_err(|e| format!("--prompt: {e}"))?),
+        _ => None,
+    };
+
+    let mut header = None;
+    let mut chapters = vec![Chapter {
+        label: "start".to_string(),
+        start: 0.0,
+        records: Vec::new(),
+    }];
+    let mut decoder = utf8::Decoder::new();
+    let mut line = String::new();
+    for record in capture::records(&args.file)? {
+        let record = record?;
+        let mut boundary = None;
+        match &record.event {
+            Event::Header { .. } => {
+                header = Some(serde_json::to_string(&record)?);
+                continue;
+            }
+            Event::Marker { label } if prompt.is_none() => {
+                chapters.push(Chapter {
+                    label: label.clone(),
+                    start: record.t,
+                    records: Vec::new(),
+                });
+            }
+            Event::Output { data } => {
+                if let Some(prompt) = &prompt {
+                    line.push_str(&decoder.decode(data));
+                    if let Some(end) = line.rfind('\n') {
+                        line.drain(..=end);
+                    }
+                    if line.len() > MAX_LINE {
+                        line.clear();
+                    }
+                    if prompt.is_match(&line) {
+                        boundary = Some(line.trim().to_string());
+                    }
+                }
+            }
+            _ => {}
+        }
+        let t = record.t;
+        chapters.last_mut().unwrap().records.push(record);
+        if let Some(label) = boundary {
+            chapters.push(Chapter {
+                label,
+                start: t,
+                records: Vec::new(),
+            });
+        }
+    }
+    chapters.retain(|chapter| !chapter.records.is_empty());
+
+    if let Some(dir) = &args.output_dir {
+        std::fs::create_dir_all(dir).map_err(|e| format!("{}: {e}", dir.display()))?;
+    }
+    for (i, chapter) in chapters.iter_mut().enumerate() {
+        let end = chapter
+            .records
+            .last()
+            .map_or(chapter.start, |record| record.t);
+        let bytes: usize = chapter
+            .records
+            .iter()
+            .map(|record| match &record.event {
+                Event::Output { data } => data.len(),
+                _ => 0,
+            })
+            .sum();
+        println!(
+            "CHAPTER {} {:?} from {:.3}s to {end:.3}s, {} events, {bytes} bytes of output",
+            i + 1,
+            chapter.label,
+            chapter.start,
+            chapter.records.len()
+        );
+
+        let Some(dir) = &args.output_dir else {
+            continue;
+        };
+        let path = dir.join(format!("chapter-{:03}.jsonl", i + 1));
+        let mut file = BufWriter::new(File::create(&path)?);
+        if let Some(header) = &header {
+            writeln!(file, "{header}")?;
+        }
+        for record in &mut chapter.records {
+            record.t -= chapter.start;
+            serde_json::to_writer(&mut file, &*record)?;
+            writeln!(file)?;
+        }
+        file.flush()?;
+        println!("  written to {}", path.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,418 @@
+use nix::sys::resource::{rlim_t, Resource};
+use nix::sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use nix::unistd::{Gid, Uid, User};
+
+use libc::c_int;
+
+use std::io::Error as IoError;
+use std::os::unix::fs::PermissionsExt as _;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI32, Ordering};
+
+pub struct Credentials {
+    uid: Option<Uid>,
+    gid: Gid,
+    groups: Vec<Gid>,
+}
+
+impl Credentials {
+    pub fn resolve(
+        user: Option<&str>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+    ) -> Result<Option<Self>, String> {
+        if user.is_none() && uid.is_none() && gid.is_none() {
+            return Ok(None);
+        }
+        let euid = Uid::effective();
+        if !euid.is_root() {
+            return Err(format!(
+                "--user, --uid and --gid need root (running as uid {euid})"
+            ));
+        }
+
+        let entry = match (user, uid) {
+            (Some(name), _) => Some(
+                User::from_name(name)
+                    .map_err(|e| format!("user {name}: {e}"))?
+                    .ok_or_else(|| format!("no such user {name}"))?,
+            ),
+            (None, Some(uid)) => User::from_uid(Uid::from_raw(uid)).ok().flatten(),
+            (None, None) => None,
+        };
+
+        let uid = uid
+            .map(Uid::from_raw)
+            .or(entry.as_ref().map(|user| user.uid));
+        let gid = gid
+            .map(Gid::from_raw)
+            .or(entry.as_ref().map(|user| user.gid))
+            .unwrap_or_else(Gid::current);
+        let groups = match &entry {
+            Some(user) => {
+                let name = std::ffi::CString::new(user.name.as_str()).unwrap();
+                nix::unistd::getgrouplist(&name, gid)
+                    .map_err(|e| format!("groups of {}: {e}", user.name))?
+            }
+            None => vec![gid],
+        };
+
+        Ok(Some(Self { uid, gid, groups }))
+    }
+
+    pub fn describe(&self) -> String {
+        let uid = match self.uid {
+            Some(uid) => uid.to_string(),
+            None => "unchanged".to_string(),
+        };
+        let groups: Vec<_> = self.groups.iter().map(Gid::to_string).collect();
+        format!("uid {uid}, gid {}, groups {}", self.gid, groups.join(","))
+    }
+
+    // Runs in the forked child: no allocation, and the uid goes last since it takes
+    // away the right to change the others.
+    pub fn apply(&self) -> Result<(), IoError> {
+        nix::unistd::setgroups(&self.groups)?;
+        nix::unistd::setgid(self.gid)?;
+        if let Some(uid) = self.uid {
+            nix::unistd::setuid(uid)?;
+        }
+        Ok(())
+    }
+}
+
+// Where exec will find the program, looked up now so that a typo fails before the pty is
+// set up.
+pub fn find_program(program: &str) -> Result<PathBuf, String> {
+    if program.contains('/') {
+        let path = PathBuf::from(program);
+        return match std::fs::metadata(&path) {
+            Ok(meta) if meta.is_dir() => Err(format!("{program}: is a directory")),
+            Ok(meta) if meta.permissions().mode() & 0o111 == 0 => {
+                Err(format!("{program}: not executable"))
+            }
+            Ok(_) => Ok(path),
+            Err(e) => Err(format!("{program}: {e}")),
+        };
+    }
+    let dirs = std::env::var_os("PATH").unwrap_or_default();
+    std::env::split_paths(&dirs)
+        .map(|dir| dir.join(program))
+        .find(|path| {
+            std::fs::metadata(path)
+                .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+        })
+        .ok_or_else(|| format!("{program}: not found in PATH"))
+}
+
+// What a program on a terminal tends to look for, reported but not required: reproducing
+// its absence is what --chroot is for.
+const ROOT_EXPECTED: &[&str] = &[
+    "/dev/tty",
+    "/dev/null",
+    "/dev/pts",
+    "/etc/passwd",
+    "/usr/share/terminfo",
+];
+
+// Catches before the fork what would otherwise only surface as the exec failing.
+pub fn check_root(root: &Path, program: &str) -> Result<Vec<&'static str>, String> {
+    let euid = Uid::effective();
+    if !euid.is_root() {
+        return Err(format!("--chroot needs root (running as uid {euid})"));
+    }
+    if !root.is_dir() {
+        return Err(format!("--chroot {}: not a directory", root.display()));
+    }
+    if let Some(relative) = program.strip_prefix('/') {
+        if !root.join(relative).exists() {
+            return Err(format!("--chroot {}: no {program} in it", root.display()));
+        }
+    }
+    Ok(ROOT_EXPECTED
+        .iter()
+        .copied()
+        .filter(|path| !root.join(&path[1..]).exists())
+        .collect())
+}
+
+pub fn chroot(root: &Path, cwd: &Path) -> Result<(), IoError> {
+    nix::unistd::chroot(root)?;
+    nix::unistd::chdir(cwd)?;
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+const NAMESPACES: &[(c_int, &str)] = &[
+    (libc::CLONE_NEWNS, "mount"),
+    (libc::CLONE_NEWPID, "pid"),
+    (libc::CLONE_NEWNET, "net"),
+    (libc::CLONE_NEWUTS, "uts"),
+    (libc::CLONE_NEWIPC, "ipc"),
+];
+#[cfg(not(target_os = "linux"))]
+const NAMESPACES: &[(c_int, &str)] = &[];
+
+pub fn parse_namespaces(names: &[String]) -> Result<c_int, String> {
+    if NAMESPACES.is_empty() && !names.is_empty() {
+        return Err("--unshare only works on Linux".to_string());
+    }
+    names.iter().try_fold(0, |flags, name| {
+        match NAMESPACES.iter().find(|(_, known)| known == name) {
+            Some((flag, _)) => Ok(flags | flag),
+            None => Err(format!(
+                "unknown namespace `{name}` (one of mount, pid, net, uts, ipc)"
+            )),
+        }
+    })
+}
+
+const CAP_SYS_ADMIN: u32 = 21;
+
+// Like check_root, before the fork rather than as an EPERM from inside it.
+pub fn check_namespaces() -> Result<(), String> {
+    let admin = std::fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| {
+            let caps = status
+                .lines()
+                .find_map(|line| line.strip_prefix("CapEff:"))?;
+            u64::from_str_radix(caps.trim(), 16).ok()
+        })
+        .is_some_and(|caps| caps & (1 << CAP_SYS_ADMIN) != 0);
+    if !admin {
+        let euid = Uid::effective();
+        return Err(format!(
+            "--unshare needs root or CAP_SYS_ADMIN (running as uid {euid})"
+        ));
+    }
+    Ok(())
+}
+
+// Runs before --chroot, while / is still a mount point to make private. A new pid namespace
+// only applies to the children of the caller, so like `unshare --fork` this forks, and the
+// program goes on to be pid 1 while the process left outside waits for it.
+#[cfg(target_os = "linux")]
+pub fn unshare(flags: c_int) -> Result<(), IoError> {
+    if unsafe { libc::unshare(flags) } == -1 {
+        return Err(IoError::last_os_error());
+    }
+    if flags & libc::CLONE_NEWNS != 0 {
+        // Keep mounts made inside from propagating back to the host.
+        let res = unsafe {
+            libc::mount(
+                c"none".as_ptr(),
+                c"/".as_ptr(),
+                std::ptr::null(),
+                libc::MS_REC | libc::MS_PRIVATE,
+                std::ptr::null(),
+            )
+        };
+        if res == -1 {
+            return Err(IoError::last_os_error());
+        }
+    }
+    if flags & libc::CLONE_NEWPID != 0 {
+        match unsafe { libc::fork() } {
+            -1 => return Err(IoError::last_os_error()),
+            0 => {}
+            pid => wait_pid_one(pid),
+        }
+    }
+    Ok(())
+}
+
+// After --chroot, so that ps inside sees the new pid namespace in its own /proc.
+#[cfg(target_os = "linux")]
+pub fn mount_proc(flags: c_int) -> Result<(), IoError> {
+    let both = libc::CLONE_NEWNS | libc::CLONE_NEWPID;
+    if flags & both != both {
+        return Ok(());
+    }
+    let res = unsafe {
+        libc::mount(
+            c"proc".as_ptr(),
+            c"/proc".as_ptr(),
+            c"proc".as_ptr(),
+            libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+            std::ptr::null(),
+        )
+    };
+    if res == -1 {
+        return Err(IoError::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn unshare(flags: c_int) -> Result<(), IoError> {
+    Err(IoError::from(std::io::ErrorKind::Unsupported))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn mount_proc(flags: c_int) -> Result<(), IoError> {
+    Err(IoError::from(std::io::ErrorKind::Unsupported))
+}
+
+static PID_ONE: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn forward(signal: c_int) {
+    unsafe { libc::kill(PID_ONE.load(Ordering::Relaxed), signal) };
+}
+
+// The process the session knows as the child: it passes on what is sent to it alone and
+// ends the way pid 1 did. Only async-signal-safe calls, as it is between fork and exec.
+#[cfg(target_os = "linux")]
+fn wait_pid_one(pid: libc::pid_t) -> ! {
+    PID_ONE.store(pid, Ordering::Relaxed);
+    unsafe {
+        // Among these is the pipe std reads until the exec, which would otherwise wait for
+        // this process to end too.
+        if libc::syscall(libc::SYS_close_range, 3, libc::c_uint::MAX, 0) == -1 {
+            for fd in 3..libc::sysconf(libc::_SC_OPEN_MAX).clamp(3, 65536) as c_int {
+                libc::close(fd);
+            }
+        }
+        // Keys typed reach pid 1 directly, being in the same process group.
+        let ignore = SigAction::new(SigHandler::SigIgn, SaFlags::empty(), SigSet::empty());
+        let pass = SigAction::new(
+            SigHandler::Handler(forward),
+            SaFlags::empty(),
+            SigSet::empty(),
+        );
+        for (sig, action) in [
+            (Signal::SIGINT, &ignore),
+            (Signal::SIGQUIT, &ignore),
+            (Signal::SIGTERM, &pass),
+            (Signal::SIGHUP, &pass),
+        ] {
+            let _ = signal::sigaction(sig, action);
+        }
+
+        let mut status = 0;
+        while libc::waitpid(pid, &mut status, 0) == -1 {
+            if IoError::last_os_error().raw_os_error() != Some(libc::EINTR) {
+                libc::_exit(1);
+            }
+        }
+        if libc::WIFSIGNALED(status) {
+            let number = libc::WTERMSIG(status);
+            let default = SigAction::new(SigHandler::SigDfl, SaFlags::empty(), SigSet::empty());
+            if let Ok(sig) = Signal::try_from(number) {
+                let _ = signal::sigaction(sig, &default);
+            }
+            libc::kill(libc::getpid(), number);
+            libc::_exit(128 + number);
+        }
+        libc::_exit(libc::WEXITSTATUS(status))
+    }
+}
+
+const RESOURCES: &[(Resource, &str)] = &[
+    (Resource::RLIMIT_NOFILE, "nofile"),
+    (Resource::RLIMIT_NPROC, "nproc"),
+    (Resource::RLIMIT_CPU, "cpu"),
+    (Resource::RLIMIT_AS, "as"),
+    (Resource::RLIMIT_DATA, "data"),
+    (Resource::RLIMIT_STACK, "stack"),
+    (Resource::RLIMIT_FSIZE, "fsize"),
+    (Resource::RLIMIT_CORE, "core"),
+    (Resource::RLIMIT_MEMLOCK, "memlock"),
+];
+
+// What programs print when they run into one of the limits above.
+const LIMIT_ERRORS: &[&str] = &[
+    "Too many open files",
+    "Resource temporarily unavailable",
+    "Cannot allocate memory",
+    "File too large",
+];
+
+pub struct Rlimit {
+    resource: Resource,
+    name: &'static str,
+    soft: rlim_t,
+    hard: rlim_t,
+}
+
+impl Rlimit {
+    // NAME=LIMIT sets both limits like `ulimit`, NAME=SOFT:HARD sets them separately.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (name, value) = spec
+            .split_once('=')
+            .ok_or_else(|| format!("expected NAME=LIMIT in `{spec}`"))?;
+        let &(resource, name) = RESOURCES
+            .iter()
+            .find(|(_, known)| *known == name)
+            .ok_or_else(|| format!("unknown resource `{name}`"))?;
+
+        let limit = |value: &str| match value {
+            "unlimited" => Ok(libc::RLIM_INFINITY),
+            _ => value
+                .parse()
+                .map_err(|_| format!("invalid limit `{value}` in `{spec}`")),
+        };
+        let (soft, hard) = match value.split_once(':') {
+            Some((soft, hard)) => (limit(soft)?, limit(hard)?),
+            None => (limit(value)?, limit(value)?),
+        };
+
+        Ok(Self {
+            resource,
+            name,
+            soft,
+            hard,
+        })
+    }
+
+    pub fn describe(&self) -> String {
+        let show = |limit: rlim_t| match limit {
+            libc::RLIM_INFINITY => "unlimited".to_string(),
+            limit => limit.to_string(),
+        };
+        format!("{}={}:{}", self.name, show(self.soft), show(self.hard))
+    }
+
+    pub fn apply(&self) -> Result<(), IoError> {
+        nix::sys::resource::setrlimit(self.resource, self.soft, self.hard)?;
+        Ok(())
+    }
+
+    pub fn current() -> Vec<Self> {
+        RESOURCES
+            .iter()
+            .filter_map(|&(resource, name)| {
+                let (soft, hard) = nix::sys::resource::getrlimit(resource).ok()?;
+                Some(Self {
+                    resource,
+                    name,
+                    soft,
+                    hard,
+                })
+            })
+            .collect()
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn soft(&self) -> rlim_t {
+        self.soft
+    }
+}
+
+pub fn limit_errors(output: &str) -> impl Iterator<Item = &'static str> + '_ {
+    LIMIT_ERRORS
+        .iter()
+        .copied()
+        .filter(move |message| output.contains(message))
+}
+
+pub fn limit_signal(signal: i32) -> Option<&'static str> {
+    match Signal::try_from(signal) {
+        Ok(Signal::SIGXCPU) => Some("SIGXCPU, the cpu limit"),
+        Ok(Signal::SIGXFSZ) => Some("SIGXFSZ, the fsize limit"),
+        _ => None,
+    }
+}
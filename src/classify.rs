@@ -0,0 +1,97 @@
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::time::{Duration, Instant};
+
+// Input that is not echoed within this long is taken to have been swallowed (ECHO off,
+// a password prompt) and stops being matched against.
+const WINDOW: Duration = Duration::from_secs(1);
+
+const COLOR: &str = "\x1b[36m";
+const RESET: &str = "\x1b[0m";
+
+// Tells the echo of what we sent apart from what the program printed, by matching the
+// output against the bytes the line discipline or readline would echo for the input.
+pub struct Classifier {
+    expected: VecDeque<(u8, Instant)>,
+    color: bool,
+}
+
+impl Classifier {
+    pub fn new() -> Self {
+        Self {
+            expected: VecDeque::new(),
+            color: nix::unistd::isatty(1).unwrap_or(false),
+        }
+    }
+
+    pub fn sent(&mut self, bytes: &[u8]) {
+        let now = Instant::now();
+        for &byte in bytes {
+            let caret;
+            let echo: &[u8] = match byte {
+                // ICRNL and ONLCR: Enter comes back as CR LF whichever of the two was sent.
+                b'\r' | b'\n' => b"\r\n",
+                b'\t' => b"\t",
+                // ECHOCTL shows the other control characters in caret notation.
+                0x7f => b"^?",
+                0x00..=0x1f => {
+                    caret = [b'^', byte + 0x40];
+                    &caret
+                }
+                _ => std::slice::from_ref(&byte),
+            };
+            self.expected
+                .extend(echo.iter().map(|&expected| (expected, now)));
+        }
+    }
+
+    // One flag per byte of `buf`, true for echo.
+    pub fn classify(&mut self, buf: &[u8]) -> Vec<bool> {
+        let now = Instant::now();
+        while let Some(&(_, at)) = self.expected.front() {
+            if now.duration_since(at) < WINDOW {
+                break;
+            }
+            self.expected.pop_front();
+        }
+
+        buf.iter()
+            .map(|&byte| {
+                // Without ONLCR the CR never shows up, only the LF.
+                if byte == b'\n' && matches!(self.expected.front(), Some((b'\r', _))) {
+                    self.expected.pop_front();
+                }
+                match self.expected.front() {
+                    Some(&(expected, _)) if expected == byte => {
+                        self.expected.pop_front();
+                        true
+                    }
+                    _ => false,
+                }
+            })
+            .collect()
+    }
+
+    pub fn print(&mut self, buf: &[u8]) {
+        let echo = self.classify(buf);
+        if !echo.contains(&true) {
+            return;
+        }
+
+        let mut line = String::from("SPLIT");
+        let mut start = 0;
+        while start < buf.len() {
+            let is_echo = echo[start];
+            let len = echo[start..].iter().take_while(|&&e| e == is_echo).count();
+            let text = String::from_utf8_lossy(&buf[start..start + len]);
+            match (is_echo, self.color) {
+                (true, true) => write!(line, " {COLOR}echo {text:?}{RESET}"),
+                (true, false) => write!(line, " echo {text:?}"),
+                (false, _) => write!(line, " output {text:?}"),
+            }
+            .unwrap();
+            start += len;
+        }
+        println!("{line}");
+    }
+}
@@ -0,0 +1,753 @@
+use crate::capture::Format;
+use crate::cast;
+use crate::config::Settings;
+use crate::env;
+
+use clap::{Args, Parser, Subcommand};
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+#[derive(Parser)]
+#[command(
+    version,
+    about = "Run a program on a pty and log everything that crosses it"
+)]
+#[command(args_conflicts_with_subcommands = true)]
+#[command(after_help = "Type :help in a session for the list of colon-commands.")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Cmd>,
+
+    #[command(flatten)]
+    pub run: RunArgs,
+
+    /// Filter for the tool's own diagnostics on stderr, like `debug` or `debug_pty=trace`
+    /// [default: $RUST_LOG or warn]
+    #[arg(long, global = true, value_name = "FILTER")]
+    pub log_filter: Option<String>,
+}
+
+#[derive(Subcommand)]
+pub enum Cmd {
+    /// Run a session interactively (the default)
+    Run(RunArgs),
+    /// Run a session and capture it to a file
+    Record(RecordArgs),
+    /// Sit between this terminal and the program, passing everything through while
+    /// capturing it
+    #[command(visible_alias = "mitm")]
+    Wrap(RecordArgs),
+    /// Play back a capture with its original timing
+    Replay(ReplayArgs),
+    /// Cut a capture into chapters at its markers or at prompts, and list them or write
+    /// each to a file of its own
+    Split(SplitArgs),
+    /// Find a control sequence, regex or bytes in a capture and print where, with context;
+    /// exits non-zero if there is no match
+    Search(SearchArgs),
+    /// Open a pty, print its slave, window size and termios, and exit
+    Probe(RunArgs),
+    /// List every pty on the system with its size, foreground pgrp and holders
+    List,
+    /// Attach to a running process with ptrace and log its reads and writes on ttys
+    Snoop(SnoopArgs),
+    /// Trace the traffic of a pty with an eBPF probe (needs root and bpftrace)
+    Bpf(BpfArgs),
+    /// Measure how fast output flows from the child through the pty
+    Bench(BenchArgs),
+    /// Run a script of send/expect steps and exit non-zero if an expect fails
+    Test(TestArgs),
+    /// Run the same script against two shells in lockstep and show where their output
+    /// and termios changes differ
+    Compare(CompareArgs),
+    /// Start a program on a pty in the background, to be reached with `attach`
+    Daemon(DaemonArgs),
+    /// Connect this terminal to a session started with `daemon`
+    Attach(AttachArgs),
+    /// Follow a pane of a running tmux through a control mode client and log its output
+    /// like a session's reads
+    Tmux(TmuxArgs),
+    /// Run a log from `tmux pipe-pane` or screen's logfile through the decoder, the mode
+    /// tracking and the capture, as if it had been a session
+    Ingest(IngestArgs),
+    #[command(hide = true)]
+    InsideReport(InsideArgs),
+    #[command(hide = true)]
+    SelfTest,
+    /// Open a pty one step at a time (posix_openpt, grantpt, unlockpt, open) and log the
+    /// result, the slave's owner and mode, and the fd flags of each
+    PtySetup,
+    /// Check the system for pty problems: devpts, pty count, ulimits, TIOCSTI and terminfo
+    Doctor,
+    /// Print the termios, window size and cc values of a freshly opened pty as JSON
+    Defaults(DefaultsArgs),
+}
+
+#[derive(Args)]
+pub struct RunArgs {
+    /// Defaults for these options, [profiles], [aliases] and [macros]
+    /// [default: ~/.config/debug-pty/config.toml]
+    #[arg(short, long, value_name = "PATH")]
+    pub config: Option<String>,
+
+    /// Apply [profiles.NAME] from the config file
+    #[arg(short, long, value_name = "NAME")]
+    pub profile: Option<String>,
+
+    /// Program to run on the slave [default: $SHELL, else the one in the passwd entry, else
+    /// /bin/bash]
+    #[arg(short, long)]
+    pub shell: Option<String>,
+
+    /// How input lines are interpreted [default: str]
+    #[arg(short, long = "mod", visible_alias = "mode", value_name = "MODE")]
+    #[arg(value_parser = ["str", "bytes", "b64"])]
+    pub mode: Option<String>,
+
+    /// Send \e, \x1b, ^C, ^[, ... literally in str mode
+    #[arg(long)]
+    pub no_escapes: bool,
+
+    /// Appended to every str/bytes line [default: lf]
+    #[arg(short, long, value_parser = ["lf", "cr", "crlf", "none"])]
+    pub line_ending: Option<String>,
+
+    /// Write input to the master, or push it into the slave's input queue with TIOCSTI
+    /// [default: master]
+    #[arg(long, value_parser = ["master", "tiocsti"])]
+    pub inject: Option<String>,
+
+    /// How to allocate the pty, logging each step: openpty, posix_openpt (with grantpt,
+    /// unlockpt and ptsname) or forkpty (login_tty in the child) [default: openpty]
+    #[arg(long, value_parser = ["openpty", "posix_openpt", "forkpty"])]
+    pub alloc: Option<String>,
+
+    /// Make the master non-blocking like most terminal emulators, and count the reads
+    /// that return EAGAIN and how long each then waited for data
+    #[arg(long)]
+    pub nonblock: bool,
+
+    /// Read the master from N threads at once, each on its own dup of the fd, and log
+    /// which reader got which bytes
+    #[arg(long, value_name = "N")]
+    pub readers: Option<usize>,
+
+    /// Size of each read from the master [default: 1024, 4096 for wrap]
+    #[arg(long, value_name = "BYTES", value_parser = clap::value_parser!(u64).range(1..))]
+    pub read_buffer: Option<u64>,
+
+    /// Read once per wakeup, or drain whatever is ready into the same chunk until it would
+    /// block [default: once]
+    #[arg(long, value_parser = ["once", "drain"])]
+    pub read_strategy: Option<String>,
+
+    /// Also write the raw output to PATH, spliced there from the master without a copy
+    /// through userspace where the kernel allows it (Linux 6.5 and later; reads once per
+    /// wakeup)
+    #[arg(long, value_name = "PATH")]
+    pub raw_log: Option<String>,
+
+    /// Initial window size of the pty
+    #[arg(short, long, value_name = "ROWSxCOLS")]
+    pub winsize: Option<String>,
+
+    /// Set (ECHO), clear (-ECHO) flags or apply `raw` before spawning
+    #[arg(short, long, value_name = "SPEC", value_delimiter = ',')]
+    pub termios: Vec<String>,
+
+    /// Set a variable in the child's environment (repeatable)
+    #[arg(short, long, value_name = "KEY=VAL", value_parser = parse_env_var)]
+    pub env: Vec<String>,
+
+    /// Pass this process's whole environment to the child
+    #[arg(long, conflicts_with = "inherit_env_only")]
+    pub inherit_env: bool,
+
+    /// Pass only the variables matching PATTERN (`LC_*`, `TERM`; repeatable)
+    #[arg(long, value_name = "PATTERN")]
+    pub inherit_env_only: Vec<String>,
+
+    /// Read variables from PATH instead of ./.env (repeatable, later files win)
+    #[arg(long, value_name = "PATH")]
+    pub env_file: Vec<String>,
+
+    /// Do not read any env file
+    #[arg(long, conflicts_with = "env_file")]
+    pub no_env_file: bool,
+
+    /// Working directory of the child [default: the current one]
+    #[arg(long, value_name = "PATH")]
+    pub cwd: Option<String>,
+
+    /// Change the child's root to DIR before it execs, --cwd then being inside it [default:
+    /// /] (needs root)
+    #[arg(long, value_name = "DIR")]
+    pub chroot: Option<String>,
+
+    /// Start the shell as a login shell (argv[0] prefixed with a dash)
+    #[arg(long)]
+    pub login: bool,
+
+    /// Run the child as this user, with its groups (needs root)
+    #[arg(short, long, value_name = "NAME")]
+    pub user: Option<String>,
+
+    /// Run the child with this user id (needs root)
+    #[arg(long, conflicts_with = "user")]
+    pub uid: Option<u32>,
+
+    /// Run the child with this group id (needs root)
+    #[arg(long)]
+    pub gid: Option<u32>,
+
+    /// Start the child in new namespaces: mount, pid, net, uts, ipc (needs root or
+    /// CAP_SYS_ADMIN); with pid the program is pid 1, with a fresh /proc if mount is given too
+    #[arg(long, value_name = "NS", value_delimiter = ',')]
+    pub unshare: Vec<String>,
+
+    /// Limit a resource of the child: nofile, nproc, cpu, as, data, stack, fsize, core or
+    /// memlock, as NAME=LIMIT or NAME=SOFT:HARD (repeatable)
+    #[arg(long, value_name = "NAME=LIMIT")]
+    pub rlimit: Vec<String>,
+
+    /// Register the pty in utmp/wtmp while the child runs, like a terminal emulator
+    #[arg(long)]
+    pub utmp: bool,
+
+    /// Adopt orphaned descendants of the child and report how they exit
+    #[arg(long)]
+    pub subreaper: bool,
+
+    /// Log the tty ioctls, reads and writes of the child and its descendants (ptrace)
+    #[arg(long, conflicts_with = "subreaper")]
+    pub trace_child: bool,
+
+    /// Pass traffic through a filter, in the order given: redact=TEXT, rewrite=FROM=>TO,
+    /// ratelimit=BYTES, drop=PERCENT (repeatable)
+    #[arg(short, long = "filter", value_name = "SPEC")]
+    pub filters: Vec<String>,
+
+    /// Serve Prometheus metrics at http://ADDR/metrics, or https with --tls-cert
+    #[arg(long, value_name = "ADDR")]
+    pub metrics: Option<String>,
+
+    /// Serve --metrics over TLS with this certificate chain (PEM)
+    #[arg(long, value_name = "FILE", requires_all = ["metrics", "tls_key"])]
+    pub tls_cert: Option<String>,
+
+    /// The private key (PEM) of --tls-cert
+    #[arg(long, value_name = "FILE", requires = "tls_cert")]
+    pub tls_key: Option<String>,
+
+    /// Have the child first print what it sees of its fds 0-2 (tty, termios, size, pgrp,
+    /// sid), then exec the real program
+    #[arg(long)]
+    pub inside_report: bool,
+
+    /// Run a built-in test program as the child, with commands that toggle ECHO, go raw,
+    /// emit partial escape sequences and ignore SIGWINCH (type help in it)
+    #[arg(long, conflicts_with_all = ["command", "exec"])]
+    pub self_test: bool,
+
+    /// Only open the pty, print the slave path and wait, for other programs to attach to
+    #[arg(long, conflicts_with_all = ["command", "exec", "self_test"])]
+    pub print_pts: bool,
+
+    /// Run COMMAND with the shell's -c, print the transcript and exit with its status.
+    /// Given several times, the commands are typed into one shell in turn, each marked in
+    /// the capture with its status and duration, and the exit status is the last one's
+    #[arg(long, conflicts_with = "exec")]
+    pub command: Vec<String>,
+
+    /// Program and arguments to run directly instead of the shell
+    #[arg(last = true, value_name = "COMMAND")]
+    pub exec: Vec<String>,
+
+    /// Run the program on HOST instead, in a pty ssh requests there, with everything here
+    /// applied to what comes back; without --command or a COMMAND it is the login shell
+    /// there
+    #[arg(long, value_name = "[USER@]HOST", conflicts_with_all = ["self_test", "print_pts"])]
+    pub ssh: Option<String>,
+
+    /// Run the program in a running container with `docker exec -it`, nothing installed in
+    /// the image; without --command or a COMMAND it is sh there, and --env goes to it
+    #[arg(long, value_name = "CONTAINER", conflicts_with_all = ["ssh", "self_test", "print_pts"])]
+    pub docker: Option<String>,
+
+    /// Like --docker, with podman
+    #[arg(long, value_name = "CONTAINER", conflicts_with_all = ["docker", "ssh", "self_test", "print_pts"])]
+    pub podman: Option<String>,
+
+    /// Start the program through systemd-run in a transient scope, named UNIT.scope
+    /// [default: debug-pty-PID], for the journal and cgroup tools; the unit goes in the
+    /// capture
+    #[arg(long, value_name = "UNIT", num_args = 0..=1, default_missing_value = "")]
+    pub scope: Option<String>,
+
+    /// Resource property for the --scope, as systemd-run -p takes it (`MemoryMax=1G`;
+    /// repeatable)
+    #[arg(long, value_name = "PROP=VALUE", requires = "scope")]
+    pub scope_property: Vec<String>,
+
+    /// Also log the output as whole lines, showing a line without an ending once it is
+    /// older than TIMEOUT
+    #[arg(long, value_name = "TIMEOUT", num_args = 0..=1, default_missing_value = "1s")]
+    pub lines: Option<String>,
+
+    /// Split each read into the echo of recent input and the program's own output, with
+    /// the echo colored on a terminal
+    #[arg(long)]
+    pub classify_echo: bool,
+
+    /// Note where the line discipline translated bytes in either direction (ICRNL, INLCR,
+    /// IGNCR, ONLCR, OCRNL, ISIG...) according to the termios at the time
+    #[arg(long)]
+    pub ldisc_notes: bool,
+
+    /// Flag whenever the termios diverges from FLAG or -FLAG, after DURATION if given
+    /// (`ECHO:5s`: ECHO must never be off for more than 5s; repeatable)
+    #[arg(long, value_name = "FLAG[:DURATION]", value_delimiter = ',')]
+    pub baseline: Vec<String>,
+
+    /// Exit non-zero if the termios diverged from the --baseline
+    #[arg(long)]
+    pub baseline_strict: bool,
+
+    /// Cut the session into commands, each from the line entered at a prompt, as echoed, to
+    /// the next prompt, marked in the capture: a prompt is the last line of output matching
+    /// `regex:REGEX`, the private OSC that `sentinel` prints from PROMPT_COMMAND (bash), or
+    /// an OSC 133 A from the shell's own integration (`osc133`); how long each took, and its
+    /// exit status from the sentinel or OSC 133 D, is listed at the end. A line that the
+    /// editor redraws across several rows is read from its last row only
+    #[arg(long, value_name = "MODE")]
+    pub prompt_detect: Option<String>,
+
+    /// In wrap, when the program exits with the alternate screen, a hidden cursor, mouse
+    /// tracking, bracketed paste or attributes still set, send the sequences that undo them
+    #[arg(long)]
+    pub reset_on_exit: bool,
+
+    /// In wrap, pass the program's window title changes (OSC 0 and 2) on to this terminal
+    /// or drop them; they are logged either way [default: pass]
+    #[arg(long, value_parser = ["pass", "drop"])]
+    pub host_title: Option<String>,
+
+    /// In wrap, the key that, followed by `:`, opens a prompt for the session commands
+    /// (resize, signal, termios, record, ...); typed twice it is sent once. `none` turns it off
+    /// [default: ^]]
+    #[arg(long, value_name = "CHAR")]
+    pub escape_char: Option<String>,
+
+    /// Write byte by byte and measure how fast each is echoed
+    #[arg(long)]
+    pub echo_latency: bool,
+
+    /// Print throughput, child state and termios every second
+    #[arg(long)]
+    pub status: bool,
+}
+
+impl RunArgs {
+    pub fn settings(&self) -> Settings {
+        Settings {
+            shell: self.shell.clone(),
+            mode: self.mode.clone(),
+            escapes: self.no_escapes.then_some(false),
+            line_ending: self.line_ending.clone(),
+            inject: self.inject.clone(),
+            alloc: self.alloc.clone(),
+            nonblock: self.nonblock.then_some(true),
+            readers: self.readers,
+            read_buffer: self.read_buffer.map(|bytes| bytes as usize),
+            read_strategy: self.read_strategy.clone(),
+            raw_log: self.raw_log.clone(),
+            lines: self.lines.clone(),
+            classify_echo: self.classify_echo.then_some(true),
+            ldisc_notes: self.ldisc_notes.then_some(true),
+            baseline: (!self.baseline.is_empty()).then(|| self.baseline.clone()),
+            baseline_strict: self.baseline_strict.then_some(true),
+            prompt_detect: self.prompt_detect.clone(),
+            reset_on_exit: self.reset_on_exit.then_some(true),
+            host_title: self.host_title.clone(),
+            escape_char: self.escape_char.clone(),
+            echo_latency: self.echo_latency.then_some(true),
+            status: self.status.then_some(true),
+            winsize: self.winsize.clone(),
+            termios: (!self.termios.is_empty()).then(|| self.termios.clone()),
+            env: (!self.env.is_empty()).then(|| self.env.clone()),
+            inherit_env: self.inherit_env.then_some(true),
+            inherit_env_only: (!self.inherit_env_only.is_empty())
+                .then(|| self.inherit_env_only.clone()),
+            cwd: self.cwd.clone(),
+            chroot: self.chroot.clone(),
+            login: self.login.then_some(true),
+            exec: (!self.exec.is_empty()).then(|| self.exec.clone()),
+            ssh: self.ssh.clone(),
+            docker: self.docker.clone(),
+            podman: self.podman.clone(),
+            scope: self.scope.clone(),
+            scope_property: (!self.scope_property.is_empty()).then(|| self.scope_property.clone()),
+            user: self.user.clone(),
+            uid: self.uid,
+            gid: self.gid,
+            unshare: (!self.unshare.is_empty()).then(|| self.unshare.clone()),
+            rlimit: (!self.rlimit.is_empty()).then(|| self.rlimit.clone()),
+            utmp: self.utmp.then_some(true),
+            subreaper: self.subreaper.then_some(true),
+            trace_child: self.trace_child.then_some(true),
+            filters: (!self.filters.is_empty()).then(|| self.filters.clone()),
+            metrics: self.metrics.clone(),
+            tls_cert: self.tls_cert.clone(),
+            tls_key: self.tls_key.clone(),
+            inside_report: self.inside_report.then_some(true),
+            self_test: self.self_test.then_some(true),
+            print_pts: self.print_pts.then_some(true),
+            env_files: if self.no_env_file {
+                Some(Vec::new())
+            } else {
+                (!self.env_file.is_empty()).then(|| self.env_file.clone())
+            },
+        }
+    }
+}
+
+pub fn key_values(pairs: &[(String, String)]) -> BTreeMap<String, String> {
+    pairs.iter().cloned().collect()
+}
+
+fn parse_key_value(s: &str) -> Result<(String, String), String> {
+    env::parse_var(s).ok_or_else(|| "expected NAME=VALUE".to_string())
+}
+
+fn parse_env_var(s: &str) -> Result<String, String> {
+    match env::parse_var(s) {
+        Some(_) => Ok(s.to_string()),
+        None => Err("expected KEY=VAL".to_string()),
+    }
+}
+
+#[derive(Args)]
+pub struct RecordArgs {
+    /// Capture file to write
+    #[arg(short, long, value_name = "PATH")]
+    pub output: PathBuf,
+
+    /// JSON lines, a memory-mapped file of binary records synced at intervals for heavy
+    /// output, or an asciicast v2 file for asciinema, all of which replay reads
+    #[arg(long, default_value = "jsonl", value_parser = ["jsonl", "mmap", "cast"])]
+    pub format: String,
+
+    /// Upload the capture to an asciinema server when done and print the link; needs
+    /// --format cast and curl
+    #[arg(long)]
+    pub upload: bool,
+
+    /// asciinema server to upload to
+    #[arg(long, value_name = "URL", default_value = cast::DEFAULT_SERVER)]
+    pub upload_url: String,
+
+    /// Token for the server [default: the install id of the asciinema client]
+    #[arg(long, value_name = "TOKEN")]
+    pub upload_token: Option<String>,
+
+    /// Title kept in the capture's header
+    #[arg(long, value_name = "TEXT")]
+    pub title: Option<String>,
+
+    /// Label kept in the capture's header (repeatable)
+    #[arg(long = "label", value_name = "KEY=VAL", value_parser = parse_key_value)]
+    pub labels: Vec<(String, String)>,
+
+    /// Keep the child's whole environment in the capture's header; by default the variables
+    /// named like *TOKEN*, *SECRET*, *KEY* or *PASS* are left out
+    #[arg(long)]
+    pub capture_env: bool,
+
+    #[command(flatten)]
+    pub run: RunArgs,
+}
+
+impl RecordArgs {
+    pub fn format(&self) -> Format {
+        Format::parse(&self.format).unwrap_or(Format::Jsonl)
+    }
+
+    pub fn labels(&self) -> BTreeMap<String, String> {
+        key_values(&self.labels)
+    }
+}
+
+#[derive(Args)]
+pub struct ReplayArgs {
+    /// Capture file written by `record`
+    pub file: PathBuf,
+
+    /// Playback speed factor
+    #[arg(long, default_value_t = 1.0)]
+    pub speed: f64,
+
+    /// Print all events at once instead of waiting
+    #[arg(long)]
+    pub no_delay: bool,
+
+    /// Start at a marker, by label or number, skipping what came before it
+    #[arg(long, value_name = "MARKER")]
+    pub from: Option<String>,
+
+    /// List the markers with their times and exit
+    #[arg(long, conflicts_with = "from")]
+    pub markers: bool,
+
+    /// Show the events of a capture that is still being written as they arrive, until the
+    /// program exits
+    #[arg(long, conflicts_with_all = ["from", "markers", "rerun"])]
+    pub follow: bool,
+
+    /// Run the program again on a pty with the captured termios, window size and
+    /// environment, and type the captured input into it; run options override those
+    #[arg(long)]
+    pub rerun: bool,
+
+    #[command(flatten)]
+    pub run: RunArgs,
+}
+
+#[derive(Args)]
+pub struct SplitArgs {
+    /// Capture file written by `record`
+    pub file: PathBuf,
+
+    /// Where a chapter starts: at each marker, or after each output ending in a prompt
+    #[arg(long, default_value = "markers", value_parser = ["markers", "prompt"])]
+    pub at: String,
+
+    /// Regex for the last line of output that makes a prompt
+    #[arg(long, value_name = "REGEX", default_value = r"[$#%>] ?$")]
+    pub prompt: String,
+
+    /// Directory to write the chapters to, as chapter-001.jsonl and so on
+    #[arg(short, long, value_name = "DIR")]
+    pub output_dir: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct SearchArgs {
+    /// Capture file, in any of the formats
+    pub file: PathBuf,
+
+    /// Sequence with names for the control bytes, like `CSI ? 1049 h` or `OSC 0 ; BEL`
+    #[arg(long, value_name = "SEQ", conflicts_with_all = ["regex", "bytes"])]
+    pub seq: Option<String>,
+
+    /// Regex over the raw bytes
+    #[arg(long, value_name = "REGEX", conflicts_with = "bytes")]
+    pub regex: Option<String>,
+
+    /// Bytes as the `bytes` mode takes them, like `1b 5b "?25l"`
+    #[arg(long, value_name = "BYTES")]
+    pub bytes: Option<String>,
+
+    /// Search the input as well as the output
+    #[arg(long)]
+    pub input: bool,
+
+    /// Bytes of context to show on each side of a match
+    #[arg(long, value_name = "BYTES", default_value_t = 32)]
+    pub context: usize,
+}
+
+#[derive(Args)]
+pub struct SnoopArgs {
+    /// Process (thread) to attach to
+    pub pid: i32,
+}
+
+#[derive(Args)]
+pub struct BpfArgs {
+    /// Pty to trace, as /dev/pts/N or N
+    pub tty: String,
+}
+
+#[derive(Args)]
+pub struct InsideArgs {
+    #[arg(long)]
+    pub arg0: Option<String>,
+
+    #[arg(last = true, required = true)]
+    pub command: Vec<String>,
+}
+
+#[derive(Args)]
+pub struct DefaultsArgs {
+    /// Instead, show where this system's defaults and the known ones of Linux, macOS and
+    /// FreeBSD disagree
+    #[arg(long)]
+    pub compare: bool,
+}
+
+#[derive(Args)]
+pub struct BenchArgs {
+    /// How many bytes the child writes
+    #[arg(short, long, default_value_t = 16 << 20)]
+    pub bytes: u64,
+
+    #[command(flatten)]
+    pub run: RunArgs,
+}
+
+#[derive(Args)]
+pub struct TestArgs {
+    /// Script with one step per line: send, sendline, expect, sleep or a macro name; send
+    /// and sendline also take a here-document (`send <<EOF`, `<<'EOF'` without escapes),
+    /// and `on PATTERN [TIMEOUT] { ... } on ... { ... } else { ... }` runs the steps for
+    /// whichever pattern appears first; `repeat N { ... }` runs steps N times, and
+    /// `retry N [timeout DURATION] { ... }` up to N times until they pass. ${NAME} is
+    /// replaced with the value from --var, or with a group of an earlier `expect-re REGEX`
+    /// (${1}, or the group's name)
+    pub script: PathBuf,
+
+    /// Value for ${NAME} in the script (repeatable)
+    #[arg(long = "var", value_name = "NAME=VALUE", value_parser = parse_key_value)]
+    pub vars: Vec<(String, String)>,
+
+    #[command(flatten)]
+    pub run: RunArgs,
+}
+
+#[derive(Args)]
+pub struct DaemonArgs {
+    /// Unix socket to listen on for `attach`
+    #[arg(long, value_name = "PATH")]
+    pub socket: PathBuf,
+
+    /// Capture file to write, including what goes by while nothing is attached
+    #[arg(short, long, value_name = "PATH")]
+    pub output: Option<PathBuf>,
+
+    /// Format of the capture, as for `record`
+    #[arg(long, default_value = "jsonl", value_parser = ["jsonl", "mmap", "cast"])]
+    pub format: String,
+
+    /// Let several terminals attach at once, all typing into the program, instead of a new
+    /// one taking over
+    #[arg(long)]
+    pub shared: bool,
+
+    /// File of `LEVEL TOKEN` lines a client must present one of, the level being observe,
+    /// write, control (resize) or admin (take over); without it anyone who can open the
+    /// socket is admin
+    #[arg(long, value_name = "PATH")]
+    pub token_file: Option<PathBuf>,
+
+    /// Permissions of the socket, in octal
+    #[arg(long, value_name = "MODE", default_value = "600")]
+    pub socket_mode: String,
+
+    #[command(flatten)]
+    pub run: RunArgs,
+}
+
+impl DaemonArgs {
+    pub fn format(&self) -> Format {
+        Format::parse(&self.format).unwrap_or(Format::Jsonl)
+    }
+}
+
+#[derive(Args)]
+pub struct AttachArgs {
+    /// Socket of the session, as given to `daemon`
+    pub socket: PathBuf,
+
+    /// Keys that detach, leaving the program running; caret notation and \x escapes work
+    #[arg(long, value_name = "KEYS", default_value = "^]d")]
+    pub detach_keys: String,
+
+    /// Name the input from this terminal goes under in the capture [default: $USER]
+    #[arg(long, value_name = "NAME")]
+    pub name: Option<String>,
+
+    /// File with the token to present [default: $DEBUG_PTY_TOKEN]
+    #[arg(long, value_name = "PATH")]
+    pub token_file: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct TmuxArgs {
+    /// tmux session to attach to [default: the most recent one]
+    #[arg(short, long, value_name = "NAME")]
+    pub session: Option<String>,
+
+    /// Pane to follow, by id like %3 [default: the active pane of the session]
+    #[arg(short, long, value_name = "ID")]
+    pub pane: Option<String>,
+
+    /// Capture file to write
+    #[arg(short, long, value_name = "PATH")]
+    pub output: Option<PathBuf>,
+
+    /// Format of the capture, as for `record`
+    #[arg(long, default_value = "jsonl", value_parser = ["jsonl", "mmap", "cast"])]
+    pub format: String,
+
+    /// Pass the output through a filter, as for `run` (repeatable)
+    #[arg(short, long = "filter", value_name = "SPEC")]
+    pub filters: Vec<String>,
+}
+
+impl TmuxArgs {
+    pub fn format(&self) -> Format {
+        Format::parse(&self.format).unwrap_or(Format::Jsonl)
+    }
+}
+
+#[derive(Args)]
+pub struct IngestArgs {
+    /// Log of the raw output
+    pub file: PathBuf,
+
+    /// scriptreplay timing file with the delay and length of each chunk; without one,
+    /// screen's time-stamp lines are used if the log has them
+    #[arg(long, value_name = "PATH")]
+    pub timing: Option<PathBuf>,
+
+    /// Capture file to write, for replay
+    #[arg(short, long, value_name = "PATH")]
+    pub output: Option<PathBuf>,
+
+    /// Format of the capture, as for `record`
+    #[arg(long, default_value = "jsonl", value_parser = ["jsonl", "mmap", "cast"])]
+    pub format: String,
+
+    /// Pass the output through a filter, as for `run` (repeatable)
+    #[arg(short, long = "filter", value_name = "SPEC")]
+    pub filters: Vec<String>,
+}
+
+impl IngestArgs {
+    pub fn format(&self) -> Format {
+        Format::parse(&self.format).unwrap_or(Format::Jsonl)
+    }
+}
+
+#[derive(Args)]
+pub struct CompareArgs {
+    /// First program to run, shown as A
+    #[arg(long, value_name = "SHELL")]
+    pub shell_a: String,
+
+    /// Second program to run, shown as B
+    #[arg(long, value_name = "SHELL")]
+    pub shell_b: String,
+
+    /// Script with one step per line, as for `test`
+    #[arg(long, value_name = "PATH")]
+    pub script: PathBuf,
+
+    /// Value for ${NAME} in the script (repeatable)
+    #[arg(long = "var", value_name = "NAME=VALUE", value_parser = parse_key_value)]
+    pub vars: Vec<(String, String)>,
+
+    #[command(flatten)]
+    pub run: RunArgs,
+}
@@ -0,0 +1,312 @@
+use crate::input::{self, LineEnding};
+use crate::{
+    debug_termios, execute, flags, holders, macros, parse_winsize, regions, send_line, xmodem,
+    Session, Writer, WriterMode,
+};
+
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
+
+use termios::Termios;
+
+use std::io::Error as IoError;
+use std::os::fd::RawFd;
+use std::str::FromStr as _;
+use std::time::Duration;
+
+pub struct Command {
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub about: &'static str,
+}
+
+macro_rules! commands {
+    ($($name: literal, $usage: literal, $about: literal;)*) => {
+        &[ $( Command { name: $name, usage: $usage, about: $about }, )* ]
+    };
+}
+
+pub const COMMANDS: &[Command] = commands![
+    "help", "", "print this list";
+    "stats", "", "print byte, chunk-size and read-gap statistics";
+    "who", "", "list the processes that have the slave or the master open";
+    "pts", "", "print the slave path, for other programs to open";
+    "resize", "ROWSxCOLS", "set the size of the pty";
+    "signal", "NAME", "send a signal (INT, TERM, ...) to the foreground process group";
+    "termios", "", "print the termios of the pty";
+    "mode", "str|bytes|b64", "change how the following lines are interpreted";
+    "lf", "LINE", "send LINE ending with LF";
+    "cr", "LINE", "send LINE ending with CR";
+    "crlf", "LINE", "send LINE ending with CR LF";
+    "noeol", "LINE", "send LINE without a line ending";
+    "cc", "NAME", "send the current c_cc byte for NAME (EOF, INTR, ...)";
+    "b64", "PAYLOAD", "decode PAYLOAD and send the raw bytes";
+    "alias", "[NAME = \"BYTES\"]", "define or list aliases, sent with :NAME";
+    "macro", "[NAME = STEP; ...]", "define or list macros, run with :NAME";
+    "mark", "LABEL", "put a marker in the capture, for replay --from";
+    "record", "pause|resume", "leave the traffic out of the capture, or take it in again";
+    "capture-start", "NAME", "start saving the output read from now on";
+    "capture-end", "NAME --to PATH", "write the output saved since capture-start NAME";
+    "sendfile", "PATH [--chunk BYTES] [--delay DURATION]", "stream a file into the master";
+    "xmodem", "send|recv PATH", "transfer a file with the child (rx/sx, rz --xmodem)";
+    "ymodem", "send|recv PATH", "batch transfer; recv PATH is a directory (rz/sz)";
+];
+
+pub fn print_help() {
+    println!("Commands (input lines starting with `:`):");
+    for cmd in COMMANDS {
+        let synopsis = format!(":{} {}", cmd.name, cmd.usage);
+        if synopsis.len() < 23 {
+            println!("    {synopsis:<23}{}", cmd.about);
+        } else {
+            println!("    {synopsis}");
+            println!("    {:<23}{}", "", cmd.about);
+        }
+    }
+    println!();
+    println!("Macro steps: send TEXT, sendline TEXT, expect \"TEXT\" [TIMEOUT], expect-re");
+    println!("\"REGEX\" [TIMEOUT], sleep DURATION, capture-start NAME, capture-end NAME --to");
+    println!("PATH, or the name of another alias or macro.");
+}
+
+pub fn arguments(name: &str) -> Vec<&'static str> {
+    match name {
+        "mode" => vec!["str", "bytes", "b64"],
+        "cc" => flags::CC.iter().map(|(_, name)| &name[1..]).collect(),
+        "xmodem" | "ymodem" => vec!["send", "recv"],
+        "record" => vec!["pause", "resume"],
+        "signal" => vec!["INT", "TERM", "HUP", "QUIT", "TSTP", "CONT", "KILL"],
+        _ => Vec::new(),
+    }
+}
+
+fn capture_end(arg: &str, session: &Session) -> Result<String, String> {
+    let (name, path) = regions::parse_end(arg)?;
+    let len = session.regions.lock().unwrap().end(&name, &path)?;
+    Ok(format!(
+        "CAPTURE-END {name} {len} bytes to {}",
+        path.display()
+    ))
+}
+
+pub fn takes_path(name: &str) -> bool {
+    matches!(name, "sendfile" | "xmodem" | "ymodem")
+}
+
+pub fn run(
+    command: &str,
+    master: RawFd,
+    writer: &mut Writer,
+    session: &Session,
+) -> Result<bool, IoError> {
+    let (name, arg) = command
+        .split_once(char::is_whitespace)
+        .unwrap_or((command, ""));
+
+    match name {
+        "help" => print_help(),
+        "stats" => session.stats.lock().unwrap().report(),
+        "pts" => match &session.slave_path {
+            Some(path) => println!("PTS {}", path.display()),
+            None => println!("The slave path is unknown"),
+        },
+        "who" => match session.slave_path.as_deref().and_then(holders::tty_index) {
+            Some(index) => holders::print(index),
+            None => println!("The slave is not a pty slave"),
+        },
+        "resize" => match parse_winsize(arg.trim()) {
+            Some(winsize) => {
+                unsafe { libc::ioctl(master, libc::TIOCSWINSZ, &winsize) };
+                let note = format!("WINSIZE {}x{}", winsize.ws_row, winsize.ws_col);
+                println!("{note}");
+                println!();
+                session.record(|rec| {
+                    rec.resize(winsize.ws_row, winsize.ws_col);
+                    rec.note(note);
+                });
+            }
+            None => println!("Usage: :resize ROWSxCOLS"),
+        },
+        "signal" => signal(arg.trim(), master),
+        "termios" => match Termios::from_fd(master) {
+            Ok(term) => debug_termios(&term),
+            Err(e) => println!("The termios is unavailable: {e}"),
+        },
+        "b64" => match input::decode_base64(arg) {
+            Ok(cmd) => execute(&cmd, master, session)?,
+            Err(e) => println!("Not sent: {e}"),
+        },
+        "mark" => {
+            let label = input::unquote(arg.trim());
+            println!("MARK {label:?}");
+            println!();
+            session.record(|rec| rec.mark(label));
+        }
+        "record" => record(arg.trim(), session),
+        "capture-start" => match input::unquote(arg.trim()) {
+            "" => println!("Usage: :capture-start NAME"),
+            name => {
+                session.regions.lock().unwrap().start(name);
+                println!("CAPTURE-START {name}");
+            }
+        },
+        "capture-end" => match capture_end(arg, session) {
+            Ok(line) => println!("{line}"),
+            Err(e) => println!("capture-end: {e}"),
+        },
+        "sendfile" => send_file(arg, master, session)?,
+        "xmodem" => xmodem::run(xmodem::Protocol::Xmodem, arg, master, session)?,
+        "ymodem" => xmodem::run(xmodem::Protocol::Ymodem, arg, master, session)?,
+        "lf" | "cr" | "crlf" => {
+            let ending = LineEnding::parse(name).unwrap();
+            return send_line(arg, ending, master, writer, session);
+        }
+        "noeol" => return send_line(arg, LineEnding::None, master, writer, session),
+        "cc" => send_cc(arg, master, session)?,
+        "mode" => match WriterMode::parse(arg) {
+            Some(mode) => {
+                println!("MODE {} (was {})", mode.name(), writer.mode.name());
+                println!();
+                session.record(|rec| rec.note(format!("MODE {}", mode.name())));
+                writer.mode = mode;
+            }
+            None => println!(
+                "Usage: :mode str|bytes|b64 (currently {})",
+                writer.mode.name()
+            ),
+        },
+        "alias" if arg.is_empty() => writer.macros.print_aliases(),
+        "alias" => {
+            if let Err(e) = writer.macros.define_alias(arg) {
+                println!("Invalid alias: {e}");
+            }
+        }
+        "macro" if arg.is_empty() => writer.macros.print_macros(),
+        "macro" => {
+            if let Err(e) = writer.macros.define_macro(arg) {
+                println!("Invalid macro: {e}");
+            }
+        }
+        name if arg.is_empty() && writer.macros.contains(name) => {
+            return macros::invoke(name, master, writer, session);
+        }
+        _ => println!("Unknown command :{command}"),
+    }
+
+    Ok(false)
+}
+
+fn record(arg: &str, session: &Session) {
+    let Some(recorder) = &session.recorder else {
+        println!("Not recording");
+        return;
+    };
+    let mut recorder = recorder.lock().unwrap();
+    match arg {
+        "pause" if recorder.is_paused() => println!("The capture is already paused"),
+        "resume" if !recorder.is_paused() => println!("The capture is not paused"),
+        "pause" | "resume" => {
+            recorder.set_paused(arg == "pause");
+            println!(
+                "CAPTURE {}",
+                if arg == "pause" { "paused" } else { "resumed" }
+            );
+            println!();
+        }
+        _ => println!("Usage: :record pause|resume"),
+    }
+}
+
+fn signal(name: &str, master: RawFd) {
+    let name = name.to_ascii_uppercase();
+    let name = if name.starts_with("SIG") {
+        name
+    } else {
+        format!("SIG{name}")
+    };
+    let Ok(sig) = Signal::from_str(&name) else {
+        println!("Unknown signal {name}");
+        return;
+    };
+    match unsafe { libc::tcgetpgrp(master) } {
+        -1 => println!("There is no foreground process group"),
+        pgrp => match nix::sys::signal::killpg(Pid::from_raw(pgrp), sig) {
+            Ok(()) => println!("SIGNAL {sig} sent to process group {pgrp}"),
+            Err(e) => println!("Could not send {sig}: {e}"),
+        },
+    }
+    println!();
+}
+
+fn send_cc(name: &str, master: RawFd, session: &Session) -> Result<(), IoError> {
+    let Some(index) = flags::cc_index(name) else {
+        println!("Unknown special character {name:?}");
+        return Ok(());
+    };
+
+    // On Linux the master reports the termios of its slave.
+    let term = Termios::from_fd(master)?;
+    let byte = term.c_cc[index];
+    if byte == flags::VDISABLE {
+        println!("{name} is disabled on this terminal");
+        return Ok(());
+    }
+
+    execute(&[byte], master, session)
+}
+
+fn send_file(arg: &str, master: RawFd, session: &Session) -> Result<(), IoError> {
+    let mut path = None;
+    let mut chunk = 1024;
+    let mut delay = Duration::ZERO;
+
+    let mut args = arg.split_whitespace();
+    while let Some(arg) = args.next() {
+        match arg {
+            "--chunk" => match args.next().and_then(|n| n.parse().ok()) {
+                Some(n) if n > 0 => chunk = n,
+                _ => {
+                    println!("--chunk takes a positive number of bytes");
+                    return Ok(());
+                }
+            },
+            "--delay" => match args.next().and_then(input::parse_duration) {
+                Some(d) => delay = d,
+                None => {
+                    println!("--delay takes a duration such as 10ms");
+                    return Ok(());
+                }
+            },
+            _ => path = Some(arg),
+        }
+    }
+
+    let Some(path) = path else {
+        println!("Usage: :sendfile PATH [--chunk BYTES] [--delay DURATION]");
+        return Ok(());
+    };
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(e) => {
+            println!("Could not read {path}: {e}");
+            return Ok(());
+        }
+    };
+
+    println!(
+        "SENDFILE {path} ({} bytes, chunk {chunk}, delay {delay:?})",
+        data.len()
+    );
+    let mut sent = 0;
+    for (i, bytes) in data.chunks(chunk).enumerate() {
+        if i > 0 && !delay.is_zero() {
+            std::thread::sleep(delay);
+        }
+        execute(bytes, master, session)?;
+        sent += bytes.len();
+        println!("SENDFILE {sent}/{}", data.len());
+    }
+    println!();
+
+    Ok(())
+}
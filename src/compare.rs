@@ -0,0 +1,361 @@
+use crate::cli::CompareArgs;
+use crate::macros::{self, Step, MAX_DEPTH};
+use crate::{build_cmd, env, flags, rawio, setup_pty, Args, Writer};
+
+use nix::errno::Errno;
+use regex::bytes::Regex;
+
+use termios::Termios;
+
+use std::collections::BTreeMap;
+use std::os::fd::{AsRawFd as _, OwnedFd};
+use std::process::{Child, ExitCode};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// A step is over once neither side has printed anything for this long.
+const SETTLE: Duration = Duration::from_millis(200);
+const MAX_SETTLE: Duration = Duration::from_secs(5);
+
+struct Side {
+    label: &'static str,
+    shell: String,
+    master: OwnedFd,
+    child: Child,
+    output: Arc<Mutex<Vec<u8>>>,
+    seen: usize,
+    term: Termios,
+}
+
+impl Side {
+    fn start(
+        label: &'static str,
+        shell: &str,
+        args: &Args,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let (pty, term) = setup_pty(args)?;
+        let env = env::compose(
+            &args.inherit_env,
+            shell,
+            env::load_files(args.env_files.as_deref())?,
+            &args.env,
+        );
+        let child = build_cmd(shell, pty.slave.as_raw_fd(), args.alloc, env)?.spawn()?;
+        drop(pty.slave);
+
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let fd = pty.master.as_raw_fd();
+        let sink = output.clone();
+        std::thread::spawn(move || {
+            let mut buf = [0; 4096];
+            loop {
+                match rawio::read(fd, &mut buf) {
+                    Ok(0) | Err(Errno::EIO) => break,
+                    Ok(num_bytes) => sink.lock().unwrap().extend_from_slice(&buf[..num_bytes]),
+                    Err(e) => {
+                        tracing::warn!("reading the master of {label}: {e}");
+                        break;
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            label,
+            shell: shell.to_string(),
+            master: pty.master,
+            child,
+            output,
+            seen: 0,
+            term,
+        })
+    }
+
+    fn len(&self) -> usize {
+        self.output.lock().unwrap().len()
+    }
+
+    fn contains_new(&self, pattern: &[u8]) -> bool {
+        let output = self.output.lock().unwrap();
+        output[self.seen..]
+            .windows(pattern.len().max(1))
+            .any(|window| window == pattern)
+    }
+
+    fn matches_new(&self, re: &Regex) -> bool {
+        let output = self.output.lock().unwrap();
+        re.is_match(&output[self.seen..])
+    }
+
+    fn first_new(&self, patterns: &[&[u8]]) -> Option<usize> {
+        let output = self.output.lock().unwrap();
+        crate::tap::earliest(&output[self.seen..], patterns).map(|(i, _)| i)
+    }
+
+    fn write(&self, bytes: &[u8]) {
+        if let Err((_, e)) = rawio::write_all(self.master.as_raw_fd(), bytes) {
+            println!("{} write failed: {e}", self.label);
+        }
+    }
+
+    // The output since the last step, and the termios flags that changed meanwhile.
+    fn take(&mut self) -> (Vec<u8>, Vec<String>) {
+        let output = self.output.lock().unwrap();
+        let new = output[self.seen..].to_vec();
+        self.seen = output.len();
+        drop(output);
+
+        let changes = match Termios::from_fd(self.master.as_raw_fd()) {
+            Ok(term) => {
+                let changes = flags::diff(&self.term, &term);
+                self.term = term;
+                changes
+            }
+            Err(_) => Vec::new(),
+        };
+        (new, changes)
+    }
+}
+
+pub fn run(compare: &CompareArgs, args: Args) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let script = std::fs::read_to_string(&compare.script)?;
+    let steps = crate::macros::parse_script(&script, &crate::cli::key_values(&compare.vars))
+        .map_err(|e| format!("{}: {e}", compare.script.display()))?;
+
+    let mut sides = [
+        Side::start("A", &compare.shell_a, &args)?,
+        Side::start("B", &compare.shell_b, &args)?,
+    ];
+    for side in &sides {
+        println!("{} {}", side.label, side.shell);
+    }
+    let writer = Writer {
+        mode: args.mode,
+        escapes: args.escapes,
+        line_ending: args.line_ending,
+        macros: args.macros,
+    };
+
+    let mut comparison = Comparison::default();
+    // Whatever the shells print before any input, like the first prompt.
+    comparison.step("start", &mut sides);
+    comparison.run(&steps, &writer, &mut sides, 0);
+
+    for side in &mut sides {
+        let _ = side.child.kill();
+        let status = side.child.wait()?;
+        println!("{} {} {status}", side.label, side.shell);
+    }
+    println!(
+        "COMPARE {} of {} steps differ",
+        comparison.differ, comparison.steps
+    );
+
+    Ok(if comparison.differ > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+#[derive(Default)]
+struct Comparison {
+    steps: usize,
+    differ: usize,
+}
+
+impl Comparison {
+    fn run(&mut self, steps: &[Step], writer: &Writer, sides: &mut [Side; 2], depth: usize) {
+        for step in steps {
+            match step {
+                Step::Send(text) | Step::SendLine(text) => {
+                    let mut bytes = match writer.interpret(text) {
+                        Ok(bytes) => bytes,
+                        Err(e) => {
+                            println!("Not sent: {e}");
+                            continue;
+                        }
+                    };
+                    if matches!(step, Step::SendLine(_)) {
+                        bytes.extend_from_slice(writer.default_ending().as_bytes());
+                    }
+                    // Back to back, so that neither child gets a head start.
+                    for side in sides.iter() {
+                        side.write(&bytes);
+                    }
+                    self.step(&format!("send {text:?}"), sides);
+                }
+                Step::SendRaw(text) | Step::SendLineRaw(text) => {
+                    let mut bytes = text.clone();
+                    if matches!(step, Step::SendLineRaw(_)) {
+                        bytes.extend_from_slice(writer.default_ending().as_bytes());
+                    }
+                    for side in sides.iter() {
+                        side.write(&bytes);
+                    }
+                    let text = String::from_utf8_lossy(text);
+                    self.step(&format!("send {text:?}"), sides);
+                }
+                Step::Expect(pattern, timeout) => {
+                    let start = Instant::now();
+                    while start.elapsed() < *timeout
+                        && !sides.iter().all(|side| side.contains_new(pattern))
+                    {
+                        std::thread::sleep(Duration::from_millis(10));
+                    }
+                    for side in sides.iter() {
+                        if !side.contains_new(pattern) {
+                            println!(
+                                "{} did not print {:?} within {timeout:?}",
+                                side.label,
+                                String::from_utf8_lossy(pattern)
+                            );
+                        }
+                    }
+                    self.step(
+                        &format!("expect {:?}", String::from_utf8_lossy(pattern)),
+                        sides,
+                    );
+                }
+                // Each side would bind different values, so what follows is sent as written.
+                Step::ExpectRe(pattern, timeout) => {
+                    let re = match macros::compile_re(pattern, &BTreeMap::new()) {
+                        Ok(re) => re,
+                        Err(e) => {
+                            println!("Not matched: {e}");
+                            self.step(&format!("expect-re {pattern}"), sides);
+                            continue;
+                        }
+                    };
+                    let start = Instant::now();
+                    while start.elapsed() < *timeout
+                        && !sides.iter().all(|side| side.matches_new(&re))
+                    {
+                        std::thread::sleep(Duration::from_millis(10));
+                    }
+                    for side in sides.iter() {
+                        if !side.matches_new(&re) {
+                            println!("{} did not match {re} within {timeout:?}", side.label);
+                        }
+                    }
+                    self.step(&format!("expect-re {re}"), sides);
+                }
+                Step::Sleep(duration) => std::thread::sleep(*duration),
+                // The output of both sides is shown step by step already.
+                Step::CaptureStart(_) | Step::CaptureEnd(..) => {}
+                // Both sides must take the same branch for the rest to be comparable.
+                Step::On(branches, otherwise, timeout) => {
+                    let patterns: Vec<&[u8]> = branches.iter().map(|(p, _)| p.as_slice()).collect();
+                    let start = Instant::now();
+                    while start.elapsed() < *timeout
+                        && !sides.iter().all(|side| side.first_new(&patterns).is_some())
+                    {
+                        std::thread::sleep(Duration::from_millis(10));
+                    }
+                    let taken = sides.each_ref().map(|side| side.first_new(&patterns));
+                    let shown = |taken: Option<usize>| match taken {
+                        Some(i) => format!("on {:?}", String::from_utf8_lossy(patterns[i])),
+                        None => "else".to_string(),
+                    };
+                    if taken[0] != taken[1] {
+                        println!("A took {}, B took {}", shown(taken[0]), shown(taken[1]));
+                        self.step("on", sides);
+                        continue;
+                    }
+                    self.step(&shown(taken[0]), sides);
+                    let steps = match taken[0] {
+                        Some(i) => &branches[i].1,
+                        None => match otherwise {
+                            Some(steps) => steps,
+                            None => continue,
+                        },
+                    };
+                    self.run(steps, writer, sides, depth);
+                }
+                Step::Repeat(count, steps) => {
+                    for _ in 0..*count {
+                        self.run(steps, writer, sides, depth);
+                    }
+                }
+                // Nothing fails here for a retry to react to; expects only report.
+                Step::Retry(_, steps) => self.run(steps, writer, sides, depth),
+                Step::Invoke(name) => {
+                    if let Some(value) = writer.macros.alias(name) {
+                        for side in sides.iter() {
+                            side.write(value);
+                        }
+                        self.step(&format!("alias {name}"), sides);
+                    } else if let Some(steps) = writer.macros.steps(name) {
+                        if depth >= MAX_DEPTH {
+                            println!("MACRO {name} nested too deeply, stopped");
+                            continue;
+                        }
+                        self.run(steps, writer, sides, depth + 1);
+                    } else {
+                        println!("MACRO {name} is not defined");
+                    }
+                }
+            }
+        }
+    }
+
+    fn step(&mut self, label: &str, sides: &mut [Side; 2]) {
+        settle(sides);
+        self.steps += 1;
+
+        let [a, b] = sides;
+        let (out_a, term_a) = a.take();
+        let (out_b, term_b) = b.take();
+        let same = out_a == out_b && term_a == term_b;
+        println!(
+            "STEP {} {label} {}",
+            self.steps,
+            if same { "SAME" } else { "DIFFER" }
+        );
+        if !same {
+            self.differ += 1;
+        }
+
+        let (lines_a, lines_b) = (split_lines(&out_a), split_lines(&out_b));
+        for i in 0..lines_a.len().max(lines_b.len()) {
+            match (lines_a.get(i), lines_b.get(i)) {
+                (Some(line_a), Some(line_b)) if line_a == line_b => println!("  = {line_a:?}"),
+                (line_a, line_b) => {
+                    if let Some(line) = line_a {
+                        println!("  A {line:?}");
+                    }
+                    if let Some(line) = line_b {
+                        println!("  B {line:?}");
+                    }
+                }
+            }
+        }
+        for (side, changes) in [(&a, term_a), (&b, term_b)] {
+            if !changes.is_empty() {
+                println!("  {} termios {}", side.label, changes.join(" "));
+            }
+        }
+    }
+}
+
+fn settle(sides: &[Side; 2]) {
+    let start = Instant::now();
+    let mut lens = sides.each_ref().map(Side::len);
+    let mut quiet = Instant::now();
+    while quiet.elapsed() < SETTLE && start.elapsed() < MAX_SETTLE {
+        std::thread::sleep(Duration::from_millis(20));
+        let now = sides.each_ref().map(Side::len);
+        if now != lens {
+            lens = now;
+            quiet = Instant::now();
+        }
+    }
+}
+
+// Lines keep their \r so that CR handling differences show up.
+fn split_lines(output: &[u8]) -> Vec<String> {
+    String::from_utf8_lossy(output)
+        .split_inclusive('\n')
+        .map(str::to_string)
+        .collect()
+}
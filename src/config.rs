@@ -0,0 +1,214 @@
+use crate::hooks::HookConfig;
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use std::collections::BTreeMap;
+use std::io::ErrorKind as IoErrorKind;
+use std::path::PathBuf;
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Settings {
+    pub shell: Option<String>,
+    pub mode: Option<String>,
+    pub escapes: Option<bool>,
+    pub line_ending: Option<String>,
+    pub inject: Option<String>,
+    pub echo_latency: Option<bool>,
+    pub status: Option<bool>,
+    pub winsize: Option<String>,
+    pub termios: Option<Vec<String>>,
+    pub env: Option<Vec<String>>,
+    pub inherit_env: Option<bool>,
+    pub inherit_env_only: Option<Vec<String>>,
+    pub env_files: Option<Vec<String>>,
+    pub cwd: Option<String>,
+    pub chroot: Option<String>,
+    pub login: Option<bool>,
+    pub exec: Option<Vec<String>>,
+    pub ssh: Option<String>,
+    pub docker: Option<String>,
+    pub podman: Option<String>,
+    pub scope: Option<String>,
+    pub scope_property: Option<Vec<String>>,
+    pub user: Option<String>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub unshare: Option<Vec<String>>,
+    pub rlimit: Option<Vec<String>>,
+    pub utmp: Option<bool>,
+    pub subreaper: Option<bool>,
+    pub trace_child: Option<bool>,
+    pub filters: Option<Vec<String>>,
+    pub metrics: Option<String>,
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    pub inside_report: Option<bool>,
+    pub self_test: Option<bool>,
+    pub alloc: Option<String>,
+    pub print_pts: Option<bool>,
+    pub nonblock: Option<bool>,
+    pub readers: Option<usize>,
+    pub read_buffer: Option<usize>,
+    pub read_strategy: Option<String>,
+    pub raw_log: Option<String>,
+    pub reset_on_exit: Option<bool>,
+    pub host_title: Option<String>,
+    pub escape_char: Option<String>,
+    pub lines: Option<String>,
+    pub classify_echo: Option<bool>,
+    pub ldisc_notes: Option<bool>,
+    pub baseline: Option<Vec<String>>,
+    pub baseline_strict: Option<bool>,
+    pub prompt_detect: Option<String>,
+}
+
+impl Settings {
+    pub fn or(self, other: Self) -> Self {
+        Self {
+            shell: self.shell.or(other.shell),
+            mode: self.mode.or(other.mode),
+            escapes: self.escapes.or(other.escapes),
+            line_ending: self.line_ending.or(other.line_ending),
+            inject: self.inject.or(other.inject),
+            echo_latency: self.echo_latency.or(other.echo_latency),
+            status: self.status.or(other.status),
+            winsize: self.winsize.or(other.winsize),
+            termios: self.termios.or(other.termios),
+            env: self.env.or(other.env),
+            inherit_env: self.inherit_env.or(other.inherit_env),
+            inherit_env_only: self.inherit_env_only.or(other.inherit_env_only),
+            env_files: self.env_files.or(other.env_files),
+            cwd: self.cwd.or(other.cwd),
+            chroot: self.chroot.or(other.chroot),
+            login: self.login.or(other.login),
+            exec: self.exec.or(other.exec),
+            ssh: self.ssh.or(other.ssh),
+            docker: self.docker.or(other.docker),
+            podman: self.podman.or(other.podman),
+            scope: self.scope.or(other.scope),
+            scope_property: self.scope_property.or(other.scope_property),
+            user: self.user.or(other.user),
+            uid: self.uid.or(other.uid),
+            gid: self.gid.or(other.gid),
+            unshare: self.unshare.or(other.unshare),
+            rlimit: self.rlimit.or(other.rlimit),
+            utmp: self.utmp.or(other.utmp),
+            subreaper: self.subreaper.or(other.subreaper),
+            trace_child: self.trace_child.or(other.trace_child),
+            filters: self.filters.or(other.filters),
+            metrics: self.metrics.or(other.metrics),
+            tls_cert: self.tls_cert.or(other.tls_cert),
+            tls_key: self.tls_key.or(other.tls_key),
+            inside_report: self.inside_report.or(other.inside_report),
+            self_test: self.self_test.or(other.self_test),
+            alloc: self.alloc.or(other.alloc),
+            print_pts: self.print_pts.or(other.print_pts),
+            nonblock: self.nonblock.or(other.nonblock),
+            readers: self.readers.or(other.readers),
+            read_buffer: self.read_buffer.or(other.read_buffer),
+            read_strategy: self.read_strategy.or(other.read_strategy),
+            raw_log: self.raw_log.or(other.raw_log),
+            reset_on_exit: self.reset_on_exit.or(other.reset_on_exit),
+            host_title: self.host_title.or(other.host_title),
+            escape_char: self.escape_char.or(other.escape_char),
+            lines: self.lines.or(other.lines),
+            classify_echo: self.classify_echo.or(other.classify_echo),
+            ldisc_notes: self.ldisc_notes.or(other.ldisc_notes),
+            baseline: self.baseline.or(other.baseline),
+            baseline_strict: self.baseline_strict.or(other.baseline_strict),
+            prompt_detect: self.prompt_detect.or(other.prompt_detect),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Config {
+    pub settings: Settings,
+    pub profiles: BTreeMap<String, Settings>,
+    pub aliases: BTreeMap<String, String>,
+    pub macros: BTreeMap<String, String>,
+    pub hooks: HookConfig,
+}
+
+impl Config {
+    pub fn load(path: Option<&str>) -> Result<Self, String> {
+        let (path, required) = match path {
+            Some(path) => (PathBuf::from(path), true),
+            None => match default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Self::default()),
+            },
+        };
+
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if !required && e.kind() == IoErrorKind::NotFound => {
+                return Ok(Self::default());
+            }
+            Err(e) => return Err(format!("{}: {e}", path.display())),
+        };
+
+        Self::parse(&text).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    fn parse(text: &str) -> Result<Self, toml::de::Error> {
+        // The top-level keys are the default profile, so the tables are taken out before
+        // the rest is checked for unknown settings.
+        let mut table: toml::Table = toml::from_str(text)?;
+        let profiles = take(&mut table, "profiles")?.unwrap_or_default();
+        let aliases = take(&mut table, "aliases")?.unwrap_or_default();
+        let macros = take(&mut table, "macros")?.unwrap_or_default();
+        let hooks = take(&mut table, "hooks")?.unwrap_or_default();
+        let settings = toml::Value::Table(table).try_into()?;
+
+        Ok(Self {
+            settings,
+            profiles,
+            aliases,
+            macros,
+            hooks,
+        })
+    }
+
+    pub fn take_settings(&mut self, profile: Option<&str>) -> Result<Settings, String> {
+        let defaults = std::mem::take(&mut self.settings);
+        match profile {
+            Some(name) => match self.profiles.remove(name) {
+                Some(profile) => Ok(profile.or(defaults)),
+                None => Err(format!("unknown profile {name:?}")),
+            },
+            None => Ok(defaults),
+        }
+    }
+}
+
+fn take<T: DeserializeOwned>(
+    table: &mut toml::Table,
+    key: &str,
+) -> Result<Option<T>, toml::de::Error> {
+    table.remove(key).map(|value| value.try_into()).transpose()
+}
+
+pub fn setting<T>(
+    key: &str,
+    value: Option<&str>,
+    parse: impl Fn(&str) -> Option<T>,
+) -> Result<Option<T>, String> {
+    match value {
+        Some(value) => match parse(value) {
+            Some(value) => Ok(Some(value)),
+            None => Err(format!("invalid {key} {value:?}")),
+        },
+        None => Ok(None),
+    }
+}
+
+fn default_path() -> Option<PathBuf> {
+    let dir = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(dir.join("debug-pty/config.toml"))
+}
@@ -0,0 +1,442 @@
+use crate::auth::{Level, Tokens};
+use crate::capture::{Reading, Recorder};
+use crate::cli::{AttachArgs, DaemonArgs};
+use crate::escape::{caret, Detach};
+use crate::{alloc, hostterm, mitm, open_pty, rawio, spawn_child, Args};
+
+use nix::errno::Errno;
+use nix::unistd::ForkResult;
+
+use termios::Termios;
+
+use std::collections::BTreeMap;
+use std::io::{Read as _, Write as _};
+use std::os::fd::{AsRawFd as _, RawFd};
+use std::os::unix::fs::{FileTypeExt as _, PermissionsExt as _};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::process::ExitCode;
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// What an attached terminal sends: a kind byte, a big-endian length and the payload. The
+// output comes back as it is.
+const FRAME_INPUT: u8 = 0;
+const FRAME_WINSIZE: u8 = 1;
+const FRAME_NAME: u8 = 2;
+const FRAME_AUTH: u8 = 3;
+
+const TOKEN_VAR: &str = "DEBUG_PTY_TOKEN";
+const AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+const POLL_MS: libc::c_int = 200;
+// Reads queued for a terminal before it counts as stalled and is dropped.
+const CLIENT_QUEUE: usize = 256;
+
+type Clients = Arc<Mutex<BTreeMap<u32, Client>>>;
+
+// The output goes to each attached terminal through a queue and a thread of its own, so
+// that one that stops reading holds up neither the others nor the program.
+struct Client {
+    queue: SyncSender<Vec<u8>>,
+    stream: UnixStream,
+    level: Level,
+}
+
+impl Client {
+    fn new(stream: UnixStream, level: Level) -> Result<Self, std::io::Error> {
+        let mut writer = stream.try_clone()?;
+        let (queue, rx) = sync_channel::<Vec<u8>>(CLIENT_QUEUE);
+        std::thread::spawn(move || {
+            for chunk in rx {
+                if writer.write_all(&chunk).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Self {
+            queue,
+            stream,
+            level,
+        })
+    }
+
+    fn close(&self) {
+        let _ = self.stream.shutdown(std::net::Shutdown::Both);
+    }
+}
+
+// Starts the program on a pty and goes into the background, keeping the master and the
+// capture until the program exits. Unless shared, one terminal at a time is attached to
+// type, and an admin attaching takes over.
+pub fn serve(mut args: Args, daemon: &DaemonArgs) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let socket = &daemon.socket;
+    let tokens = match &daemon.token_file {
+        Some(path) => Some(Arc::new(Tokens::load(path)?)),
+        None => None,
+    };
+    let mode = u32::from_str_radix(&daemon.socket_mode, 8)
+        .map_err(|_| format!("invalid socket mode {:?}", daemon.socket_mode))?;
+    // A stale socket left by a session that died is replaced; anything else is a mistake.
+    if let Ok(meta) = socket.symlink_metadata() {
+        if !meta.file_type().is_socket() {
+            return Err(format!("{} exists and is not a socket", socket.display()).into());
+        }
+        if UnixStream::connect(socket).is_ok() {
+            return Err(format!("{} is in use by a running session", socket.display()).into());
+        }
+        std::fs::remove_file(socket)?;
+    }
+    let listener = UnixListener::bind(socket)?;
+    std::fs::set_permissions(socket, std::fs::Permissions::from_mode(mode))?;
+    let pty = open_pty(args.alloc, args.winsize.as_ref())?;
+    let master = pty.master.as_raw_fd();
+
+    // Still a single thread here, so the fork is safe.
+    match unsafe { nix::unistd::fork() }? {
+        ForkResult::Parent { child } => {
+            println!("Session started in process {child}");
+            println!("Attach with: debug-pty attach {}", socket.display());
+            return Ok(ExitCode::SUCCESS);
+        }
+        ForkResult::Child => {}
+    }
+    nix::unistd::setsid()?;
+    let null = std::fs::File::options()
+        .read(true)
+        .write(true)
+        .open("/dev/null")?;
+    for fd in 0..3 {
+        nix::unistd::dup2(null.as_raw_fd(), fd)?;
+    }
+
+    let slave = alloc::ptsname(master).ok();
+    let recorder = match &daemon.output {
+        Some(log) => Some(Arc::new(Mutex::new(Recorder::create(
+            log,
+            daemon.format(),
+            &args.argv().join(" "),
+            slave.as_deref(),
+            Some(Reading {
+                buffer: args.read_buffer.unwrap_or(4096),
+                strategy: args.read_strategy.name().to_string(),
+            }),
+            Some(crate::metadata(&args, master, None)?),
+        )?))),
+        None => None,
+    };
+    if let (Some(recorder), Some(size)) = (&recorder, &args.winsize) {
+        recorder.lock().unwrap().resize(size.ws_row, size.ws_col);
+    }
+    let mut child = spawn_child(&mut args, pty.slave)?;
+
+    let clients = Clients::default();
+    let shared = daemon.shared;
+    spawn_accept(
+        listener,
+        master,
+        shared,
+        tokens,
+        clients.clone(),
+        recorder.clone(),
+    );
+
+    let mut buf = vec![0; args.read_buffer.unwrap_or(4096)];
+    loop {
+        match rawio::read_chunk(master, &mut buf, args.read_strategy) {
+            Ok(0) | Err(Errno::EIO) => break,
+            Ok(num_bytes) => {
+                if let Some(recorder) = &recorder {
+                    recorder.lock().unwrap().output(&buf[..num_bytes]);
+                }
+                // Output while nobody is attached is only in the capture.
+                clients.lock().unwrap().retain(|id, client| {
+                    if client.queue.try_send(buf[..num_bytes].to_vec()).is_ok() {
+                        return true;
+                    }
+                    if let Some(recorder) = &recorder {
+                        recorder
+                            .lock()
+                            .unwrap()
+                            .note(format!("DROPPED #{id}, not reading"));
+                    }
+                    client.close();
+                    false
+                });
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let _ = std::fs::remove_file(socket);
+    for client in std::mem::take(&mut *clients.lock().unwrap()).into_values() {
+        client.close();
+    }
+    let status = child.wait()?;
+    if let Some(recorder) = &recorder {
+        let mut recorder = recorder.lock().unwrap();
+        recorder.exit(&status);
+        recorder.finish();
+    }
+    Ok(ExitCode::from(status.code().unwrap_or(1) as u8))
+}
+
+fn spawn_accept(
+    listener: UnixListener,
+    master: RawFd,
+    shared: bool,
+    tokens: Option<Arc<Tokens>>,
+    clients: Clients,
+    recorder: Option<Arc<Mutex<Recorder>>>,
+) {
+    std::thread::spawn(move || {
+        for (id, stream) in (1..).zip(listener.incoming()) {
+            let Ok(mut stream) = stream else {
+                continue;
+            };
+            let Ok(mut reader) = stream.try_clone() else {
+                continue;
+            };
+            let clients = clients.clone();
+            let tokens = tokens.clone();
+            let recorder = recorder.clone();
+            // Each on a thread of its own, so that a client slow to authenticate holds up
+            // nobody else.
+            std::thread::spawn(move || {
+                // Without tokens the socket's permissions are all there is, and whoever
+                // gets through them may do anything.
+                let level = match &tokens {
+                    Some(tokens) => match authenticate(&mut reader, tokens) {
+                        Some(level) => level,
+                        None => {
+                            let _ = stream.write_all(b"debug-pty: access denied\r\n");
+                            if let Some(recorder) = &recorder {
+                                recorder.lock().unwrap().note(format!("DENIED #{id}"));
+                            }
+                            return;
+                        }
+                    },
+                    None => Level::Admin,
+                };
+                {
+                    // Unless shared, one terminal at a time may write. Only an admin takes
+                    // over from it; observers watch alongside.
+                    let mut clients = clients.lock().unwrap();
+                    let writing: Vec<u32> = clients
+                        .iter()
+                        .filter(|(_, client)| client.level >= Level::Write)
+                        .map(|(&id, _)| id)
+                        .collect();
+                    if !shared && level >= Level::Write && !writing.is_empty() {
+                        if level < Level::Admin {
+                            let _ = stream.write_all(b"debug-pty: session in use\r\n");
+                            if let Some(recorder) = &recorder {
+                                recorder
+                                    .lock()
+                                    .unwrap()
+                                    .note(format!("REFUSED #{id}, in use"));
+                            }
+                            return;
+                        }
+                        for previous in writing {
+                            if let Some(previous) = clients.remove(&previous) {
+                                previous.close();
+                            }
+                        }
+                    }
+                    let Ok(client) = Client::new(stream, level) else {
+                        return;
+                    };
+                    clients.insert(id, client);
+                }
+                relay(reader, id, level, master, recorder.as_deref());
+                clients.lock().unwrap().remove(&id);
+            });
+        }
+    });
+}
+
+fn authenticate(stream: &mut UnixStream, tokens: &Tokens) -> Option<Level> {
+    stream.set_read_timeout(Some(AUTH_TIMEOUT)).ok()?;
+    let mut head = [0; 3];
+    stream.read_exact(&mut head).ok()?;
+    if head[0] != FRAME_AUTH {
+        return None;
+    }
+    let mut token = vec![0; u16::from_be_bytes([head[1], head[2]]) as usize];
+    stream.read_exact(&mut token).ok()?;
+    stream.set_read_timeout(None).ok()?;
+    tokens.check(&token)
+}
+
+// Input from one attached terminal, until it detaches or is taken over, as far as its level
+// allows. The capture tags it with the name the terminal gave and the number it got here.
+fn relay(
+    mut stream: UnixStream,
+    id: u32,
+    level: Level,
+    master: RawFd,
+    recorder: Option<&Mutex<Recorder>>,
+) {
+    let mut client = format!("#{id}");
+    let mut head = [0; 3];
+    while stream.read_exact(&mut head).is_ok() {
+        let mut payload = vec![0; u16::from_be_bytes([head[1], head[2]]) as usize];
+        if stream.read_exact(&mut payload).is_err() {
+            break;
+        }
+        match head[0] {
+            FRAME_NAME => {
+                client = format!("{}#{id}", String::from_utf8_lossy(&payload));
+                if let Some(recorder) = recorder {
+                    let note = format!("ATTACH {client} as {}", level.name());
+                    recorder.lock().unwrap().note(note);
+                }
+            }
+            FRAME_INPUT if level >= Level::Write => {
+                if let Some(recorder) = recorder {
+                    recorder.lock().unwrap().input_from(&client, &payload);
+                }
+                if rawio::write_all(master, &payload).is_err() {
+                    break;
+                }
+            }
+            FRAME_WINSIZE if payload.len() == 4 && level >= Level::Control => {
+                let rows = u16::from_be_bytes([payload[0], payload[1]]);
+                let cols = u16::from_be_bytes([payload[2], payload[3]]);
+                let size = libc::winsize {
+                    ws_row: rows,
+                    ws_col: cols,
+                    ws_xpixel: 0,
+                    ws_ypixel: 0,
+                };
+                unsafe { libc::ioctl(master, libc::TIOCSWINSZ, &size) };
+                if let Some(recorder) = recorder {
+                    let mut recorder = recorder.lock().unwrap();
+                    recorder.resize(rows, cols);
+                    recorder.note(format!("WINSIZE {rows}x{cols} from {client}"));
+                }
+            }
+            _ => {}
+        }
+    }
+    if let Some(recorder) = recorder {
+        recorder.lock().unwrap().note(format!("DETACH {client}"));
+    }
+}
+
+enum End {
+    Detached,
+    Ended,
+}
+
+pub fn attach(attach: &AttachArgs) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let socket = &attach.socket;
+    if !nix::unistd::isatty(0).unwrap_or(false) {
+        return Err("attach needs a terminal on stdin".into());
+    }
+    let keys = crate::input::unescape_keys(&attach.detach_keys);
+    if keys.is_empty() {
+        return Err("the detach keys cannot be empty".into());
+    }
+    let mut stream = UnixStream::connect(socket)
+        .map_err(|e| format!("could not attach to {}: {e}", socket.display()))?;
+    let token = match &attach.token_file {
+        Some(path) => Some(
+            std::fs::read_to_string(path)
+                .map_err(|e| format!("{}: {e}", path.display()))?
+                .trim()
+                .to_string(),
+        ),
+        None => std::env::var(TOKEN_VAR).ok(),
+    };
+    if let Some(token) = token {
+        send(&mut stream, FRAME_AUTH, token.as_bytes())?;
+    }
+    let name = match &attach.name {
+        Some(name) => name.clone(),
+        None => std::env::var("USER").unwrap_or_else(|_| "unknown".to_string()),
+    };
+    send(&mut stream, FRAME_NAME, name.as_bytes())?;
+    let keys_shown: String = keys.iter().map(|&key| caret(key)).collect();
+    println!("Attached to {}, {keys_shown} detaches", socket.display());
+
+    let mut raw = Termios::from_fd(0)?;
+    termios::cfmakeraw(&mut raw);
+    let guard = hostterm::Guard::enter(0, &raw)?;
+
+    let mut detach = Detach::new(keys);
+    let mut last = None;
+    let mut stdout = std::io::stdout();
+    let mut buf = [0; 4096];
+    let end = loop {
+        // Polled like wrap does rather than caught as SIGWINCH.
+        if let Some(size) = mitm::winsize(0) {
+            let size = (size.ws_row, size.ws_col);
+            if last != Some(size) {
+                let mut payload = size.0.to_be_bytes().to_vec();
+                payload.extend_from_slice(&size.1.to_be_bytes());
+                send(&mut stream, FRAME_WINSIZE, &payload)?;
+                last = Some(size);
+            }
+        }
+
+        let mut fds = [
+            libc::pollfd {
+                fd: 0,
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: stream.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+        if unsafe { libc::poll(fds.as_mut_ptr(), 2, POLL_MS) } <= 0 {
+            continue;
+        }
+        if fds[1].revents != 0 {
+            match stream.read(&mut buf) {
+                Ok(0) | Err(_) => break End::Ended,
+                Ok(num_bytes) => {
+                    stdout.write_all(&buf[..num_bytes])?;
+                    stdout.flush()?;
+                }
+            }
+        }
+        if fds[0].revents != 0 {
+            let num_bytes = match rawio::read(0, &mut buf) {
+                Ok(0) | Err(_) => break End::Detached,
+                Ok(num_bytes) => num_bytes,
+            };
+            let (input, detached) = detach.scan(&buf[..num_bytes]);
+            if !input.is_empty() {
+                send(&mut stream, FRAME_INPUT, &input)?;
+            }
+            if detached {
+                break End::Detached;
+            }
+        }
+    };
+    drop(guard);
+
+    match end {
+        End::Detached => {
+            println!("\r\nDetached, the program keeps running");
+            println!("Reattach with: debug-pty attach {}", socket.display());
+        }
+        End::Ended => println!("\r\nDisconnected: the program exited or another terminal attached"),
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn send(stream: &mut UnixStream, kind: u8, payload: &[u8]) -> Result<(), std::io::Error> {
+    for chunk in payload.chunks(u16::MAX as usize) {
+        let mut frame = vec![kind];
+        frame.extend_from_slice(&(chunk.len() as u16).to_be_bytes());
+        frame.extend_from_slice(chunk);
+        stream.write_all(&frame)?;
+    }
+    Ok(())
+}
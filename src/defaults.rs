@@ -0,0 +1,254 @@
+use crate::flags;
+
+use nix::pty::Winsize;
+
+use serde::Serialize;
+
+use termios::Termios;
+
+use std::collections::BTreeSet;
+use std::os::fd::AsRawFd as _;
+
+#[derive(Serialize)]
+struct Defaults {
+    platform: String,
+    rows: u16,
+    cols: u16,
+    speed: Option<u32>,
+    #[serde(flatten)]
+    termios: flags::Snapshot,
+}
+
+struct Known {
+    platform: &'static str,
+    speed: u32,
+    iflag: &'static [&'static str],
+    oflag: &'static [&'static str],
+    cflag: &'static [&'static str],
+    lflag: &'static [&'static str],
+    cc: &'static [(&'static str, u8)],
+}
+
+// What a fresh pty starts with on each system: tty_std_termios in the Linux kernel and
+// TTYDEF_* from sys/ttydefaults.h on macOS and FreeBSD. Linux disables a cc with 0, the
+// BSDs with 0xff.
+const KNOWN: &[Known] = &[
+    Known {
+        platform: "linux",
+        speed: 38400,
+        iflag: &["ICRNL", "IXON"],
+        oflag: &["OPOST", "ONLCR"],
+        cflag: &["CBAUD", "CSIZE", "CREAD", "HUPCL"],
+        lflag: &[
+            "ISIG", "ICANON", "ECHO", "ECHOE", "ECHOK", "ECHOCTL", "ECHOKE", "IEXTEN",
+        ],
+        cc: &[
+            ("VDISCARD", 0x0f),
+            ("VEOF", 0x04),
+            ("VEOL", 0x00),
+            ("VEOL2", 0x00),
+            ("VERASE", 0x7f),
+            ("VINTR", 0x03),
+            ("VKILL", 0x15),
+            ("VLNEXT", 0x16),
+            ("VMIN", 1),
+            ("VQUIT", 0x1c),
+            ("VREPRINT", 0x12),
+            ("VSTART", 0x11),
+            ("VSTOP", 0x13),
+            ("VSUSP", 0x1a),
+            ("VSWTCH", 0x00),
+            ("VTIME", 0),
+            ("VWERASE", 0x17),
+        ],
+    },
+    Known {
+        platform: "macos",
+        speed: 9600,
+        iflag: &["BRKINT", "ICRNL", "IXON", "IXANY", "IMAXBEL"],
+        oflag: &["OPOST", "ONLCR"],
+        cflag: &["CSIZE", "CREAD", "HUPCL"],
+        lflag: &[
+            "ISIG", "ICANON", "ECHO", "ECHOE", "ECHOCTL", "ECHOKE", "IEXTEN",
+        ],
+        cc: &[
+            ("VDISCARD", 0x0f),
+            ("VDSUSP", 0x19),
+            ("VEOF", 0x04),
+            ("VEOL", 0xff),
+            ("VEOL2", 0xff),
+            ("VERASE", 0x7f),
+            ("VINTR", 0x03),
+            ("VKILL", 0x15),
+            ("VLNEXT", 0x16),
+            ("VMIN", 1),
+            ("VQUIT", 0x1c),
+            ("VREPRINT", 0x12),
+            ("VSTART", 0x11),
+            ("VSTATUS", 0x14),
+            ("VSTOP", 0x13),
+            ("VSUSP", 0x1a),
+            ("VTIME", 0),
+            ("VWERASE", 0x17),
+        ],
+    },
+    Known {
+        platform: "freebsd",
+        speed: 9600,
+        iflag: &["BRKINT", "ICRNL", "IXON", "IXANY", "IMAXBEL", "IUTF8"],
+        oflag: &["OPOST", "ONLCR"],
+        cflag: &["CSIZE", "CREAD", "HUPCL"],
+        lflag: &[
+            "ISIG", "ICANON", "ECHO", "ECHOE", "ECHOCTL", "ECHOKE", "IEXTEN",
+        ],
+        cc: &[
+            ("VDISCARD", 0x0f),
+            ("VDSUSP", 0x19),
+            ("VEOF", 0x04),
+            ("VEOL", 0xff),
+            ("VEOL2", 0xff),
+            ("VERASE", 0x7f),
+            ("VERASE2", 0x08),
+            ("VINTR", 0x03),
+            ("VKILL", 0x15),
+            ("VLNEXT", 0x16),
+            ("VMIN", 1),
+            ("VQUIT", 0x1c),
+            ("VREPRINT", 0x12),
+            ("VSTART", 0x11),
+            ("VSTATUS", 0x14),
+            ("VSTOP", 0x13),
+            ("VSUSP", 0x1a),
+            ("VTIME", 0),
+            ("VWERASE", 0x17),
+        ],
+    },
+];
+
+impl From<&Known> for Defaults {
+    fn from(known: &Known) -> Self {
+        let names = |list: &[&str]| list.iter().map(|name| name.to_string()).collect();
+        Self {
+            platform: known.platform.to_string(),
+            rows: 0,
+            cols: 0,
+            speed: Some(known.speed),
+            termios: flags::Snapshot {
+                iflag: names(known.iflag),
+                oflag: names(known.oflag),
+                cflag: names(known.cflag),
+                lflag: names(known.lflag),
+                cc: known
+                    .cc
+                    .iter()
+                    .map(|&(name, value)| (name.to_string(), value))
+                    .collect(),
+            },
+        }
+    }
+}
+
+pub fn run(compare: bool) -> Result<(), Box<dyn std::error::Error>> {
+    // No size and no termios of our own, to see what the kernel hands out.
+    let pty = nix::pty::openpty(None, None)?;
+    let term = Termios::from_fd(pty.master.as_raw_fd())?;
+    let mut winsize: Winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(pty.master.as_raw_fd(), libc::TIOCGWINSZ, &mut winsize) } == -1 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let live = live(&term, &winsize);
+
+    if compare {
+        print_comparison(&live);
+    } else {
+        println!("{}", serde_json::to_string_pretty(&live)?);
+    }
+    Ok(())
+}
+
+fn live(term: &Termios, winsize: &Winsize) -> Defaults {
+    Defaults {
+        platform: std::env::consts::OS.to_string(),
+        rows: winsize.ws_row,
+        cols: winsize.ws_col,
+        speed: baud(termios::cfgetospeed(term)),
+        termios: flags::Snapshot::of(term),
+    }
+}
+
+fn baud(speed: termios::speed_t) -> Option<u32> {
+    use termios::*;
+    [
+        (B0, 0),
+        (B300, 300),
+        (B1200, 1200),
+        (B2400, 2400),
+        (B4800, 4800),
+        (B9600, 9600),
+        (B19200, 19200),
+        (B38400, 38400),
+    ]
+    .into_iter()
+    .find_map(|(code, baud)| (code == speed).then_some(baud))
+}
+
+// Only the rows where the systems disagree, with this one's live pty in front.
+fn print_comparison(live: &Defaults) {
+    let known: Vec<Defaults> = KNOWN.iter().map(Defaults::from).collect();
+    let columns: Vec<&Defaults> = std::iter::once(live).chain(&known).collect();
+
+    print!("{:<16}{:<10}", "", "live");
+    for known in KNOWN {
+        print!("{:<10}", known.platform);
+    }
+    println!();
+
+    let show = |label: String, cells: Vec<String>| {
+        if cells.iter().any(|cell| *cell != cells[0]) {
+            print!("{label:<16}");
+            for cell in cells {
+                print!("{cell:<10}");
+            }
+            println!();
+        }
+    };
+
+    let speed = |defaults: &Defaults| match defaults.speed {
+        Some(speed) => speed.to_string(),
+        None => "?".to_string(),
+    };
+    show(
+        "speed".to_string(),
+        columns.iter().map(|d| speed(d)).collect(),
+    );
+
+    type Field = fn(&Defaults) -> &Vec<String>;
+    let fields: [(&str, Field); 4] = [
+        ("iflag", |d| &d.termios.iflag),
+        ("oflag", |d| &d.termios.oflag),
+        ("cflag", |d| &d.termios.cflag),
+        ("lflag", |d| &d.termios.lflag),
+    ];
+    for (field, get) in fields {
+        let names: BTreeSet<&String> = columns.iter().flat_map(|d| get(d)).collect();
+        for name in names {
+            let cells = columns
+                .iter()
+                .map(|d| if get(d).contains(name) { "+" } else { "-" }.to_string())
+                .collect();
+            show(format!("{field} {name}"), cells);
+        }
+    }
+
+    let names: BTreeSet<&String> = columns.iter().flat_map(|d| d.termios.cc.keys()).collect();
+    for name in names {
+        let cells = columns
+            .iter()
+            .map(|d| match d.termios.cc.get(name) {
+                Some(value) => format!("{value:#04x}"),
+                None => "n/a".to_string(),
+            })
+            .collect();
+        show(format!("cc {name}"), cells);
+    }
+}
@@ -0,0 +1,251 @@
+use crate::child::Rlimit;
+
+use nix::pty::openpty;
+use nix::unistd::Group;
+
+use std::os::fd::AsRawFd as _;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Level {
+    Ok,
+    Warn,
+    Fail,
+}
+
+struct Report {
+    failed: bool,
+}
+
+impl Report {
+    fn finding(&mut self, level: Level, text: impl AsRef<str>, hint: &str) {
+        let label = match level {
+            Level::Ok => "OK  ",
+            Level::Warn => "WARN",
+            Level::Fail => "FAIL",
+        };
+        println!("{label} {}", text.as_ref());
+        if level != Level::Ok && !hint.is_empty() {
+            println!("     -> {hint}");
+        }
+        self.failed |= level == Level::Fail;
+    }
+}
+
+pub fn run() -> ExitCode {
+    let mut report = Report { failed: false };
+    #[cfg(target_os = "linux")]
+    {
+        devpts(&mut report);
+        pty_count(&mut report);
+    }
+    open(&mut report);
+    limits(&mut report);
+    #[cfg(target_os = "linux")]
+    tiocsti(&mut report);
+    terminfo(&mut report);
+
+    if report.failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+fn devpts(report: &mut Report) {
+    let mounts = std::fs::read_to_string("/proc/self/mounts").unwrap_or_default();
+    let Some(options) = mounts.lines().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        let (_, target, fstype, options) = (
+            fields.next()?,
+            fields.next()?,
+            fields.next()?,
+            fields.next()?,
+        );
+        (target == "/dev/pts" && fstype == "devpts").then(|| options.to_string())
+    }) else {
+        report.finding(
+            Level::Fail,
+            "devpts is not mounted on /dev/pts",
+            "mount -t devpts devpts /dev/pts -o gid=5,mode=620,ptmxmode=666",
+        );
+        return;
+    };
+    report.finding(Level::Ok, format!("devpts on /dev/pts ({options})"), "");
+
+    let option = |name: &str| {
+        options
+            .split(',')
+            .find_map(|option| option.strip_prefix(name)?.strip_prefix('='))
+    };
+
+    let tty = Group::from_name("tty").ok().flatten();
+    match (option("gid"), &tty) {
+        (None, _) => report.finding(
+            Level::Warn,
+            "devpts has no gid= option, new slaves belong to the opener's group",
+            "write(1) and wall(1) cannot reach them; mount with gid=<tty group>",
+        ),
+        (Some(gid), Some(tty)) if gid != tty.gid.to_string() => report.finding(
+            Level::Warn,
+            format!("devpts gid={gid} is not the tty group ({})", tty.gid),
+            "write(1) and wall(1) cannot reach the slaves",
+        ),
+        (Some(gid), _) => report.finding(Level::Ok, format!("devpts gid={gid}"), ""),
+    }
+
+    match option("mode") {
+        Some(mode) if u32::from_str_radix(mode, 8).is_ok_and(|mode| mode & 0o600 != 0o600) => {
+            report.finding(
+                Level::Warn,
+                format!("devpts mode={mode} does not let the owner read and write the slave"),
+                "mount with mode=620",
+            )
+        }
+        Some(mode) => report.finding(Level::Ok, format!("devpts mode={mode}"), ""),
+        None => {}
+    }
+
+    // A private devpts instance (containers) comes with ptmxmode=000; /dev/ptmx must then
+    // point at /dev/pts/ptmx or openpty goes to the host's instance.
+    if option("ptmxmode") == Some("000") {
+        let target = std::fs::read_link("/dev/ptmx").ok();
+        if target.as_deref() != Some(Path::new("pts/ptmx")) {
+            report.finding(
+                Level::Warn,
+                "devpts has ptmxmode=000 and /dev/ptmx is not a link to pts/ptmx",
+                "ln -sf pts/ptmx /dev/ptmx, or mount with ptmxmode=666",
+            );
+        }
+    }
+}
+
+fn pty_count(report: &mut Report) {
+    let read = |name: &str| -> Option<u64> {
+        std::fs::read_to_string(format!("/proc/sys/kernel/pty/{name}"))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    };
+    let (Some(nr), Some(max)) = (read("nr"), read("max")) else {
+        report.finding(Level::Warn, "could not read /proc/sys/kernel/pty", "");
+        return;
+    };
+
+    let text = format!("{nr} of {max} ptys in use (kernel.pty.max)");
+    if nr * 10 >= max * 9 {
+        report.finding(
+            Level::Warn,
+            text,
+            "raise it with sysctl kernel.pty.max, or look for leaked masters with `debug-pty list`",
+        );
+    } else {
+        report.finding(Level::Ok, text, "");
+    }
+}
+
+fn open(report: &mut Report) {
+    match openpty(None, None) {
+        Ok(pty) => {
+            let slave = nix::unistd::ttyname(pty.slave.as_raw_fd())
+                .map(|path| path.display().to_string())
+                .unwrap_or_else(|_| "a slave".to_string());
+            report.finding(Level::Ok, format!("opened a pty with {slave}"), "");
+        }
+        Err(e) => report.finding(
+            Level::Fail,
+            format!("could not open a pty: {e}"),
+            "check the devpts findings above and the permissions of /dev/ptmx",
+        ),
+    }
+}
+
+fn limits(report: &mut Report) {
+    for limit in Rlimit::current() {
+        match limit.name() {
+            // Every session costs a master, a slave and the child's copies.
+            "nofile" if limit.soft() < 256 => report.finding(
+                Level::Warn,
+                format!("ulimit {}", limit.describe()),
+                "few open files; raise it with ulimit -n or --rlimit nofile=N for the child",
+            ),
+            "nproc" if limit.soft() < 64 => report.finding(
+                Level::Warn,
+                format!("ulimit {}", limit.describe()),
+                "fork may fail with EAGAIN; raise it with ulimit -u",
+            ),
+            _ => report.finding(Level::Ok, format!("ulimit {}", limit.describe()), ""),
+        }
+    }
+}
+
+fn tiocsti(report: &mut Report) {
+    match std::fs::read_to_string("/proc/sys/dev/tty/legacy_tiocsti") {
+        Ok(value) if value.trim() == "0" => report.finding(
+            Level::Warn,
+            "TIOCSTI is disabled (dev.tty.legacy_tiocsti = 0)",
+            "--inject and :inject fail with EIO unless run with CAP_SYS_ADMIN; \
+             sysctl dev.tty.legacy_tiocsti=1 turns it back on",
+        ),
+        Ok(_) => report.finding(Level::Ok, "TIOCSTI is enabled", ""),
+        // Kernels before 6.2 have no switch and always allow it.
+        Err(_) => report.finding(
+            Level::Ok,
+            "TIOCSTI is enabled (no legacy_tiocsti sysctl)",
+            "",
+        ),
+    }
+}
+
+fn terminfo(report: &mut Report) {
+    let Ok(term) = std::env::var("TERM") else {
+        report.finding(
+            Level::Warn,
+            "TERM is not set",
+            "the child inherits no TERM; pass -e TERM=xterm-256color",
+        );
+        return;
+    };
+
+    match find_terminfo(&term) {
+        Some(path) => report.finding(Level::Ok, format!("TERM={term} ({})", path.display()), ""),
+        None => report.finding(
+            Level::Warn,
+            format!("TERM={term} has no terminfo entry"),
+            "curses programs will misbehave; install it or pass -e TERM=xterm-256color",
+        ),
+    }
+}
+
+fn find_terminfo(term: &str) -> Option<PathBuf> {
+    let first = term.chars().next()?;
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    if let Some(dir) = std::env::var_os("TERMINFO") {
+        dirs.push(dir.into());
+    }
+    if let Some(home) = std::env::var_os("HOME") {
+        dirs.push(Path::new(&home).join(".terminfo"));
+    }
+    if let Ok(list) = std::env::var("TERMINFO_DIRS") {
+        dirs.extend(
+            list.split(':')
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from),
+        );
+    }
+    dirs.extend(
+        ["/etc/terminfo", "/lib/terminfo", "/usr/share/terminfo"]
+            .iter()
+            .map(PathBuf::from),
+    );
+
+    // ncurses files entries by their first letter, macOS by its hex code.
+    dirs.into_iter().find_map(|dir| {
+        [first.to_string(), format!("{:x}", first as u32)]
+            .iter()
+            .map(|sub| dir.join(sub).join(term))
+            .find(|path| path.exists())
+    })
+}
@@ -0,0 +1,147 @@
+use crate::command::{self, COMMANDS};
+
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Context, Editor, Helper};
+
+use std::io::Error as IoError;
+use std::path::PathBuf;
+
+pub struct LineEditor {
+    editor: Editor<InputHelper, DefaultHistory>,
+    history: Option<PathBuf>,
+}
+
+impl LineEditor {
+    pub fn new() -> Result<Self, IoError> {
+        let mut editor = Editor::new().map_err(into_io)?;
+        editor.set_helper(Some(InputHelper {
+            files: FilenameCompleter::new(),
+        }));
+
+        let history = history_path();
+        if let Some(path) = &history {
+            // A missing history file just means this is the first session.
+            let _ = editor.load_history(path);
+        }
+
+        Ok(Self { editor, history })
+    }
+
+    pub fn read_line(&mut self) -> Result<Option<String>, IoError> {
+        loop {
+            match self.editor.readline("") {
+                Ok(line) => {
+                    if !line.trim().is_empty() {
+                        self.add_history(&line);
+                    }
+                    // Continuation lines become real newlines in the payload.
+                    return Ok(Some(line.replace("\\\n", "\n")));
+                }
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => return Ok(None),
+                Err(e) => return Err(into_io(e)),
+            }
+        }
+    }
+
+    fn add_history(&mut self, line: &str) {
+        let _ = self.editor.add_history_entry(line);
+        if let Some(path) = &self.history {
+            if let Err(e) = self.editor.save_history(path) {
+                tracing::warn!("could not save history to {}: {e}", path.display());
+            }
+        }
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    let dir = match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".local/state"),
+    }
+    .join("debug-pty");
+
+    std::fs::create_dir_all(&dir).ok()?;
+    Some(dir.join("history"))
+}
+
+fn into_io(e: ReadlineError) -> IoError {
+    match e {
+        ReadlineError::Io(e) => e,
+        e => IoError::other(e),
+    }
+}
+
+struct InputHelper {
+    files: FilenameCompleter,
+}
+
+impl Helper for InputHelper {}
+
+impl Completer for InputHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        // Only colon-commands are completed; anything else is payload.
+        let Some(command) = line[..pos].strip_prefix(':') else {
+            return Ok((0, Vec::new()));
+        };
+
+        let Some((name, _)) = command.split_once(char::is_whitespace) else {
+            let names = COMMANDS.iter().map(|cmd| cmd.name);
+            return Ok((1, candidates(names, command)));
+        };
+
+        if command::takes_path(name) {
+            return self.files.complete(line, pos, ctx);
+        }
+
+        let start = line[..pos].rfind(char::is_whitespace).map_or(0, |i| i + 1);
+        let words = command::arguments(name);
+        Ok((start, candidates(words, &line[start..pos])))
+    }
+}
+
+fn candidates<'a>(words: impl IntoIterator<Item = &'a str>, prefix: &str) -> Vec<Pair> {
+    words
+        .into_iter()
+        .filter(|word| word.starts_with(prefix))
+        .map(|word| Pair {
+            display: word.to_string(),
+            replacement: word.to_string(),
+        })
+        .collect()
+}
+
+impl Hinter for InputHelper {
+    type Hint = String;
+}
+
+impl Highlighter for InputHelper {}
+
+impl Validator for InputHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        // An odd number of trailing backslashes continues the line; `\\` is an escaped one.
+        let trailing = ctx
+            .input()
+            .bytes()
+            .rev()
+            .take_while(|&b| b == b'\\')
+            .count();
+        if trailing % 2 == 1 {
+            Ok(ValidationResult::Incomplete)
+        } else {
+            Ok(ValidationResult::Valid(None))
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use dotenvy::Error as DotError;
+use nix::unistd::{Uid, User};
+
+use std::io::ErrorKind as IoErrorKind;
+use std::path::PathBuf;
+
+pub enum Inherit {
+    Nothing,
+    All,
+    Only(Vec<String>),
+}
+
+impl Inherit {
+    fn includes(&self, name: &str) -> bool {
+        match self {
+            Self::Nothing => false,
+            Self::All => true,
+            Self::Only(patterns) => patterns.iter().any(|pattern| glob(pattern, name)),
+        }
+    }
+}
+
+pub fn parse_var(s: &str) -> Option<(String, String)> {
+    let (key, value) = s.split_once('=')?;
+    if key.is_empty() {
+        return None;
+    }
+    Some((key.to_string(), value.to_string()))
+}
+
+// Without explicit files, `.env` in the working directory is read if it exists.
+pub fn load_files(files: Option<&[PathBuf]>) -> Result<Vec<(String, String)>, String> {
+    let Some(files) = files else {
+        return match dotenvy::dotenv_iter() {
+            Ok(env) => env
+                .collect::<Result<_, _>>()
+                .map_err(|e| format!(".env: {e}")),
+            Err(DotError::Io(e)) if e.kind() == IoErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(format!(".env: {e}")),
+        };
+    };
+
+    let mut env = Vec::new();
+    for path in files {
+        let vars = dotenvy::from_path_iter(path)
+            .and_then(|vars| vars.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+        env.extend(vars);
+    }
+    Ok(env)
+}
+
+// What the user runs when nothing says otherwise.
+pub fn default_shell() -> String {
+    if let Some(shell) = std::env::var("SHELL")
+        .ok()
+        .filter(|shell| !shell.is_empty())
+    {
+        tracing::info!(shell, "shell from $SHELL");
+        return shell;
+    }
+    let entry = User::from_uid(Uid::current()).ok().flatten();
+    if let Some(shell) = entry
+        .map(|user| user.shell)
+        .filter(|shell| !shell.as_os_str().is_empty())
+    {
+        let shell = shell.to_string_lossy().into_owned();
+        tracing::info!(shell, "shell from the passwd entry");
+        return shell;
+    }
+    tracing::info!("no shell in $SHELL or the passwd entry, using /bin/bash");
+    "/bin/bash".to_string()
+}
+
+// Later entries win, so SHELL goes after the inherited variables but stays overridable.
+pub fn compose(
+    inherit: &Inherit,
+    shell: &str,
+    files: Vec<(String, String)>,
+    vars: &[(String, String)],
+) -> Vec<(String, String)> {
+    let mut env: Vec<_> = std::env::vars()
+        .filter(|(name, _)| inherit.includes(name))
+        .collect();
+    env.push(("SHELL".to_string(), shell.to_string()));
+    env.extend(files);
+    env.extend(vars.iter().cloned());
+    env
+}
+
+// Names that tend to hold credentials, left out of capture headers unless asked for.
+const SECRETS: &[&str] = &["*TOKEN*", "*SECRET*", "*KEY*", "*PASS*"];
+
+pub fn is_secret(name: &str) -> bool {
+    let name = name.to_ascii_uppercase();
+    SECRETS.iter().any(|pattern| glob(pattern, &name))
+}
+
+fn glob(pattern: &str, name: &str) -> bool {
+    let Some((prefix, rest)) = pattern.split_once('*') else {
+        return pattern == name;
+    };
+    let Some(name) = name.strip_prefix(prefix) else {
+        return false;
+    };
+    (0..=name.len())
+        .filter(|&i| name.is_char_boundary(i))
+        .any(|i| glob(rest, &name[i..]))
+}
@@ -0,0 +1,220 @@
+use crate::{command, rawio, Session, Writer};
+
+use termios::Termios;
+
+use std::io::Write as _;
+use std::os::fd::RawFd;
+
+pub enum Segment {
+    Bytes(Vec<u8>),
+    Repl,
+}
+
+// Like ssh's ~: the escape character followed by `:` opens the prompt, and typed twice it
+// sends one of itself. Anything else after it goes to the program as typed.
+pub struct Escape {
+    key: Option<u8>,
+    pending: bool,
+}
+
+impl Escape {
+    pub fn new(key: Option<u8>) -> Self {
+        Self {
+            key,
+            pending: false,
+        }
+    }
+
+    pub fn split(&mut self, input: &[u8]) -> Vec<Segment> {
+        let Some(key) = self.key else {
+            return vec![Segment::Bytes(input.to_vec())];
+        };
+
+        let mut segments = Vec::new();
+        let mut bytes = Vec::new();
+        for &byte in input {
+            if !std::mem::take(&mut self.pending) {
+                if byte == key {
+                    self.pending = true;
+                } else {
+                    bytes.push(byte);
+                }
+                continue;
+            }
+            match byte {
+                b':' => {
+                    segments.push(Segment::Bytes(std::mem::take(&mut bytes)));
+                    segments.push(Segment::Repl);
+                }
+                byte if byte == key => bytes.push(key),
+                byte => bytes.extend_from_slice(&[key, byte]),
+            }
+        }
+        segments.push(Segment::Bytes(bytes));
+        segments
+    }
+}
+
+// The sequence that leaves an attached session. A partial match is held back until the
+// next byte shows whether it goes on.
+pub struct Detach {
+    keys: Vec<u8>,
+    matched: usize,
+}
+
+impl Detach {
+    pub fn new(keys: Vec<u8>) -> Self {
+        Self { keys, matched: 0 }
+    }
+
+    // Returns the bytes to send on and whether the whole sequence was typed; whatever
+    // followed it in the same read is dropped.
+    pub fn scan(&mut self, input: &[u8]) -> (Vec<u8>, bool) {
+        let mut out = Vec::with_capacity(input.len());
+        for &byte in input {
+            if self.keys.is_empty() {
+                out.push(byte);
+                continue;
+            }
+            if byte != self.keys[self.matched] {
+                // A broken match goes through as typed, and the byte may start a new one.
+                out.extend_from_slice(&self.keys[..std::mem::take(&mut self.matched)]);
+                if byte != self.keys[0] {
+                    out.push(byte);
+                    continue;
+                }
+            }
+            self.matched += 1;
+            if self.matched == self.keys.len() {
+                self.matched = 0;
+                return (out, true);
+            }
+        }
+        (out, false)
+    }
+}
+
+// Runs on the input thread with the terminal back in the user's own mode. Holding stdout
+// holds back the program's output until the prompt is left. The lines are the same
+// commands as in a session, with or without the `:`.
+pub fn repl(master: RawFd, host: &Termios, writer: &mut Writer, session: &Session) {
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    let Ok(raw) = Termios::from_fd(0) else {
+        return;
+    };
+    if termios::tcsetattr(0, termios::TCSANOW, host).is_err() {
+        return;
+    }
+
+    let _ = writeln!(
+        out,
+        "\r\n[debug-pty] type help, or an empty line to go back"
+    );
+    loop {
+        let _ = write!(out, "debug-pty> ");
+        let _ = out.flush();
+        let Some(line) = read_line() else {
+            break;
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        let command = line.strip_prefix(':').unwrap_or(line);
+        match command::run(command, master, writer, session) {
+            Ok(false) => {}
+            Ok(true) => break,
+            Err(e) => println!("{command}: {e}"),
+        }
+    }
+
+    let _ = termios::tcsetattr(0, termios::TCSANOW, &raw);
+}
+
+// Byte by byte, so that nothing typed after the line is taken from the program.
+fn read_line() -> Option<String> {
+    let mut line = Vec::new();
+    let mut byte = [0];
+    loop {
+        match rawio::read(0, &mut byte) {
+            Ok(0) | Err(_) => return None,
+            Ok(_) if byte[0] == b'\n' => return Some(String::from_utf8_lossy(&line).into_owned()),
+            Ok(_) => line.push(byte[0]),
+        }
+    }
+}
+
+pub fn caret(key: u8) -> String {
+    match key {
+        0x00..=0x1f => format!("^{}", (key ^ 0x40) as char),
+        0x7f => "^?".to_string(),
+        key => (key as char).to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The bytes of each segment, with the prompt as None.
+    fn split(escape: &mut Escape, input: &[u8]) -> Vec<Option<Vec<u8>>> {
+        escape
+            .split(input)
+            .into_iter()
+            .map(|segment| match segment {
+                Segment::Bytes(bytes) => Some(bytes),
+                Segment::Repl => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn escape_prompt() {
+        let mut escape = Escape::new(Some(0x1d));
+        assert_eq!(
+            split(&mut escape, b"ls\x1d:x"),
+            [Some(b"ls".to_vec()), None, Some(b"x".to_vec())]
+        );
+        assert_eq!(
+            split(&mut escape, b"\x1d\x1d\x1da"),
+            [Some(b"\x1d\x1da".to_vec())]
+        );
+        assert_eq!(
+            split(&mut Escape::new(None), b"\x1d:"),
+            [Some(b"\x1d:".to_vec())]
+        );
+    }
+
+    #[test]
+    fn escape_across_reads() {
+        let mut escape = Escape::new(Some(b'~'));
+        assert_eq!(split(&mut escape, b"ab~"), [Some(b"ab".to_vec())]);
+        assert_eq!(
+            split(&mut escape, b":"),
+            [Some(Vec::new()), None, Some(Vec::new())]
+        );
+        assert_eq!(split(&mut escape, b"~"), [Some(Vec::new())]);
+        assert_eq!(split(&mut escape, b"."), [Some(b"~.".to_vec())]);
+    }
+
+    #[test]
+    fn detach() {
+        let mut detach = Detach::new(b"\x1dd".to_vec());
+        assert_eq!(detach.scan(b"ls\x1dx"), (b"ls\x1dx".to_vec(), false));
+        assert_eq!(detach.scan(b"a\x1d\x1ddrop"), (b"a\x1d".to_vec(), true));
+        let mut none = Detach::new(Vec::new());
+        assert_eq!(none.scan(b"\x1dd"), (b"\x1dd".to_vec(), false));
+    }
+
+    #[test]
+    fn detach_across_reads() {
+        let mut detach = Detach::new(b"^]d".to_vec());
+        assert_eq!(detach.scan(b"x^"), (b"x".to_vec(), false));
+        assert_eq!(detach.scan(b"]"), (Vec::new(), false));
+        assert_eq!(detach.scan(b"e"), (b"^]e".to_vec(), false));
+        assert_eq!(detach.scan(b"^]"), (Vec::new(), false));
+        assert_eq!(detach.scan(b"d"), (Vec::new(), true));
+        assert_eq!(detach.scan(b"d"), (b"d".to_vec(), false));
+    }
+}
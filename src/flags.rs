@@ -0,0 +1,142 @@
+pub use crate::platform::{CC, CFLAGS, IFLAGS, LFLAGS, OFLAGS, VDISABLE};
+
+use serde::{Deserialize, Serialize};
+
+use termios::{tcflag_t, Termios};
+
+use std::collections::BTreeMap;
+
+pub fn cc_index(name: &str) -> Option<usize> {
+    let name = name.to_ascii_uppercase();
+    let name = name.strip_prefix('V').unwrap_or(&name);
+    CC.iter()
+        .find(|(_, cc)| cc[1..] == *name)
+        .map(|&(index, _)| index)
+}
+
+pub fn apply(term: &mut Termios, spec: &str) -> Result<(), String> {
+    if spec == "raw" {
+        termios::cfmakeraw(term);
+        return Ok(());
+    }
+
+    let (set, name) = match spec.strip_prefix('-') {
+        Some(name) => (false, name),
+        None => (true, spec.strip_prefix('+').unwrap_or(spec)),
+    };
+    let name = name.to_ascii_uppercase();
+
+    let t = &mut **term;
+    let tables = [
+        (&mut t.c_iflag, IFLAGS),
+        (&mut t.c_oflag, OFLAGS),
+        (&mut t.c_cflag, CFLAGS),
+        (&mut t.c_lflag, LFLAGS),
+    ];
+    for (flags, table) in tables {
+        if let Some(&(flag, _)) = table.iter().find(|(_, flag)| *flag == name) {
+            if set {
+                *flags |= flag;
+            } else {
+                *flags &= !flag;
+            }
+            return Ok(());
+        }
+    }
+
+    Err(format!("unknown termios flag {spec:?}"))
+}
+
+pub fn diff(old: &Termios, new: &Termios) -> Vec<String> {
+    let tables = [
+        (old.c_iflag, new.c_iflag, IFLAGS),
+        (old.c_oflag, new.c_oflag, OFLAGS),
+        (old.c_cflag, new.c_cflag, CFLAGS),
+        (old.c_lflag, new.c_lflag, LFLAGS),
+    ];
+    let mut changes = Vec::new();
+    for (old, new, table) in tables {
+        for &(flag, name) in table {
+            match (old & flag != 0, new & flag != 0) {
+                (false, true) => changes.push(format!("+{name}")),
+                (true, false) => changes.push(format!("-{name}")),
+                _ => {}
+            }
+        }
+    }
+    for &(index, name) in CC {
+        if old.c_cc[index] != new.c_cc[index] {
+            changes.push(format!("{name}={:#04x}", new.c_cc[index]));
+        }
+    }
+    changes
+}
+
+// The termios by name, which carries over to another system as far as the names do.
+#[derive(Serialize, Deserialize)]
+pub struct Snapshot {
+    pub iflag: Vec<String>,
+    pub oflag: Vec<String>,
+    pub cflag: Vec<String>,
+    pub lflag: Vec<String>,
+    pub cc: BTreeMap<String, u8>,
+}
+
+impl Snapshot {
+    pub fn of(term: &Termios) -> Self {
+        let set = |flags: tcflag_t, table: &[(tcflag_t, &str)]| {
+            table
+                .iter()
+                .filter(|&&(flag, _)| flags & flag != 0)
+                .map(|&(_, name)| name.to_string())
+                .collect()
+        };
+        Self {
+            iflag: set(term.c_iflag, IFLAGS),
+            oflag: set(term.c_oflag, OFLAGS),
+            cflag: set(term.c_cflag, CFLAGS),
+            lflag: set(term.c_lflag, LFLAGS),
+            cc: CC
+                .iter()
+                .map(|&(index, name)| (name.to_string(), term.c_cc[index]))
+                .collect(),
+        }
+    }
+
+    // Sets every flag this system knows as it was, and returns what it does not know. The
+    // fields that take more than one bit, like CSIZE or CBAUD, are left as they are.
+    pub fn restore(&self, term: &mut Termios) -> Vec<String> {
+        let t = &mut **term;
+        let tables = [
+            ("iflag", &mut t.c_iflag, IFLAGS, &self.iflag),
+            ("oflag", &mut t.c_oflag, OFLAGS, &self.oflag),
+            ("cflag", &mut t.c_cflag, CFLAGS, &self.cflag),
+            ("lflag", &mut t.c_lflag, LFLAGS, &self.lflag),
+        ];
+        let mut missing = Vec::new();
+        for (field, flags, table, names) in tables {
+            for &(flag, name) in table {
+                if flag.count_ones() != 1 {
+                    continue;
+                }
+                if names.iter().any(|set| set == name) {
+                    *flags |= flag;
+                } else {
+                    *flags &= !flag;
+                }
+            }
+            for name in names {
+                if !table.iter().any(|(_, known)| known == name) {
+                    missing.push(format!("{field} {name}"));
+                }
+            }
+        }
+        for (name, &value) in &self.cc {
+            match CC.iter().find(|(_, known)| known == name) {
+                Some(&(index, _)) => term.c_cc[index] = value,
+                None => missing.push(format!("cc {name}")),
+            }
+        }
+        missing
+    }
+}
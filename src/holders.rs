@@ -0,0 +1,146 @@
+use crate::platform;
+use crate::procfs;
+
+use nix::pty::Winsize;
+
+use std::fs::OpenOptions;
+use std::io::Error as IoError;
+use std::os::fd::AsRawFd as _;
+use std::os::unix::fs::{MetadataExt as _, OpenOptionsExt as _};
+use std::path::Path;
+
+pub struct Holder {
+    pub index: u32,
+    pub pid: u32,
+    pub comm: String,
+    pub fd: u32,
+    pub master: bool,
+    pub flags: i32,
+}
+
+pub fn tty_index(slave: &Path) -> Option<u32> {
+    platform::slave_index(slave)
+}
+
+// Every open end of every pty, from one pass over /proc.
+pub fn scan() -> Vec<Holder> {
+    let mut holders = Vec::new();
+
+    for pid in procfs::pids() {
+        for file in procfs::open_files(pid) {
+            let (index, master) = match file.tty_index {
+                Some(index) => (index, true),
+                None => match tty_index(&file.target) {
+                    Some(index) => (index, false),
+                    None => continue,
+                },
+            };
+            holders.push(Holder {
+                index,
+                pid,
+                comm: procfs::parse(pid).map(|stat| stat.comm).unwrap_or_default(),
+                fd: file.fd,
+                master,
+                flags: file.flags,
+            });
+        }
+    }
+
+    holders
+}
+
+pub fn print(index: u32) {
+    let holders: Vec<_> = scan().into_iter().filter(|h| h.index == index).collect();
+    if holders.is_empty() {
+        println!(
+            "WHO nothing has {} open (or /proc is not readable)",
+            platform::slave_path(index)
+        );
+    }
+    for holder in &holders {
+        print_holder("WHO", holder);
+    }
+    println!();
+}
+
+pub fn list() -> Result<(), IoError> {
+    let holders = scan();
+
+    for index in platform::slaves()? {
+        let path = platform::slave_path(index);
+        let size = match winsize(&path) {
+            Ok(ws) => format!("{}x{}", ws.ws_row, ws.ws_col),
+            Err(e) => format!("size unavailable ({e})"),
+        };
+        let holders: Vec<_> = holders.iter().filter(|h| h.index == index).collect();
+        let foreground = match foreground(&path, &holders) {
+            Some(pgrp) => pgrp.to_string(),
+            None => "unknown".to_string(),
+        };
+
+        println!("PTY {path} {size} fg {foreground}");
+        for holder in holders {
+            print_holder("   ", holder);
+        }
+        println!();
+    }
+
+    Ok(())
+}
+
+fn print_holder(prefix: &str, holder: &Holder) {
+    println!(
+        "{prefix} {:>7} {:<16} fd {:<3} {} {}",
+        holder.pid,
+        format!("({})", holder.comm),
+        holder.fd,
+        if holder.master { "master" } else { "slave " },
+        describe_flags(holder.flags),
+    );
+}
+
+fn winsize(path: &str) -> Result<Winsize, IoError> {
+    let file = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NOCTTY | libc::O_NONBLOCK)
+        .open(path)?;
+    let mut winsize: Winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(file.as_raw_fd(), libc::TIOCGWINSZ, &mut winsize) } == -1 {
+        return Err(IoError::last_os_error());
+    }
+    Ok(winsize)
+}
+
+// tcgetpgrp only works on our own controlling terminal, so this goes by the tpgid of a
+// process whose controlling terminal is the pty.
+fn foreground(path: &str, holders: &[&Holder]) -> Option<i32> {
+    let rdev = std::fs::metadata(path).ok()?.rdev();
+    let (major, minor) = (libc::major(rdev) as i32, libc::minor(rdev) as i32);
+    let tty_nr = (minor & 0xff) | (major << 8) | ((minor & !0xff) << 12);
+
+    holders
+        .iter()
+        .filter_map(|holder| procfs::parse(holder.pid))
+        .find(|stat| stat.tty_nr == tty_nr && stat.tpgid > 0)
+        .map(|stat| stat.tpgid)
+}
+
+pub fn describe_flags(flags: i32) -> String {
+    let mut names = vec![match flags & libc::O_ACCMODE {
+        libc::O_RDONLY => "O_RDONLY",
+        libc::O_WRONLY => "O_WRONLY",
+        _ => "O_RDWR",
+    }];
+    for (flag, name) in [
+        (libc::O_NONBLOCK, "O_NONBLOCK"),
+        (libc::O_APPEND, "O_APPEND"),
+        (libc::O_CLOEXEC, "O_CLOEXEC"),
+        (libc::O_ASYNC, "O_ASYNC"),
+        (libc::O_SYNC, "O_SYNC"),
+    ] {
+        if flags & flag == flag {
+            names.push(name);
+        }
+    }
+    names.join("|")
+}
@@ -0,0 +1,123 @@
+use regex::bytes::Regex;
+use serde::Deserialize;
+
+use std::io::Write as _;
+use std::process::{Command, Stdio};
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HookConfig {
+    spawn: Option<String>,
+    exit: Option<String>,
+    termios: Option<String>,
+    #[serde(rename = "match")]
+    matches: Vec<MatchHook>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MatchHook {
+    pattern: String,
+    run: String,
+}
+
+// Commands run with `sh -c`, get DEBUG_PTY_EVENT and the event's details in the
+// environment and, where there is a payload, the bytes on stdin. Nobody waits for them.
+#[derive(Default)]
+pub struct Hooks {
+    spawn: Option<String>,
+    exit: Option<String>,
+    termios: Option<String>,
+    matches: Vec<(Regex, String)>,
+}
+
+impl Hooks {
+    pub fn new(config: HookConfig) -> Result<Self, String> {
+        let matches = config
+            .matches
+            .into_iter()
+            .map(|hook| match Regex::new(&hook.pattern) {
+                Ok(regex) => Ok((regex, hook.run)),
+                Err(e) => Err(format!("hook pattern {:?}: {e}", hook.pattern)),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            spawn: config.spawn,
+            exit: config.exit,
+            termios: config.termios,
+            matches,
+        })
+    }
+
+    pub fn watches_termios(&self) -> bool {
+        self.termios.is_some()
+    }
+
+    pub fn spawned(&self, pid: u32) {
+        if let Some(command) = &self.spawn {
+            fire("spawn", command, &[("DEBUG_PTY_PID", pid.to_string())], &[]);
+        }
+    }
+
+    pub fn exited(&self, status: &str, code: Option<i32>) {
+        if let Some(command) = &self.exit {
+            let code = code.map(|code| code.to_string()).unwrap_or_default();
+            let vars = [
+                ("DEBUG_PTY_STATUS", status.to_string()),
+                ("DEBUG_PTY_CODE", code),
+            ];
+            fire("exit", command, &vars, &[]);
+        }
+    }
+
+    pub fn termios_changed(&self, changes: &str) {
+        if let Some(command) = &self.termios {
+            fire(
+                "termios",
+                command,
+                &[("DEBUG_PTY_CHANGES", changes.to_string())],
+                &[],
+            );
+        }
+    }
+
+    // Matches against each read on its own, so text split across reads is missed.
+    pub fn output(&self, buf: &[u8]) {
+        for (regex, command) in &self.matches {
+            if let Some(found) = regex.find(buf) {
+                let text = String::from_utf8_lossy(found.as_bytes()).into_owned();
+                let vars = [
+                    ("DEBUG_PTY_PATTERN", regex.as_str().to_string()),
+                    ("DEBUG_PTY_MATCH", text),
+                ];
+                fire("match", command, &vars, buf);
+            }
+        }
+    }
+}
+
+fn fire(event: &str, command: &str, vars: &[(&str, String)], stdin: &[u8]) {
+    println!("HOOK {event}: {command}");
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("DEBUG_PTY_EVENT", event)
+        .envs(vars.iter().map(|(key, value)| (key, value)))
+        .stdin(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            tracing::warn!(event, "hook could not run: {e}");
+            return;
+        }
+    };
+
+    let stdin_data = stdin.to_vec();
+    std::thread::spawn(move || {
+        if let Some(mut pipe) = child.stdin.take() {
+            let _ = pipe.write_all(&stdin_data);
+        }
+        let _ = child.wait();
+    });
+}
@@ -0,0 +1,98 @@
+use crate::flags;
+
+use nix::sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal};
+
+use termios::Termios;
+
+use std::os::fd::RawFd;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Once;
+
+// What the panic hook and the signal handlers put back. A signal handler cannot take a
+// lock, so the settings sit in a plain static, published by storing the fd last.
+static FD: AtomicI32 = AtomicI32::new(-1);
+static mut SAVED: Option<Termios> = None;
+
+const FATAL: &[Signal] = &[
+    Signal::SIGHUP,
+    Signal::SIGINT,
+    Signal::SIGQUIT,
+    Signal::SIGTERM,
+    Signal::SIGABRT,
+    Signal::SIGSEGV,
+    Signal::SIGBUS,
+];
+
+// Changes the settings of the user's terminal and puts them back on drop, on a panic and on
+// a fatal signal, so that a crash does not leave it raw.
+pub struct Guard {
+    fd: RawFd,
+    saved: Termios,
+}
+
+impl Guard {
+    pub fn enter(fd: RawFd, term: &Termios) -> Result<Self, std::io::Error> {
+        let saved = Termios::from_fd(fd)?;
+        install();
+        unsafe { SAVED = Some(saved) };
+        FD.store(fd, Ordering::SeqCst);
+        termios::tcsetattr(fd, termios::TCSANOW, term)?;
+        Ok(Self { fd, saved })
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        FD.store(-1, Ordering::SeqCst);
+        let _ = termios::tcsetattr(self.fd, termios::TCSADRAIN, &self.saved);
+
+        match Termios::from_fd(self.fd) {
+            Ok(term) => {
+                let changes = flags::diff(&self.saved, &term);
+                if !changes.is_empty() {
+                    tracing::warn!("the terminal was not fully restored: {}", changes.join(" "));
+                }
+            }
+            Err(e) => tracing::warn!("could not check that the terminal was restored: {e}"),
+        }
+    }
+}
+
+fn install() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            restore();
+            previous(info);
+        }));
+
+        let action = SigAction::new(
+            SigHandler::Handler(on_fatal),
+            SaFlags::SA_RESETHAND,
+            SigSet::empty(),
+        );
+        for &sig in FATAL {
+            if let Err(e) = unsafe { signal::sigaction(sig, &action) } {
+                tracing::warn!("could not handle {sig}: {e}");
+            }
+        }
+    });
+}
+
+fn restore() {
+    let fd = FD.swap(-1, Ordering::SeqCst);
+    if fd == -1 {
+        return;
+    }
+    if let Some(saved) = unsafe { SAVED } {
+        let _ = termios::tcsetattr(fd, termios::TCSANOW, &saved);
+    }
+}
+
+// SA_RESETHAND has put the default action back, so raising the signal again ends the
+// process the way it would have without us.
+extern "C" fn on_fatal(sig: libc::c_int) {
+    restore();
+    unsafe { libc::raise(sig) };
+}
@@ -0,0 +1,152 @@
+use crate::capture::Recorder;
+use crate::cli::IngestArgs;
+use crate::middleware::Pipeline;
+use crate::{modes, print_text, title, utf8};
+
+use std::path::Path;
+
+// Output read at a time, if the log says when.
+type Chunk = (Option<f64>, Vec<u8>);
+
+// Runs a log written outside debug-pty, like `tmux pipe-pane -o 'cat >> FILE'` or screen's
+// logfile, through what a session does with its reads. The output is taken a line at a
+// time, timed by a scriptreplay timing file or by screen's time-stamp lines when there are
+// any.
+pub fn run(args: &IngestArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let log = std::fs::read(&args.file).map_err(|e| format!("{}: {e}", args.file.display()))?;
+    let chunks = match &args.timing {
+        Some(timing) => timed(&log, timing)?,
+        None => stamped(&log),
+    };
+
+    let mut pipeline = Pipeline::build(&args.filters)?;
+    let mut recorder = match &args.output {
+        Some(path) => Some(Recorder::create(
+            path,
+            args.format(),
+            &format!("ingested {}", args.file.display()),
+            None,
+            None,
+            None,
+        )?),
+        None => None,
+    };
+    let mut decoder = utf8::Decoder::new();
+    let mut modes = modes::Tracker::new();
+    let mut titles = title::Titles::new();
+    for (t, data) in &chunks {
+        let buf = pipeline.output(data);
+        if let Some(recorder) = &mut recorder {
+            if let Some(t) = t {
+                recorder.set_time(*t);
+            }
+            recorder.output(&buf);
+        }
+        match t {
+            Some(t) => print_text(&format!("READ at {t:.3}s"), &decoder.decode(&buf), &buf),
+            None => print_text("READ", &decoder.decode(&buf), &buf),
+        }
+        modes.feed(&buf);
+        let (_, set) = titles.scan(&buf, false);
+        for title in set {
+            let text = format!("TITLE {title:?}");
+            println!("{text}");
+            if let Some(recorder) = &mut recorder {
+                recorder.note(text);
+            }
+        }
+        println!();
+    }
+
+    println!("INGESTED {} bytes in {} chunks", log.len(), chunks.len());
+    for leftover in modes.leftover() {
+        println!("DIRTY {} left by the program", leftover.name);
+    }
+    for text in modes.unbalanced() {
+        println!("UNBALANCED {text}");
+    }
+    if let Some(recorder) = &mut recorder {
+        recorder.finish();
+    }
+    Ok(())
+}
+
+// Each line of the timing file is the delay before a chunk and its length in bytes.
+fn timed(log: &[u8], path: &Path) -> Result<Vec<Chunk>, String> {
+    let timing = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let mut chunks = Vec::new();
+    let mut t = 0.0;
+    let mut at = 0;
+    for (num, line) in timing.lines().enumerate() {
+        let entry = line.split_once(' ').and_then(|(delay, len)| {
+            Some((delay.parse::<f64>().ok()?, len.parse::<usize>().ok()?))
+        });
+        let Some((delay, len)) = entry else {
+            return Err(format!(
+                "{}:{}: expected DELAY BYTES",
+                path.display(),
+                num + 1
+            ));
+        };
+        t += delay;
+        let end = (at + len).min(log.len());
+        chunks.push((Some(t), log[at..end].to_vec()));
+        at = end;
+    }
+    if at < log.len() {
+        chunks.push((Some(t), log[at..].to_vec()));
+    }
+    Ok(chunks)
+}
+
+// screen's logtstamp lines, `-- 0:bash -- time-stamp -- Oct/16/26 12:00:00 --`, give the
+// time of the lines after them and are left out.
+fn stamped(log: &[u8]) -> Vec<Chunk> {
+    let mut chunks = Vec::new();
+    let mut first = None;
+    let mut t = None;
+    for line in log.split_inclusive(|&byte| byte == b'\n') {
+        if let Some(stamp) = time_stamp(line) {
+            let first = *first.get_or_insert(stamp);
+            t = Some((stamp - first) as f64);
+            continue;
+        }
+        chunks.push((t, line.to_vec()));
+    }
+    chunks
+}
+
+fn time_stamp(line: &[u8]) -> Option<i64> {
+    let line = std::str::from_utf8(line).ok()?.trim_end();
+    let (_, stamp) = line.strip_prefix("-- ")?.split_once(" -- time-stamp -- ")?;
+    let (date, time) = stamp.strip_suffix(" --")?.split_once(' ')?;
+
+    let mut date = date.split('/');
+    let month = date.next()?;
+    let month = match month.parse::<i64>() {
+        Ok(month) => month,
+        Err(_) => {
+            const MONTHS: [&str; 12] = [
+                "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+            ];
+            MONTHS.iter().position(|name| *name == month)? as i64 + 1
+        }
+    };
+    let day: i64 = date.next()?.parse().ok()?;
+    let year: i64 = date.next()?.parse().ok()?;
+    let year = if year < 100 { 2000 + year } else { year };
+
+    let mut time = time.split(':').map(|part| part.parse::<i64>().ok());
+    let (hours, minutes, seconds) = (time.next()??, time.next()??, time.next()??);
+    Some(days(year, month, day) * 86400 + hours * 3600 + minutes * 60 + seconds)
+}
+
+// Days since the epoch of a date in the proleptic Gregorian calendar.
+fn days(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
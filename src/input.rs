@@ -0,0 +1,428 @@
+use std::fmt;
+use std::time::Duration;
+
+#[derive(Clone, Copy)]
+pub enum LineEnding {
+    Lf,
+    Cr,
+    CrLf,
+    None,
+}
+
+impl LineEnding {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "lf" => Some(Self::Lf),
+            "cr" => Some(Self::Cr),
+            "crlf" => Some(Self::CrLf),
+            "none" => Some(Self::None),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(self) -> &'static [u8] {
+        match self {
+            Self::Lf => b"\n",
+            Self::Cr => b"\r",
+            Self::CrLf => b"\r\n",
+            Self::None => b"",
+        }
+    }
+}
+
+// Caret notation only counts as a token of its own, so that `grep ^Foo` survives.
+pub fn unescape(buf: &str) -> Vec<u8> {
+    unescape_carets(buf, false)
+}
+
+// Key sequences like `^]d`, where caret notation counts anywhere.
+pub fn unescape_keys(buf: &str) -> Vec<u8> {
+    unescape_carets(buf, true)
+}
+
+fn unescape_carets(buf: &str, anywhere: bool) -> Vec<u8> {
+    let mut cmd = Vec::with_capacity(buf.len());
+    let mut chars = buf.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '^' {
+            let at = buf.len() - chars.as_str().len() - 1;
+            let mut ahead = chars.clone();
+            let byte = match ahead.next() {
+                Some(n @ '@'..='_') => Some(n as u8 ^ 0x40),
+                Some('?') => Some(0x7f),
+                _ => None,
+            };
+            let alone = buf[..at]
+                .chars()
+                .next_back()
+                .is_none_or(char::is_whitespace)
+                && ahead.next().is_none_or(char::is_whitespace);
+            match byte {
+                Some(byte) if anywhere || alone => {
+                    cmd.push(byte);
+                    chars.next();
+                }
+                _ => cmd.push(b'^'),
+            }
+            continue;
+        }
+
+        if c != '\\' {
+            let mut utf8 = [0; 4];
+            cmd.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+            continue;
+        }
+
+        let rest = chars.as_str();
+        match chars.next() {
+            Some('e') => cmd.push(0x1b),
+            Some('n') => cmd.push(b'\n'),
+            Some('r') => cmd.push(b'\r'),
+            Some('t') => cmd.push(b'\t'),
+            Some('0') => cmd.push(0),
+            Some('\\') => cmd.push(b'\\'),
+            Some('"') => cmd.push(b'"'),
+            Some('^') => cmd.push(b'^'),
+            Some('x') => match rest.get(1..3).and_then(parse_hex) {
+                Some(byte) => {
+                    cmd.push(byte);
+                    chars.nth(1);
+                }
+                None => cmd.extend_from_slice(b"\\x"),
+            },
+            Some(c) => {
+                cmd.push(b'\\');
+                let mut utf8 = [0; 4];
+                cmd.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+            }
+            None => cmd.push(b'\\'),
+        }
+    }
+
+    cmd
+}
+
+fn parse_hex(hex: &str) -> Option<u8> {
+    if hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        u8::from_str_radix(hex, 16).ok()
+    } else {
+        None
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+    token: String,
+    reason: &'static str,
+}
+
+impl ParseError {
+    fn new(token: &str, reason: &'static str) -> Self {
+        Self {
+            token: token.to_string(),
+            reason,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid token `{}`: {}", self.token, self.reason)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+pub fn parse_bytes(buf: &str) -> Result<Vec<u8>, ParseError> {
+    let mut cmd = Vec::new();
+    let mut rest = buf.trim_end();
+
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() || rest.starts_with('#') {
+            break;
+        }
+
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let Some(end) = closing_quote(quoted) else {
+                return Err(ParseError::new(rest, "unterminated string"));
+            };
+            cmd.extend(unescape(&quoted[..end]));
+            rest = &quoted[end + 1..];
+        } else {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let (token, tail) = rest.split_at(end);
+            cmd.push(parse_byte(token)?);
+            rest = tail;
+        }
+    }
+
+    Ok(cmd)
+}
+
+pub fn split_token(s: &str) -> (&str, &str) {
+    let s = s.trim_start();
+    let end = match s.strip_prefix('"').and_then(closing_quote) {
+        Some(end) => end + 2,
+        None => s.find(char::is_whitespace).unwrap_or(s.len()),
+    };
+    (&s[..end], s[end..].trim_start())
+}
+
+pub fn unquote(token: &str) -> &str {
+    token
+        .strip_prefix('"')
+        .and_then(|token| token.strip_suffix('"'))
+        .unwrap_or(token)
+}
+
+pub fn split_outside_quotes(s: &str, sep: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => quoted = !quoted,
+            c if c == sep && !quoted => {
+                parts.push(&s[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&s[start..]);
+
+    parts
+}
+
+fn closing_quote(quoted: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in quoted.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn parse_byte(token: &str) -> Result<u8, ParseError> {
+    // Bare tokens are hex, except that a leading 0b, 0o or 0d is always a prefix; the hex
+    // bytes that look like one are written 0x0b and 0x0d.
+    let (digits, radix) = match token.get(..2) {
+        Some("0x" | "0X") => (&token[2..], 16),
+        Some("0b" | "0B") => (&token[2..], 2),
+        Some("0o" | "0O") => (&token[2..], 8),
+        Some("0d" | "0D") => (&token[2..], 10),
+        _ => match token.strip_prefix('\\') {
+            Some(octal) => (octal, 8),
+            None => (token, 16),
+        },
+    };
+
+    if digits.is_empty() && radix != 16 && token.len() == 2 {
+        return Err(ParseError::new(token, "a radix prefix, write 0x for hex"));
+    }
+    if digits.is_empty() {
+        return Err(ParseError::new(token, "missing digits"));
+    }
+    if !digits.chars().all(|c| c.is_digit(radix)) {
+        return Err(ParseError::new(token, "invalid digit"));
+    }
+
+    match u32::from_str_radix(digits, radix) {
+        Ok(byte) => u8::try_from(byte).map_err(|_| ParseError::new(token, "out of range")),
+        Err(_) => Err(ParseError::new(token, "out of range")),
+    }
+}
+
+pub fn decode_base64(buf: &str) -> Result<Vec<u8>, ParseError> {
+    let mut cmd = Vec::with_capacity(buf.len() * 3 / 4);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    let mut padded = false;
+
+    for c in buf.chars().filter(|c| !c.is_whitespace()) {
+        let value = match c {
+            '=' => {
+                padded = true;
+                continue;
+            }
+            _ if padded => return Err(ParseError::new(buf.trim(), "data after padding")),
+            'A'..='Z' => c as u32 - 'A' as u32,
+            'a'..='z' => c as u32 - 'a' as u32 + 26,
+            '0'..='9' => c as u32 - '0' as u32 + 52,
+            '+' | '-' => 62,
+            '/' | '_' => 63,
+            _ => return Err(ParseError::new(&c.to_string(), "invalid base64 character")),
+        };
+
+        acc = (acc << 6) | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            cmd.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+
+    if bits >= 6 {
+        return Err(ParseError::new(buf.trim(), "truncated base64"));
+    }
+
+    Ok(cmd)
+}
+
+pub fn encode_base64(buf: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(buf.len().div_ceil(3) * 4);
+    for chunk in buf.chunks(3) {
+        let acc = chunk.iter().enumerate().fold(0u32, |acc, (i, &byte)| {
+            acc | ((byte as u32) << (16 - 8 * i))
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(acc >> (18 - 6 * i)) as usize & 0x3f] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let split = s.find(|c: char| !c.is_ascii_digit() && c != '.')?;
+    let (value, unit) = s.split_at(split);
+    let value: f64 = value.parse().ok()?;
+
+    let secs = match unit {
+        "us" => value / 1_000_000.0,
+        "ms" => value / 1_000.0,
+        "s" => value,
+        "m" => value * 60.0,
+        _ => return None,
+    };
+    Duration::try_from_secs_f64(secs).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caret_token() {
+        assert_eq!(unescape("^C"), b"\x03");
+        assert_eq!(unescape("^["), b"\x1b");
+        assert_eq!(unescape("^?"), b"\x7f");
+        assert_eq!(unescape("cat ^D"), b"cat \x04");
+        assert_eq!(unescape("\\^C"), b"^C");
+    }
+
+    #[test]
+    fn caret_inside_word() {
+        assert_eq!(unescape("grep ^Foo"), b"grep ^Foo");
+        assert_eq!(unescape("grep ^foo"), b"grep ^foo");
+        assert_eq!(unescape("[^[a-z]]"), b"[^[a-z]]");
+        assert_eq!(unescape("x^C"), b"x^C");
+    }
+
+    #[test]
+    fn byte_radices() {
+        assert_eq!(parse_byte("1b").unwrap(), 0x1b);
+        assert_eq!(parse_byte("0x1b").unwrap(), 0x1b);
+        assert_eq!(parse_byte("\\033").unwrap(), 0o33);
+        assert_eq!(parse_byte("0o33").unwrap(), 0o33);
+        assert_eq!(parse_byte("0d27").unwrap(), 27);
+        assert_eq!(parse_byte("0b00011011").unwrap(), 0x1b);
+        assert_eq!(parse_byte("0b1").unwrap(), 1);
+        assert_eq!(parse_byte("0x0b").unwrap(), 0x0b);
+        assert_eq!(parse_byte("0x0d").unwrap(), 0x0d);
+    }
+
+    #[test]
+    fn byte_prefix_alone() {
+        assert!(parse_byte("0b").is_err());
+        assert!(parse_byte("0d").is_err());
+        assert!(parse_byte("0x").is_err());
+        assert!(parse_byte("\\").is_err());
+    }
+
+    #[test]
+    fn byte_errors() {
+        assert!(parse_byte("0b2").is_err());
+        assert!(parse_byte("0d256").is_err());
+        assert!(parse_byte("100").is_err());
+        assert!(parse_byte("zz").is_err());
+    }
+
+    #[test]
+    fn bytes_line() {
+        let bytes = parse_bytes("1b 0x5b \"A\\n\" 0d13 # up").unwrap();
+        assert_eq!(bytes, b"\x1b\x5bA\n\x0d");
+        assert!(parse_bytes("\"open").is_err());
+    }
+
+    #[test]
+    fn caret_keys() {
+        assert_eq!(unescape_keys("^]d"), b"\x1dd");
+        assert_eq!(unescape_keys("^A^D"), b"\x01\x04");
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(unescape("a\\tb\\r\\n"), b"a\tb\r\n");
+        assert_eq!(unescape("\\e[A\\0"), b"\x1b[A\0");
+        assert_eq!(unescape("\\x1b\\x7F"), b"\x1b\x7f");
+        assert_eq!(unescape("\\\\ \\\""), b"\\ \"");
+    }
+
+    #[test]
+    fn escapes_unknown() {
+        assert_eq!(unescape("\\q"), b"\\q");
+        assert_eq!(unescape("\\xg1"), b"\\xg1");
+        assert_eq!(unescape("\\x1"), b"\\x1");
+        assert_eq!(unescape("end\\"), b"end\\");
+        assert_eq!(unescape("\\xé"), "\\xé".as_bytes());
+    }
+
+    #[test]
+    fn base64() {
+        assert_eq!(decode_base64("aGVsbG8=").unwrap(), b"hello");
+        assert_eq!(decode_base64("aGVs\nbG8").unwrap(), b"hello");
+        assert_eq!(decode_base64("G1tB").unwrap(), b"\x1b[A");
+        assert_eq!(decode_base64("-_8").unwrap(), [0xfb, 0xff]);
+        assert_eq!(decode_base64("").unwrap(), b"");
+        for bytes in [&b""[..], b"a", b"ab", b"abc", b"\x00\xff\x1b"] {
+            assert_eq!(decode_base64(&encode_base64(bytes)).unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn base64_errors() {
+        assert!(decode_base64("aGVsbG8=x").is_err());
+        assert!(decode_base64("a").is_err());
+        assert!(decode_base64("aG!s").is_err());
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("250us"), Some(Duration::from_micros(250)));
+        assert_eq!(parse_duration("1.5ms"), Some(Duration::from_micros(1500)));
+        assert_eq!(parse_duration("2s"), Some(Duration::from_secs(2)));
+        assert_eq!(parse_duration("0.5m"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_duration("10"), None);
+        assert_eq!(parse_duration("10h"), None);
+        assert_eq!(parse_duration("ms"), None);
+        assert_eq!(parse_duration("1.2.3s"), None);
+    }
+}
@@ -0,0 +1,69 @@
+use crate::cli::InsideArgs;
+use crate::flags;
+
+use nix::pty::Winsize;
+
+use termios::{tcflag_t, Termios};
+
+use std::os::fd::RawFd;
+use std::os::unix::process::CommandExt as _;
+use std::process::Command;
+
+// Runs as the child, so everything here goes to the slave and shows up in READ lines.
+pub fn run(args: &InsideArgs) -> String {
+    let pid = std::process::id();
+    let (pgrp, sid) = unsafe { (libc::getpgrp(), libc::getsid(0)) };
+    println!("INSIDE pid {pid} pgrp {pgrp} sid {sid}");
+
+    for fd in 0..=2 {
+        report(fd);
+    }
+
+    let mut cmd = Command::new(&args.command[0]);
+    cmd.args(&args.command[1..]);
+    if let Some(arg0) = &args.arg0 {
+        cmd.arg0(arg0);
+    }
+    let e = cmd.exec();
+    format!("could not exec {}: {e}", args.command[0])
+}
+
+fn report(fd: RawFd) {
+    if unsafe { libc::isatty(fd) } == 0 {
+        println!("INSIDE fd {fd} is not a tty");
+        return;
+    }
+
+    let name = nix::unistd::ttyname(fd)
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|e| format!("unnamed ({e})"));
+    let mut winsize: Winsize = unsafe { std::mem::zeroed() };
+    let size = match unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut winsize) } {
+        -1 => "size unavailable".to_string(),
+        _ => format!("{}x{}", winsize.ws_row, winsize.ws_col),
+    };
+    // Both fail with ENOTTY unless the tty is our controlling terminal.
+    let (fg, sid) = unsafe { (libc::tcgetpgrp(fd), libc::tcgetsid(fd)) };
+    let controlling = match (fg, sid) {
+        (-1, _) | (_, -1) => "not controlling".to_string(),
+        (fg, sid) => format!("fg {fg} sid {sid}"),
+    };
+    println!("INSIDE fd {fd} is {name} {size} {controlling}");
+
+    match Termios::from_fd(fd) {
+        Ok(term) => {
+            let set = |flags: tcflag_t, table: &[(tcflag_t, &str)]| {
+                table
+                    .iter()
+                    .filter(|&&(flag, _)| flags & flag != 0)
+                    .map(|&(_, name)| name)
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            };
+            println!("INSIDE fd {fd} iflag {}", set(term.c_iflag, flags::IFLAGS));
+            println!("INSIDE fd {fd} oflag {}", set(term.c_oflag, flags::OFLAGS));
+            println!("INSIDE fd {fd} lflag {}", set(term.c_lflag, flags::LFLAGS));
+        }
+        Err(e) => println!("INSIDE fd {fd} termios unavailable: {e}"),
+    }
+}
@@ -0,0 +1,62 @@
+use crate::flags;
+use crate::procfs;
+use crate::Session;
+
+use termios::Termios;
+
+use std::os::fd::RawFd;
+use std::sync::Arc;
+use std::time::Duration;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+// There is no notification for tcsetpgrp or tcsetattr, so this polls; a pipeline that is in the
+// foreground for less than the interval can be missed.
+pub fn spawn_pgrp_monitor(master: RawFd, session: Arc<Session>) {
+    std::thread::spawn(move || {
+        let mut last = None;
+        let mut last_termios = Termios::from_fd(master).ok();
+        while !session.lifecycle.lock().unwrap().closed() {
+            if session.hooks.watches_termios() || session.baseline.is_some() {
+                let termios = Termios::from_fd(master).ok();
+                if let (Some(old), Some(new)) = (&last_termios, &termios) {
+                    let changes = flags::diff(old, new);
+                    if session.hooks.watches_termios() && !changes.is_empty() {
+                        let changes = changes.join(" ");
+                        println!("TERMIOS {changes}");
+                        session.hooks.termios_changed(&changes);
+                    }
+                }
+                if let (Some(baseline), Some(term)) = (&session.baseline, &termios) {
+                    for note in baseline.lock().unwrap().check(term) {
+                        println!("{note}");
+                        session.record(|rec| rec.note(note));
+                    }
+                }
+                last_termios = termios;
+            }
+
+            let pgrp = match unsafe { libc::tcgetpgrp(master) } {
+                -1 => None,
+                pgrp => Some(pgrp),
+            };
+            if pgrp != last {
+                let text = match pgrp {
+                    Some(pgrp) => format!("FOREGROUND pgrp {pgrp} {}", leader(pgrp)),
+                    None => "FOREGROUND none".to_string(),
+                };
+                println!("{text}");
+                session.record(|rec| rec.note(text));
+                last = pgrp;
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    });
+}
+
+fn leader(pgrp: i32) -> String {
+    match procfs::parse(pgrp as u32) {
+        Some(stat) => format!("({})", stat.comm),
+        None => "(leader exited)".to_string(),
+    }
+}
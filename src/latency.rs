@@ -0,0 +1,103 @@
+use crate::stats::percentile;
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+const ECHO_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub struct EchoTracker {
+    pending: VecDeque<(u8, Instant)>,
+    samples: Vec<(u8, Duration)>,
+    lost: usize,
+    after_cr: bool,
+}
+
+impl EchoTracker {
+    pub fn new() -> Self {
+        Self {
+            pending: VecDeque::new(),
+            samples: Vec::new(),
+            lost: 0,
+            after_cr: false,
+        }
+    }
+
+    pub fn injected(&mut self, byte: u8) {
+        // Other control characters are echoed in caret notation (or not at all),
+        // so there is nothing to match them against.
+        if matches!(byte, b'\n' | b'\r' | 0x20..=0x7e) {
+            self.pending.push_back((byte, Instant::now()));
+        }
+    }
+
+    pub fn observed(&mut self, buf: &[u8]) -> Vec<(u8, Duration)> {
+        let now = Instant::now();
+        let mut matched = Vec::new();
+
+        while let Some(&(_, at)) = self.pending.front() {
+            if now.duration_since(at) < ECHO_TIMEOUT {
+                break;
+            }
+            self.pending.pop_front();
+            self.lost += 1;
+        }
+
+        for &byte in buf {
+            let after_cr = std::mem::replace(&mut self.after_cr, false);
+            let Some(&(expected, at)) = self.pending.front() else {
+                continue;
+            };
+
+            let is_match = if is_newline(expected) {
+                if byte == b'\n' && after_cr {
+                    continue;
+                }
+                is_newline(byte)
+            } else {
+                byte == expected
+            };
+
+            if is_match {
+                self.pending.pop_front();
+                self.after_cr = byte == b'\r';
+                let latency = now.duration_since(at);
+                self.samples.push((expected, latency));
+                matched.push((expected, latency));
+            }
+        }
+
+        matched
+    }
+
+    pub fn report(&self) {
+        println!("ECHO LATENCY");
+        println!(
+            "matched {}, lost {}, pending {}",
+            self.samples.len(),
+            self.lost,
+            self.pending.len()
+        );
+
+        if self.samples.is_empty() {
+            return;
+        }
+
+        let mut sorted: Vec<_> = self.samples.iter().map(|&(_, d)| d).collect();
+        sorted.sort();
+        let total: Duration = sorted.iter().sum();
+
+        println!(
+            "min {:?}, p50 {:?}, p90 {:?}, p99 {:?}, max {:?}, mean {:?}",
+            sorted[0],
+            percentile(&sorted, 50.0),
+            percentile(&sorted, 90.0),
+            percentile(&sorted, 99.0),
+            sorted[sorted.len() - 1],
+            total / sorted.len() as u32,
+        );
+    }
+}
+
+fn is_newline(byte: u8) -> bool {
+    byte == b'\n' || byte == b'\r'
+}
@@ -0,0 +1,83 @@
+use crate::flags;
+
+use termios::os::target::{ICRNL, IGNCR, INLCR, ISIG, ISTRIP, OCRNL, ONLCR, OPOST};
+use termios::os::target::{VINTR, VQUIT, VSUSP};
+use termios::Termios;
+
+// Spells out what the line discipline did to the bytes in between, going by the termios
+// in effect. Inferred, not observed: the child's side is never read directly.
+pub fn input_notes(term: &Termios, bytes: &[u8]) -> Vec<String> {
+    let mut notes = Vec::new();
+    let count = |byte: u8| bytes.iter().filter(|&&b| b == byte).count();
+
+    let crs = count(b'\r');
+    if crs > 0 {
+        if term.c_iflag & IGNCR != 0 {
+            notes.push(format!(
+                "wrote 0x0d{}, child receives nothing ← IGNCR",
+                times(crs)
+            ));
+        } else if term.c_iflag & ICRNL != 0 {
+            notes.push(format!(
+                "wrote 0x0d{}, child receives 0x0a ← ICRNL",
+                times(crs)
+            ));
+        }
+    }
+    let lfs = count(b'\n');
+    if lfs > 0 && term.c_iflag & INLCR != 0 {
+        notes.push(format!(
+            "wrote 0x0a{}, child receives 0x0d ← INLCR",
+            times(lfs)
+        ));
+    }
+
+    let high = bytes.iter().filter(|&&b| b >= 0x80).count();
+    if high > 0 && term.c_iflag & ISTRIP != 0 {
+        notes.push(format!("{high} bytes lose their high bit ← ISTRIP"));
+    }
+
+    if term.c_lflag & ISIG != 0 {
+        for (index, signal) in [(VINTR, "SIGINT"), (VQUIT, "SIGQUIT"), (VSUSP, "SIGTSTP")] {
+            let cc = term.c_cc[index];
+            if cc != flags::VDISABLE && count(cc) > 0 {
+                notes.push(format!(
+                    "wrote {cc:#04x}, child gets {signal} instead of the byte ← ISIG"
+                ));
+            }
+        }
+    }
+
+    notes
+}
+
+pub fn output_notes(term: &Termios, buf: &[u8]) -> Vec<String> {
+    let mut notes = Vec::new();
+    if term.c_oflag & OPOST == 0 {
+        return notes;
+    }
+
+    if term.c_oflag & ONLCR != 0 {
+        // Every LF of the child gains a CR, so a CR LF written by the child reads as
+        // CR CR LF.
+        let translated = buf.windows(2).filter(|pair| pair == b"\r\n").count();
+        if translated > 0 {
+            notes.push(format!(
+                "child wrote 0x0a{}, master read 0x0d 0x0a ← ONLCR",
+                times(translated)
+            ));
+        }
+    }
+    if term.c_oflag & OCRNL != 0 && buf.contains(&b'\n') {
+        notes.push("0x0a read from the master may have been 0x0d from the child ← OCRNL".into());
+    }
+
+    notes
+}
+
+fn times(count: usize) -> String {
+    match count {
+        1 => String::new(),
+        count => format!(" ×{count}"),
+    }
+}
@@ -0,0 +1,71 @@
+use crate::Session;
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const FLUSH_POLL: Duration = Duration::from_millis(50);
+
+// Joins the chunks back into the lines the program wrote. A line ends with LF (CR LF
+// included) or with a CR that is not followed by LF, like progress bars redrawing in
+// place. Whatever never gets an ending is shown once it is older than the timeout.
+pub struct Assembler {
+    partial: String,
+    since: Option<Instant>,
+    timeout: Duration,
+}
+
+impl Assembler {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            partial: String::new(),
+            since: None,
+            timeout,
+        }
+    }
+
+    pub fn push(&mut self, text: &str) {
+        for c in text.chars() {
+            if c != '\n' && self.partial.ends_with('\r') {
+                self.emit("");
+            }
+            self.partial.push(c);
+            self.since.get_or_insert_with(Instant::now);
+            if c == '\n' {
+                self.emit("");
+            }
+        }
+    }
+
+    pub fn flush_stale(&mut self) {
+        let Some(since) = self.since else {
+            return;
+        };
+        // A lone CR may still turn out to be half of a CR LF.
+        if since.elapsed() >= self.timeout {
+            let note = format!(" (no line ending after {:?})", self.timeout);
+            self.emit(&note);
+        }
+    }
+
+    pub fn flush(&mut self) {
+        if !self.partial.is_empty() {
+            self.emit(" (no line ending at the end)");
+        }
+    }
+
+    fn emit(&mut self, note: &str) {
+        println!("LINE {:?}{note}", self.partial);
+        println!();
+        self.partial.clear();
+        self.since = None;
+    }
+}
+
+pub fn spawn_flusher(session: Arc<Session>) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(FLUSH_POLL);
+        if let Some(lines) = &session.lines {
+            lines.lock().unwrap().flush_stale();
+        }
+    });
+}
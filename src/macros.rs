@@ -0,0 +1,773 @@
+use crate::input::{self, LineEnding};
+use crate::regions;
+use crate::tap::Tap;
+use crate::{execute, send_line, Session, Writer};
+
+use regex::bytes::Regex;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Error as IoError;
+use std::os::fd::RawFd;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+const EXPECT_TIMEOUT: Duration = Duration::from_secs(10);
+pub const MAX_DEPTH: usize = 8;
+
+#[derive(Clone)]
+pub enum Step {
+    Send(String),
+    SendLine(String),
+    // From a here-document with a quoted delimiter, sent as written.
+    SendRaw(Vec<u8>),
+    SendLineRaw(Vec<u8>),
+    Expect(Vec<u8>, Duration),
+    // Binds its groups as ${1}, ${2}... and by name for what follows. Compiled as it runs,
+    // once what earlier ones bound is filled in.
+    ExpectRe(String, Duration),
+    Sleep(Duration),
+    CaptureStart(String),
+    CaptureEnd(String, PathBuf),
+    Invoke(String),
+    // Waits for whichever pattern comes first and runs its steps, or the else steps if none
+    // does in time.
+    On(Vec<(Vec<u8>, Vec<Step>)>, Option<Vec<Step>>, Duration),
+    Repeat(usize, Vec<Step>),
+    // Runs the steps again while they fail, at most this many times in all.
+    Retry(usize, Vec<Step>),
+}
+
+#[derive(Default)]
+pub struct Macros {
+    aliases: BTreeMap<String, Vec<u8>>,
+    macros: BTreeMap<String, Vec<Step>>,
+}
+
+impl Macros {
+    pub fn define_alias(&mut self, def: &str) -> Result<(), String> {
+        let (name, value) = split_definition(def)?;
+        self.set_alias(name, value);
+        Ok(())
+    }
+
+    pub fn define_macro(&mut self, def: &str) -> Result<(), String> {
+        let (name, value) = split_definition(def)?;
+        self.set_macro(name, value)
+    }
+
+    pub fn set_alias(&mut self, name: &str, value: &str) {
+        let value = input::unescape(input::unquote(value));
+        self.aliases.insert(name.to_string(), value);
+    }
+
+    pub fn set_macro(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let steps = input::split_outside_quotes(value, ';')
+            .into_iter()
+            .map(str::trim)
+            .filter(|step| !step.is_empty())
+            .map(|step| parse_step(step, EXPECT_TIMEOUT))
+            .collect::<Result<_, _>>()?;
+        self.macros.insert(name.to_string(), steps);
+        Ok(())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.aliases.contains_key(name) || self.macros.contains_key(name)
+    }
+
+    pub fn alias(&self, name: &str) -> Option<&[u8]> {
+        self.aliases.get(name).map(Vec::as_slice)
+    }
+
+    pub fn steps(&self, name: &str) -> Option<&[Step]> {
+        self.macros.get(name).map(Vec::as_slice)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.aliases
+            .keys()
+            .chain(self.macros.keys())
+            .map(String::as_str)
+    }
+
+    pub fn print_aliases(&self) {
+        for (name, value) in &self.aliases {
+            println!("alias {name} = {:?}", String::from_utf8_lossy(value));
+        }
+    }
+
+    pub fn print_macros(&self) {
+        for (name, steps) in &self.macros {
+            println!("macro {name} = {} steps", steps.len());
+        }
+    }
+}
+
+fn split_definition(def: &str) -> Result<(&str, &str), String> {
+    let Some((name, value)) = def.split_once('=') else {
+        return Err("expected NAME = VALUE".into());
+    };
+    let name = name.trim();
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        return Err(format!("invalid name {name:?}"));
+    }
+    Ok((name, value.trim()))
+}
+
+// Expects without a timeout of their own get WAIT.
+pub fn parse_step(step: &str, wait: Duration) -> Result<Step, String> {
+    let (op, arg) = step.split_once(char::is_whitespace).unwrap_or((step, ""));
+    let arg = arg.trim();
+
+    match op {
+        "send" => Ok(Step::Send(input::unquote(arg).to_string())),
+        "sendline" => Ok(Step::SendLine(input::unquote(arg).to_string())),
+        "expect" if !arg.is_empty() => {
+            let (pattern, timeout) = parse_pattern(arg, step, wait)?;
+            Ok(Step::Expect(pattern, timeout))
+        }
+        "expect-re" if !arg.is_empty() => {
+            // Not unescaped: the regex has escapes of its own.
+            let (pattern, timeout) = input::split_token(arg);
+            let timeout = match timeout {
+                "" => wait,
+                timeout => input::parse_duration(timeout)
+                    .ok_or_else(|| format!("invalid timeout in `{step}`"))?,
+            };
+            let pattern = input::unquote(pattern).to_string();
+            check_re(&pattern)?;
+            Ok(Step::ExpectRe(pattern, timeout))
+        }
+        "capture-start" if !arg.is_empty() => {
+            Ok(Step::CaptureStart(input::unquote(arg).to_string()))
+        }
+        "capture-end" => {
+            let (name, path) = regions::parse_end(arg)?;
+            Ok(Step::CaptureEnd(name, path))
+        }
+        "sleep" => input::parse_duration(arg)
+            .map(Step::Sleep)
+            .ok_or_else(|| format!("invalid duration in `{step}`")),
+        "expect" | "expect-re" => Err(format!("`{op}` needs a pattern")),
+        name if arg.is_empty() => Ok(Step::Invoke(name.to_string())),
+        _ => Err(format!("unknown step `{step}`")),
+    }
+}
+
+fn parse_pattern(arg: &str, step: &str, wait: Duration) -> Result<(Vec<u8>, Duration), String> {
+    let (pattern, timeout) = input::split_token(arg);
+    let timeout = match timeout {
+        "" => wait,
+        timeout => {
+            input::parse_duration(timeout).ok_or_else(|| format!("invalid timeout in `{step}`"))?
+        }
+    };
+    Ok((input::unescape(input::unquote(pattern)), timeout))
+}
+
+// ${NAME} in the script is replaced with VALUE from --var NAME=VALUE.
+pub fn parse_script(text: &str, vars: &BTreeMap<String, String>) -> Result<Vec<Step>, String> {
+    let mut parser = Parser {
+        lines: text.lines().enumerate().map(|(i, line)| (i + 1, line)),
+        vars,
+        bound: BTreeSet::new(),
+    };
+    let (steps, close) = parser.block(EXPECT_TIMEOUT)?;
+    match close {
+        Some((n, _)) => Err(format!("line {n}: `}}` without a block to close")),
+        None => Ok(steps),
+    }
+}
+
+// A script line and its number.
+type Line = (usize, String);
+
+struct Parser<'a, I> {
+    lines: I,
+    vars: &'a BTreeMap<String, String>,
+    // Left for the steps to fill in when they run.
+    bound: BTreeSet<String>,
+}
+
+impl<'a, I: Iterator<Item = (usize, &'a str)>> Parser<'a, I> {
+    // The next line that is not blank or a comment, with the variables expanded.
+    fn next(&mut self) -> Result<Option<Line>, String> {
+        for (n, line) in self.lines.by_ref() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let line =
+                expand(line, self.vars, &self.bound).map_err(|e| format!("line {n}: {e}"))?;
+            return Ok(Some((n, line)));
+        }
+        Ok(None)
+    }
+
+    // Steps up to the line that closes the block, returned along with them, as it may go
+    // on with `else {` or another `on`.
+    fn block(&mut self, wait: Duration) -> Result<(Vec<Step>, Option<Line>), String> {
+        let mut steps = Vec::new();
+        while let Some((n, line)) = self.next()? {
+            if line.starts_with('}') {
+                return Ok((steps, Some((n, line))));
+            }
+            if let Some(header) = line.strip_prefix("on ") {
+                steps.push(self.on(header.to_string(), n, wait)?);
+                continue;
+            }
+            if let Some(header) = line.strip_prefix("repeat ") {
+                let count = parse_header(header, n, |count| count.parse().ok())?;
+                if count == 0 {
+                    return Err(format!("line {n}: repeat needs a count of at least 1"));
+                }
+                steps.push(Step::Repeat(count, self.body(n, wait)?));
+                continue;
+            }
+            if let Some(header) = line.strip_prefix("retry ") {
+                let (count, wait) = parse_header(header, n, |header| {
+                    let (count, timeout) = header.split_once(" timeout ").unwrap_or((header, ""));
+                    let wait = match timeout.trim() {
+                        "" => wait,
+                        timeout => input::parse_duration(timeout)?,
+                    };
+                    Some((count.trim().parse().ok()?, wait))
+                })?;
+                if count == 0 {
+                    return Err(format!("line {n}: retry needs a count of at least 1"));
+                }
+                steps.push(Step::Retry(count, self.body(n, wait)?));
+                continue;
+            }
+            let Some((op, delimiter, literal)) = here_doc(&line) else {
+                let step = parse_step(&line, wait).map_err(|e| format!("line {n}: {e}"))?;
+                if let Step::ExpectRe(pattern, _) = &step {
+                    let re = check_re(pattern).map_err(|e| format!("line {n}: {e}"))?;
+                    self.bound
+                        .extend((1..re.captures_len()).map(|i| i.to_string()));
+                    self.bound
+                        .extend(re.capture_names().flatten().map(str::to_string));
+                }
+                steps.push(step);
+                continue;
+            };
+
+            // The lines in between as they are, indentation included, joined without a
+            // final newline: sendline adds the line ending, send nothing.
+            let mut body = Vec::new();
+            loop {
+                match self.lines.next() {
+                    Some((_, line)) if line.trim() == delimiter => break,
+                    Some((_, line)) if literal => body.push(line.to_string()),
+                    Some((n, line)) => body.push(
+                        expand(line, self.vars, &self.bound)
+                            .map_err(|e| format!("line {n}: {e}"))?,
+                    ),
+                    None => {
+                        return Err(format!("line {n}: no {delimiter} ending the here-document"))
+                    }
+                }
+            }
+            let body = body.join("\n");
+            steps.push(match (op, literal) {
+                ("send", false) => Step::Send(body),
+                ("send", true) => Step::SendRaw(body.into_bytes()),
+                (_, false) => Step::SendLine(body),
+                (_, true) => Step::SendLineRaw(body.into_bytes()),
+            });
+        }
+        Ok((steps, None))
+    }
+
+    fn body(&mut self, n: usize, wait: Duration) -> Result<Vec<Step>, String> {
+        match self.block(wait)? {
+            (steps, Some((_, close))) if close == "}" => Ok(steps),
+            (_, Some((n, _))) => Err(format!("line {n}: expected `}}`")),
+            (_, None) => Err(format!("line {n}: block not closed")),
+        }
+    }
+
+    // `on PATTERN [TIMEOUT] {`, then any number of `} on PATTERN [TIMEOUT] {`, an optional
+    // `} else {` and the closing `}`. The group waits as long as its longest timeout.
+    fn on(&mut self, mut header: String, n: usize, wait: Duration) -> Result<Step, String> {
+        let mut branches = Vec::new();
+        let mut timeout = Duration::ZERO;
+        loop {
+            let arg = header
+                .trim()
+                .strip_suffix('{')
+                .map(str::trim)
+                .ok_or_else(|| format!("line {n}: expected `{{` after `on {}`", header.trim()))?;
+            let (pattern, longest) =
+                parse_pattern(arg, &header, wait).map_err(|e| format!("line {n}: {e}"))?;
+            timeout = timeout.max(longest);
+
+            let (steps, close) = self.block(wait)?;
+            branches.push((pattern, steps));
+            let Some((n, close)) = close else {
+                return Err(format!("line {n}: `on` block not closed"));
+            };
+            let rest = close[1..].trim();
+            if rest.is_empty() {
+                return Ok(Step::On(branches, None, timeout));
+            }
+            if rest == "else {" {
+                let steps = self.body(n, wait)?;
+                return Ok(Step::On(branches, Some(steps), timeout));
+            }
+            header = rest
+                .strip_prefix("on ")
+                .ok_or_else(|| format!("line {n}: expected `}}`, `}} else {{` or `}} on`"))?
+                .to_string();
+        }
+    }
+}
+
+// What is between the keyword and the `{`.
+fn parse_header<T>(
+    header: &str,
+    n: usize,
+    parse: impl FnOnce(&str) -> Option<T>,
+) -> Result<T, String> {
+    header
+        .trim()
+        .strip_suffix('{')
+        .and_then(|header| parse(header.trim()))
+        .ok_or_else(|| format!("line {n}: invalid block header `{}`", header.trim()))
+}
+
+fn expand(
+    line: &str,
+    vars: &BTreeMap<String, String>,
+    bound: &BTreeSet<String>,
+) -> Result<String, String> {
+    let mut expanded = String::new();
+    let mut rest = line;
+    while let Some(start) = rest.find("${") {
+        let len = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("unclosed ${{ in `{line}`"))?;
+        let name = &rest[start + 2..start + len];
+        expanded.push_str(&rest[..start]);
+        match vars.get(name) {
+            Some(value) => expanded.push_str(value),
+            None if bound.contains(name) => expanded.push_str(&rest[start..=start + len]),
+            None => return Err(format!("${{{name}}} is not set, pass --var {name}=VALUE")),
+        }
+        rest = &rest[start + len + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+// What expect-re bound, filled in as the step runs; other ${...} were expanded when the
+// script was read.
+fn substitute(text: &[u8], vars: &BTreeMap<String, String>) -> Vec<u8> {
+    let mut text = text.to_vec();
+    for (name, value) in vars {
+        let name = format!("${{{name}}}");
+        let mut from = 0;
+        while let Some(at) = text[from..]
+            .windows(name.len())
+            .position(|w| w == name.as_bytes())
+        {
+            let at = from + at;
+            text.splice(at..at + name.len(), value.bytes());
+            from = at + value.len();
+        }
+    }
+    text
+}
+
+// The values go in as text to match, not as regex syntax.
+pub fn compile_re(pattern: &str, vars: &BTreeMap<String, String>) -> Result<Regex, String> {
+    let escaped = vars
+        .iter()
+        .map(|(name, value)| (name.clone(), regex::escape(value)))
+        .collect();
+    let pattern = substitute(pattern.as_bytes(), &escaped);
+    Regex::new(&String::from_utf8_lossy(&pattern)).map_err(|e| e.to_string())
+}
+
+// With what is bound only as it runs left empty, which is as much as can be checked early.
+fn check_re(pattern: &str) -> Result<Regex, String> {
+    let mut blanked = String::new();
+    let mut rest = pattern;
+    while let Some((before, var)) = rest.split_once("${") {
+        let Some((_, after)) = var.split_once('}') else {
+            break;
+        };
+        blanked.push_str(before);
+        rest = after;
+    }
+    blanked.push_str(rest);
+    Regex::new(&blanked).map_err(|e| e.to_string())
+}
+
+// `send <<EOF` or `sendline <<EOF`; quoting the delimiter (`<<'EOF'`) turns off the
+// interpretation of escapes, as in the shell.
+fn here_doc(line: &str) -> Option<(&str, &str, bool)> {
+    let (op, rest) = line.split_once(char::is_whitespace)?;
+    if op != "send" && op != "sendline" {
+        return None;
+    }
+    let quoted = rest.trim().strip_prefix("<<")?.trim_start();
+    let delimiter = quoted.trim_matches(['\'', '"']);
+    if delimiter.is_empty() || delimiter.contains(char::is_whitespace) {
+        return None;
+    }
+    Some((op, delimiter, delimiter.len() != quoted.len()))
+}
+
+#[derive(Default)]
+pub struct Outcome {
+    pub exit: bool,
+    pub failed: bool,
+}
+
+pub fn invoke(
+    name: &str,
+    master: RawFd,
+    writer: &Writer,
+    session: &Session,
+) -> Result<bool, IoError> {
+    // Installed up front so that output arriving right after a send is not missed by a
+    // following expect.
+    let mut tap = Tap::new(session);
+    let result = invoke_nested(name, master, writer, session, &mut tap, 0);
+    println!();
+    result.map(|outcome| outcome.exit)
+}
+
+pub fn run_script(
+    steps: &[Step],
+    master: RawFd,
+    writer: &Writer,
+    session: &Session,
+) -> Result<Outcome, IoError> {
+    let mut tap = Tap::new(session);
+    let result = run_steps("script", steps, master, writer, session, &mut tap, 0);
+    println!();
+    result
+}
+
+fn invoke_nested(
+    name: &str,
+    master: RawFd,
+    writer: &Writer,
+    session: &Session,
+    tap: &mut Tap,
+    depth: usize,
+) -> Result<Outcome, IoError> {
+    if let Some(value) = writer.macros.aliases.get(name) {
+        println!("ALIAS {name}");
+        execute(value, master, session)?;
+        return Ok(Outcome::default());
+    }
+
+    let Some(steps) = writer.macros.macros.get(name) else {
+        println!("MACRO {name} is not defined");
+        return Ok(Outcome {
+            failed: true,
+            ..Outcome::default()
+        });
+    };
+    if depth >= MAX_DEPTH {
+        println!("MACRO {name} nested too deeply, stopped");
+        return Ok(Outcome {
+            failed: true,
+            ..Outcome::default()
+        });
+    }
+
+    println!("MACRO {name}");
+    run_steps(
+        &format!("macro {name}"),
+        steps,
+        master,
+        writer,
+        session,
+        tap,
+        depth,
+    )
+}
+
+fn run_steps(
+    label: &str,
+    steps: &[Step],
+    master: RawFd,
+    writer: &Writer,
+    session: &Session,
+    tap: &mut Tap,
+    depth: usize,
+) -> Result<Outcome, IoError> {
+    let mut outcome = Outcome::default();
+    for step in steps {
+        match step {
+            Step::Send(text) => {
+                let text = substitute(text.as_bytes(), &tap.vars);
+                let text = String::from_utf8_lossy(&text);
+                outcome.exit |= send_line(&text, LineEnding::None, master, writer, session)?
+            }
+            Step::SendLine(text) => {
+                let text = substitute(text.as_bytes(), &tap.vars);
+                let text = String::from_utf8_lossy(&text);
+                outcome.exit |= send_line(&text, writer.default_ending(), master, writer, session)?
+            }
+            Step::SendRaw(bytes) => execute(bytes, master, session)?,
+            Step::SendLineRaw(bytes) => {
+                let mut bytes = bytes.clone();
+                bytes.extend_from_slice(writer.default_ending().as_bytes());
+                execute(&bytes, master, session)?
+            }
+            Step::Expect(pattern, timeout) => {
+                let start = Instant::now();
+                let pattern = substitute(pattern, &tap.vars);
+                let shown = String::from_utf8_lossy(&pattern);
+                if tap.expect(&pattern, *timeout) {
+                    println!("EXPECT {shown:?} matched after {:?}", start.elapsed());
+                } else {
+                    println!("EXPECT {shown:?} timed out after {timeout:?}, {label} stopped");
+                    outcome.failed = true;
+                    return Ok(outcome);
+                }
+            }
+            Step::ExpectRe(pattern, timeout) => {
+                let start = Instant::now();
+                let re = match compile_re(pattern, &tap.vars) {
+                    Ok(re) => re,
+                    Err(e) => {
+                        println!("EXPECT-RE {pattern}: {e}, {label} stopped");
+                        outcome.failed = true;
+                        return Ok(outcome);
+                    }
+                };
+                let Some(groups) = tap.expect_re(&re, *timeout) else {
+                    println!("EXPECT-RE {re} timed out after {timeout:?}, {label} stopped");
+                    outcome.failed = true;
+                    return Ok(outcome);
+                };
+                println!("EXPECT-RE {re} matched after {:?}", start.elapsed());
+                for (i, (group, name)) in groups.iter().zip(re.capture_names()).enumerate().skip(1)
+                {
+                    let value = String::from_utf8_lossy(group.as_deref().unwrap_or_default());
+                    println!("  ${{{i}}} = {value:?}");
+                    tap.vars.insert(i.to_string(), value.to_string());
+                    if let Some(name) = name {
+                        println!("  ${{{name}}} = {value:?}");
+                        tap.vars.insert(name.to_string(), value.to_string());
+                    }
+                }
+            }
+            Step::Sleep(duration) => std::thread::sleep(*duration),
+            Step::CaptureStart(name) => {
+                session.regions.lock().unwrap().start(name);
+                println!("CAPTURE-START {name}");
+            }
+            Step::CaptureEnd(name, path) => match session.regions.lock().unwrap().end(name, path) {
+                Ok(len) => println!("CAPTURE-END {name} {len} bytes to {}", path.display()),
+                Err(e) => {
+                    println!("capture-end: {e}, {label} stopped");
+                    outcome.failed = true;
+                    return Ok(outcome);
+                }
+            },
+            Step::On(branches, otherwise, timeout) => {
+                let patterns: Vec<&[u8]> = branches.iter().map(|(p, _)| p.as_slice()).collect();
+                let start = Instant::now();
+                let steps = match tap.expect_any(&patterns, *timeout) {
+                    Some(i) => {
+                        let shown = String::from_utf8_lossy(patterns[i]);
+                        println!("ON {shown:?} matched after {:?}", start.elapsed());
+                        &branches[i].1
+                    }
+                    None => match otherwise {
+                        Some(steps) => {
+                            println!("ON nothing matched within {timeout:?}, else");
+                            steps
+                        }
+                        None => {
+                            println!("ON nothing matched within {timeout:?}, {label} stopped");
+                            outcome.failed = true;
+                            return Ok(outcome);
+                        }
+                    },
+                };
+                let inner = run_steps(label, steps, master, writer, session, tap, depth)?;
+                outcome.exit |= inner.exit;
+                if inner.failed {
+                    outcome.failed = true;
+                    return Ok(outcome);
+                }
+            }
+            Step::Repeat(count, steps) => {
+                for i in 1..=*count {
+                    println!("REPEAT {i} of {count}");
+                    let inner = run_steps(label, steps, master, writer, session, tap, depth)?;
+                    outcome.exit |= inner.exit;
+                    if inner.failed {
+                        outcome.failed = true;
+                        return Ok(outcome);
+                    }
+                }
+            }
+            Step::Retry(count, steps) => {
+                for attempt in 1..=*count {
+                    let inner = run_steps(label, steps, master, writer, session, tap, depth)?;
+                    outcome.exit |= inner.exit;
+                    if !inner.failed {
+                        break;
+                    }
+                    if attempt == *count {
+                        println!("RETRY gave up after {count} attempts, {label} stopped");
+                        outcome.failed = true;
+                        return Ok(outcome);
+                    }
+                    println!("RETRY attempt {attempt} of {count} failed, again");
+                }
+            }
+            Step::Invoke(inner) => {
+                let inner = invoke_nested(inner, master, writer, session, tap, depth + 1)?;
+                outcome.exit |= inner.exit;
+                if inner.failed {
+                    outcome.failed = true;
+                    return Ok(outcome);
+                }
+            }
+        }
+    }
+
+    Ok(outcome)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Result<Vec<Step>, String> {
+        parse_script(text, &BTreeMap::new())
+    }
+
+    #[test]
+    fn on_else() {
+        let steps = parse(
+            r#"
+            on "login:" 5s {
+                sendline root
+            } on "$ " {
+                sendline ls
+            } else {
+                sendline exit
+            }
+            "#,
+        )
+        .unwrap();
+        let [Step::On(branches, Some(otherwise), timeout)] = steps.as_slice() else {
+            panic!("expected one on step with an else");
+        };
+        assert_eq!(branches.len(), 2);
+        assert_eq!(branches[0].0, b"login:");
+        assert_eq!(branches[1].0, b"$ ");
+        assert_eq!(otherwise.len(), 1);
+        assert_eq!(*timeout, Duration::from_secs(10));
+    }
+
+    #[test]
+    fn on_timeout() {
+        let steps = parse("on \"x\" 5s {\n}\n").unwrap();
+        let [Step::On(_, None, timeout)] = steps.as_slice() else {
+            panic!("expected one on step");
+        };
+        assert_eq!(*timeout, Duration::from_secs(5));
+    }
+
+    #[test]
+    fn on_not_closed() {
+        assert!(parse("on \"x\" {\n  sendline y\n").is_err());
+        assert!(parse("on \"x\"\n}\n").is_err());
+    }
+
+    #[test]
+    fn repeat_retry() {
+        let steps =
+            parse("repeat 3 {\n  sendline x\n}\nretry 2 timeout 1s {\n  expect y\n}\n").unwrap();
+        let [Step::Repeat(3, body), Step::Retry(2, retried)] = steps.as_slice() else {
+            panic!("expected repeat and retry");
+        };
+        assert_eq!(body.len(), 1);
+        let [Step::Expect(_, timeout)] = retried.as_slice() else {
+            panic!("expected an expect");
+        };
+        assert_eq!(*timeout, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn zero_count() {
+        let err = parse("sleep 1s\nrepeat 0 {\n}\n").err().unwrap();
+        assert!(err.starts_with("line 2:"), "{err}");
+        let err = parse("retry 0 {\n}\n").err().unwrap();
+        assert!(err.starts_with("line 1:"), "{err}");
+    }
+
+    #[test]
+    fn expect_re_uses_earlier_groups() {
+        let steps =
+            parse("expect-re \"pid (?<pid>\\d+)\"\nexpect-re \"${pid} (\\w+)\" 2s\n").unwrap();
+        let Step::ExpectRe(pattern, timeout) = &steps[1] else {
+            panic!("expected expect-re");
+        };
+        assert_eq!(pattern, "${pid} (\\w+)");
+        assert_eq!(*timeout, Duration::from_secs(2));
+
+        let vars = BTreeMap::from([("pid".to_string(), "4.2".to_string())]);
+        let re = compile_re(pattern, &vars).unwrap();
+        assert!(re.is_match(b"4.2 done"));
+        assert!(!re.is_match(b"412 done"));
+    }
+
+    #[test]
+    fn expect_re_unknown_var() {
+        let err = parse("expect-re \"${nope}\"\n").err().unwrap();
+        assert!(err.starts_with("line 1:"), "{err}");
+    }
+
+    #[test]
+    fn expect_re_invalid() {
+        assert!(parse("expect-re \"(\"\n").is_err());
+    }
+
+    #[test]
+    fn here_doc_expanded() {
+        let vars = BTreeMap::from([("user".to_string(), "root".to_string())]);
+        let text = "sendline <<EOF\n  id ${user}\nexit\n  EOF\nsend << END\nq\nEND\n";
+        let steps = parse_script(text, &vars).unwrap();
+        let [Step::SendLine(line), Step::Send(send)] = steps.as_slice() else {
+            panic!("expected a sendline and a send");
+        };
+        assert_eq!(line, "  id root\nexit");
+        assert_eq!(send, "q");
+    }
+
+    #[test]
+    fn here_doc_literal() {
+        let steps = parse("send <<'EOF'\n${user} \\e\nEOF\nsendline <<\"EOF\"\nx\nEOF\n").unwrap();
+        let [Step::SendRaw(send), Step::SendLineRaw(line)] = steps.as_slice() else {
+            panic!("expected raw sends");
+        };
+        assert_eq!(send, b"${user} \\e");
+        assert_eq!(line, b"x");
+    }
+
+    #[test]
+    fn here_doc_not_closed() {
+        let err = parse("sleep 1s\nsend <<EOF\nx\n").err().unwrap();
+        assert!(err.starts_with("line 2:"), "{err}");
+        assert!(matches!(parse("send <<\n").as_deref(), Ok([Step::Send(_)])));
+    }
+
+    #[test]
+    fn bare_expect() {
+        assert!(parse("expect").is_err());
+        assert!(parse("expect \"$ \"").is_ok());
+    }
+}
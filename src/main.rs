@@ -1,109 +1,1082 @@
 #![allow(unused, unused_mut)]
 
+mod alloc;
+mod auth;
+mod baseline;
+mod bench;
+#[cfg(target_os = "linux")]
+mod bpf;
+mod capture;
+mod cast;
+mod chapters;
+mod child;
+mod classify;
+mod cli;
+mod command;
+mod compare;
+mod config;
+mod daemon;
+mod defaults;
+mod doctor;
+mod editor;
+mod env;
+mod escape;
+mod flags;
+mod holders;
+mod hooks;
+mod hostterm;
+mod ingest;
+mod input;
+mod inside;
+mod jobs;
+mod latency;
+mod ldisc;
+mod lines;
+mod macros;
+mod metrics;
+mod middleware;
+mod mitm;
+mod mmap;
+mod modes;
+mod platform;
+mod procfs;
+mod prompts;
+mod rawio;
+mod reaper;
+mod regions;
+mod rerun;
+mod search;
+mod selftest;
+#[cfg(target_os = "linux")]
+mod snoop;
+mod splice;
+mod stats;
+mod status;
+mod sti;
+mod supervise;
+mod tap;
+mod title;
+mod tmux;
+#[cfg(target_os = "linux")]
+mod trace;
+mod transcript;
+mod utf8;
+mod utmp;
+mod xmodem;
+
+use capture::{Reading, Recorder};
+use cli::{Cli, Cmd, RecordArgs, RunArgs};
+use config::Config;
+use editor::LineEditor;
+use input::{LineEnding, ParseError};
+use latency::EchoTracker;
+use macros::{Macros, Step};
+use stats::Stats;
+
 use libc::c_int;
 use nix::errno::Errno;
-use nix::fcntl::{fcntl, FcntlArg, FdFlag};
-use nix::pty::OpenptyResult;
+use nix::fcntl::{fcntl, FcntlArg, FdFlag, OFlag};
+use nix::pty::{OpenptyResult, Winsize};
 
 use termios::Termios;
 
-use dotenvy::Error as DotError;
+use tracing_subscriber::EnvFilter;
+
+use clap::error::ErrorKind as ClapErrorKind;
+use clap::{CommandFactory as _, Parser as _};
 
 use std::ffi::OsStr;
-use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::fs::File;
+use std::io::{BufRead as _, Error as IoError, ErrorKind as IoErrorKind, IsTerminal as _};
 use std::os::fd::AsRawFd as _;
 use std::os::fd::FromRawFd as _;
-use std::os::fd::RawFd;
+use std::os::fd::IntoRawFd as _;
+use std::os::fd::{BorrowedFd, OwnedFd, RawFd};
 use std::os::unix::process::CommandExt as _;
-use std::process::{Command, Stdio};
+use std::os::unix::process::ExitStatusExt as _;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitCode, Stdio};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 struct Args {
     shell: String,
     mode: WriterMode,
+    echo_latency: bool,
+    status: bool,
+    escapes: bool,
+    line_ending: LineEnding,
+    injection: Injection,
+    alloc: alloc::Strategy,
+    winsize: Option<Winsize>,
+    termios: Vec<String>,
+    env: Vec<(String, String)>,
+    inherit_env: env::Inherit,
+    env_files: Option<Vec<PathBuf>>,
+    cwd: Option<PathBuf>,
+    chroot: Option<PathBuf>,
+    login: bool,
+    exec: Vec<String>,
+    command: Vec<String>,
+    ssh: Option<String>,
+    container: Option<(&'static str, String)>,
+    scope: Option<String>,
+    scope_properties: Vec<String>,
+    credentials: Option<child::Credentials>,
+    unshare: c_int,
+    rlimits: Vec<child::Rlimit>,
+    utmp: bool,
+    subreaper: bool,
+    trace_child: bool,
+    filters: Vec<String>,
+    hooks: hooks::Hooks,
+    metrics: Option<String>,
+    tls: Option<Arc<rustls::ServerConfig>>,
+    inside_report: bool,
+    self_test: bool,
+    print_pts: bool,
+    nonblock: bool,
+    readers: usize,
+    read_buffer: Option<usize>,
+    read_strategy: rawio::Strategy,
+    raw_log: Option<PathBuf>,
+    lines: Option<Duration>,
+    classify_echo: bool,
+    ldisc_notes: bool,
+    baseline: Option<baseline::Baseline>,
+    baseline_strict: bool,
+    prompt_detect: Option<prompts::Detect>,
+    reset_on_exit: bool,
+    host_title: bool,
+    escape_char: Option<u8>,
+    macros: Macros,
 }
 
 impl Args {
-    fn from_command_line() -> Option<Self> {
-        let mut args = std::env::args().skip(1);
+    fn resolve(run: &RunArgs) -> Result<Self, String> {
+        let config = Config::load(run.config.as_deref())
+            .map_err(|e| format!("could not load the config file {e}"))?;
+        let mut args = Self::from_config(config, run.profile.as_deref(), run.settings())
+            .map_err(|e| format!("invalid settings: {e}"))?;
+        args.command = run.command.clone();
+        // Only when it is run here, a chroot having programs of its own.
+        let local = args.ssh.is_none() && args.container.is_none() && args.chroot.is_none();
+        if local && args.exec.is_empty() && !args.self_test {
+            let shell = child::find_program(&args.shell).map_err(|e| format!("--shell {e}"))?;
+            args.shell = shell.to_string_lossy().into_owned();
+        }
+        Ok(args)
+    }
 
-        let mut shell: Option<String> = None;
-        let mut mode = WriterMode::String;
+    fn from_config(
+        mut config: Config,
+        profile: Option<&str>,
+        overrides: config::Settings,
+    ) -> Result<Self, String> {
+        let settings = overrides.or(config.take_settings(profile)?);
+        let mode = config::setting("mode", settings.mode.as_deref(), WriterMode::parse)?;
+        let line_ending = config::setting(
+            "line-ending",
+            settings.line_ending.as_deref(),
+            LineEnding::parse,
+        )?;
+        let injection = config::setting("inject", settings.inject.as_deref(), Injection::parse)?;
+        let alloc = config::setting("alloc", settings.alloc.as_deref(), alloc::Strategy::parse)?;
+        let winsize = config::setting("winsize", settings.winsize.as_deref(), parse_winsize)?;
+        let env = settings
+            .env
+            .unwrap_or_default()
+            .iter()
+            .map(|var| env::parse_var(var).ok_or_else(|| format!("invalid env `{var}`")))
+            .collect::<Result<_, _>>()?;
+        let inherit_env = match (settings.inherit_env, settings.inherit_env_only) {
+            (Some(true), _) => env::Inherit::All,
+            (_, Some(patterns)) => env::Inherit::Only(patterns),
+            _ => env::Inherit::Nothing,
+        };
 
-        while let Some(arg) = args.next() {
-            if arg == "--shell" {
-                if let Some(arg) = args.next() {
-                    shell = Some(arg);
-                } else {
-                    break;
+        let mut macros = Macros::default();
+        for (name, value) in &config.aliases {
+            macros.set_alias(name, value);
+        }
+        for (name, value) in &config.macros {
+            macros
+                .set_macro(name, value)
+                .map_err(|e| format!("macro {name}: {e}"))?;
+        }
+
+        Ok(Self {
+            shell: settings.shell.unwrap_or_else(env::default_shell),
+            mode: mode.unwrap_or(WriterMode::String),
+            echo_latency: settings.echo_latency.unwrap_or(false),
+            status: settings.status.unwrap_or(false),
+            escapes: settings.escapes.unwrap_or(true),
+            line_ending: line_ending.unwrap_or(LineEnding::Lf),
+            injection: injection.unwrap_or(Injection::Master),
+            alloc: alloc.unwrap_or(alloc::Strategy::Openpty),
+            winsize,
+            termios: settings.termios.unwrap_or_default(),
+            env,
+            inherit_env,
+            env_files: settings
+                .env_files
+                .map(|files| files.into_iter().map(PathBuf::from).collect()),
+            cwd: settings.cwd.map(PathBuf::from),
+            chroot: settings.chroot.map(PathBuf::from),
+            login: settings.login.unwrap_or(false),
+            exec: settings.exec.unwrap_or_default(),
+            command: Vec::new(),
+            ssh: settings.ssh,
+            container: match (settings.docker, settings.podman) {
+                (Some(_), Some(_)) => return Err("both docker and podman are set".to_string()),
+                (Some(name), None) => Some(("docker", name)),
+                (None, Some(name)) => Some(("podman", name)),
+                (None, None) => None,
+            },
+            scope: settings.scope.map(|unit| match unit.as_str() {
+                "" => format!("debug-pty-{}", std::process::id()),
+                _ => unit,
+            }),
+            scope_properties: settings.scope_property.unwrap_or_default(),
+            credentials: child::Credentials::resolve(
+                settings.user.as_deref(),
+                settings.uid,
+                settings.gid,
+            )?,
+            unshare: child::parse_namespaces(&settings.unshare.unwrap_or_default())?,
+            rlimits: settings
+                .rlimit
+                .unwrap_or_default()
+                .iter()
+                .map(|spec| child::Rlimit::parse(spec))
+                .collect::<Result<_, _>>()?,
+            utmp: settings.utmp.unwrap_or(false),
+            subreaper: settings.subreaper.unwrap_or(false),
+            trace_child: match settings.trace_child {
+                Some(true) if cfg!(not(target_os = "linux")) => {
+                    return Err("--trace-child only works on Linux".to_string())
                 }
-            } else if arg == "--mod" {
-                if let Some(arg) = args.next() {
-                    if arg == "str" {
-                        mode = WriterMode::String;
-                    } else if arg == "bytes" {
-                        mode = WriterMode::Bytes;
-                    }
-                } else {
-                    break;
+                trace_child => trace_child.unwrap_or(false),
+            },
+            filters: settings.filters.unwrap_or_default(),
+            hooks: hooks::Hooks::new(std::mem::take(&mut config.hooks))?,
+            tls: match (settings.tls_cert, settings.tls_key) {
+                (Some(cert), Some(key)) if settings.metrics.is_some() => Some(
+                    metrics::tls_config(Path::new(&cert), Path::new(&key))
+                        .map_err(|e| e.to_string())?,
+                ),
+                (None, None) => None,
+                _ => {
+                    return Err(
+                        "--tls-cert and --tls-key go together, and need --metrics".to_string()
+                    )
                 }
-            } else if arg == "--help" {
-                print_help();
-                return None;
+            },
+            metrics: settings.metrics,
+            inside_report: settings.inside_report.unwrap_or(false),
+            self_test: settings.self_test.unwrap_or(false),
+            print_pts: settings.print_pts.unwrap_or(false),
+            nonblock: settings.nonblock.unwrap_or(false),
+            readers: settings.readers.unwrap_or(1),
+            read_buffer: settings.read_buffer,
+            read_strategy: config::setting(
+                "read_strategy",
+                settings.read_strategy.as_deref(),
+                rawio::Strategy::parse,
+            )?
+            .unwrap_or(rawio::Strategy::Once),
+            raw_log: settings.raw_log.map(PathBuf::from),
+            lines: config::setting("lines", settings.lines.as_deref(), input::parse_duration)?,
+            classify_echo: settings.classify_echo.unwrap_or(false),
+            ldisc_notes: settings.ldisc_notes.unwrap_or(false),
+            baseline: match settings.baseline {
+                Some(specs) => Some(baseline::Baseline::parse(&specs)?),
+                None => None,
+            },
+            baseline_strict: settings.baseline_strict.unwrap_or(false),
+            prompt_detect: config::setting(
+                "prompt_detect",
+                settings.prompt_detect.as_deref(),
+                prompts::Detect::parse,
+            )?,
+            reset_on_exit: settings.reset_on_exit.unwrap_or(false),
+            escape_char: match settings.escape_char.as_deref() {
+                None => Some(0x1d),
+                Some("none") => None,
+                Some(key) => match input::unescape_keys(key).as_slice() {
+                    &[key] => Some(key),
+                    _ => return Err(format!("invalid escape_char {key:?}, expected one byte")),
+                },
+            },
+            host_title: match settings.host_title.as_deref() {
+                None | Some("pass") => true,
+                Some("drop") => false,
+                Some(other) => return Err(format!("invalid host_title {other:?}")),
+            },
+            macros,
+        })
+    }
+
+    // Several commands are typed into the shell instead, by transcript::run.
+    fn single_command(&self) -> Option<&String> {
+        match self.command.as_slice() {
+            [command] => Some(command),
+            _ => None,
+        }
+    }
+
+    fn argv(&self) -> Vec<String> {
+        let argv = self.target_argv();
+        let Some(unit) = &self.scope else {
+            return argv;
+        };
+        // systemd-run --scope execs the program itself once in the scope, so it stays on
+        // the slave as our child.
+        let mut scope = vec![
+            "systemd-run".to_string(),
+            "--scope".to_string(),
+            "--quiet".to_string(),
+            format!("--unit={unit}"),
+        ];
+        if !nix::unistd::Uid::effective().is_root() {
+            scope.push("--user".to_string());
+        }
+        for property in &self.scope_properties {
+            scope.push(format!("--property={property}"));
+        }
+        scope.push("--".to_string());
+        scope.extend(argv);
+        scope
+    }
+
+    fn target_argv(&self) -> Vec<String> {
+        if let Some(host) = &self.ssh {
+            // -tt: a pty there, whatever ssh makes of its own stdin.
+            let mut argv = ["ssh", "-tt", host, "--"].map(String::from).to_vec();
+            if let Some(command) = self.single_command() {
+                argv.push(command.clone());
+            } else if !self.exec.is_empty() {
+                argv.push(
+                    self.exec
+                        .iter()
+                        .map(|arg| shell_quote(arg))
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                );
+            }
+            argv
+        } else if let Some((runtime, name)) = &self.container {
+            let mut argv = [*runtime, "exec", "-it"].map(String::from).to_vec();
+            for (key, value) in &self.env {
+                argv.extend(["-e".to_string(), format!("{key}={value}")]);
+            }
+            argv.push(name.clone());
+            if let Some(command) = self.single_command() {
+                argv.extend(["sh", "-c", command.as_str()].map(String::from));
+            } else if self.exec.is_empty() {
+                argv.push("sh".to_string());
+            } else {
+                argv.extend(self.exec.iter().cloned());
             }
+            argv
+        } else if self.self_test {
+            vec![self_exe(), "self-test".to_string()]
+        } else if let Some(command) = self.single_command() {
+            vec![self.shell.clone(), "-c".to_string(), command.clone()]
+        } else if self.exec.is_empty() {
+            vec![self.shell.clone()]
+        } else {
+            self.exec.clone()
+        }
+    }
+}
+
+// The remote side runs ssh's command through a shell, so the arguments must survive one.
+fn shell_quote(arg: &str) -> String {
+    if !arg.is_empty()
+        && arg
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-_./=:,@%+".contains(&b))
+    {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+fn parse_winsize(s: &str) -> Option<Winsize> {
+    let (rows, cols) = s.split_once('x')?;
+    Some(Winsize {
+        ws_row: rows.parse().ok()?,
+        ws_col: cols.parse().ok()?,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    })
+}
+
+fn main() -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+    init_logging(cli.log_filter.as_deref())?;
+    let resolve = |run: &RunArgs| match Args::resolve(run) {
+        Ok(args) => args,
+        Err(e) => Cli::command().error(ClapErrorKind::InvalidValue, e).exit(),
+    };
+
+    match cli.command {
+        None => run_session(resolve(&cli.run), None, None),
+        Some(Cmd::Run(run)) => run_session(resolve(&run), None, None),
+        Some(Cmd::Record(record)) => {
+            check_upload(&record);
+            let code = run_session(resolve(&record.run), None, Some(&record))?;
+            upload(&record)?;
+            Ok(code)
+        }
+        Some(Cmd::Wrap(wrap)) => {
+            check_upload(&wrap);
+            let code = mitm::run(resolve(&wrap.run), &wrap)?;
+            upload(&wrap)?;
+            Ok(code)
+        }
+        Some(Cmd::Replay(replay)) if replay.rerun => {
+            rerun::run(&replay.file, replay.speed, resolve(&replay.run))
+        }
+        Some(Cmd::Replay(replay)) if replay.follow => {
+            capture::follow(&replay.file)?;
+            Ok(ExitCode::SUCCESS)
+        }
+        Some(Cmd::Replay(replay)) if replay.markers => {
+            capture::markers(&replay.file)?;
+            Ok(ExitCode::SUCCESS)
+        }
+        Some(Cmd::Replay(replay)) => {
+            capture::replay(
+                &replay.file,
+                replay.speed,
+                !replay.no_delay,
+                replay.from.as_deref(),
+            )?;
+            Ok(ExitCode::SUCCESS)
+        }
+        Some(Cmd::Search(search)) => Ok(if search::run(&search)? {
+            ExitCode::SUCCESS
+        } else {
+            ExitCode::FAILURE
+        }),
+        Some(Cmd::Split(split)) => {
+            chapters::run(&split)?;
+            Ok(ExitCode::SUCCESS)
+        }
+        Some(Cmd::Probe(run)) => {
+            probe(&resolve(&run))?;
+            Ok(ExitCode::SUCCESS)
+        }
+        Some(Cmd::List) => {
+            holders::list()?;
+            Ok(ExitCode::SUCCESS)
+        }
+        #[cfg(target_os = "linux")]
+        Some(Cmd::Snoop(snoop)) => {
+            snoop::run(snoop.pid)?;
+            Ok(ExitCode::SUCCESS)
+        }
+        #[cfg(target_os = "linux")]
+        Some(Cmd::Bpf(bpf)) => {
+            bpf::run(&bpf.tty)?;
+            Ok(ExitCode::SUCCESS)
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(Cmd::Snoop(_) | Cmd::Bpf(_)) => Err("snoop and bpf only work on Linux".into()),
+        Some(Cmd::Bench(bench)) => {
+            bench::run(&bench, &resolve(&bench.run))?;
+            Ok(ExitCode::SUCCESS)
+        }
+        Some(Cmd::Test(test)) => {
+            let script = std::fs::read_to_string(&test.script)?;
+            let steps = macros::parse_script(&script, &cli::key_values(&test.vars))
+                .map_err(|e| format!("{}: {e}", test.script.display()))?;
+            run_session(resolve(&test.run), Some(steps), None)
+        }
+        Some(Cmd::Compare(compare)) => compare::run(&compare, resolve(&compare.run)),
+        Some(Cmd::Daemon(daemon)) => daemon::serve(resolve(&daemon.run), &daemon),
+        Some(Cmd::Attach(attach)) => daemon::attach(&attach),
+        Some(Cmd::Tmux(tmux)) => tmux::run(&tmux),
+        Some(Cmd::Ingest(ingest)) => {
+            ingest::run(&ingest)?;
+            Ok(ExitCode::SUCCESS)
         }
+        Some(Cmd::InsideReport(inside)) => Err(inside::run(&inside).into()),
+        Some(Cmd::SelfTest) => {
+            selftest::run()?;
+            Ok(ExitCode::SUCCESS)
+        }
+        Some(Cmd::PtySetup) => {
+            alloc::diagnose()?;
+            Ok(ExitCode::SUCCESS)
+        }
+        Some(Cmd::Defaults(defaults)) => {
+            defaults::run(defaults.compare)?;
+            Ok(ExitCode::SUCCESS)
+        }
+        Some(Cmd::Doctor) => Ok(doctor::run()),
+    }
+}
+
+fn check_upload(record: &RecordArgs) {
+    if record.upload && record.format() != capture::Format::Cast {
+        let message = "--upload needs --format cast";
+        Cli::command()
+            .error(ClapErrorKind::ArgumentConflict, message)
+            .exit();
+    }
+}
 
-        let shell = shell.unwrap_or("/bin/bash".to_string());
-        Some(Self { shell, mode })
+fn upload(record: &RecordArgs) -> Result<(), Box<dyn std::error::Error>> {
+    if record.upload {
+        let link = cast::upload(
+            &record.output,
+            &record.upload_url,
+            record.upload_token.as_deref(),
+        )?;
+        println!("Uploaded to {link}");
     }
+    Ok(())
+}
+
+// The tool's own diagnostics, on stderr so that they stay apart from the pty log.
+fn init_logging(filter: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    let filter = match filter {
+        Some(filter) => EnvFilter::try_new(filter)?,
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("warn")),
+    };
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .init();
+    Ok(())
 }
 
-fn print_help() {
-    println!("cargo run [ -- [--shell SHELL] [--mod [str|bytes]] ]");
+fn setup_pty(args: &Args) -> Result<(OpenptyResult, Termios), Box<dyn std::error::Error>> {
+    let pty = open_pty(args.alloc, args.winsize.as_ref())?;
+    let mut term = termios::Termios::from_fd(pty.master.as_raw_fd())?;
+    if !args.termios.is_empty() {
+        for spec in &args.termios {
+            flags::apply(&mut term, spec)?;
+        }
+        termios::tcsetattr(pty.master.as_raw_fd(), termios::TCSANOW, &term)?;
+    }
+    Ok((pty, term))
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    if let Some(args) = Args::from_command_line() {
-        let OpenptyResult { master, slave } = open_pty()?;
-        let mut term = termios::Termios::from_fd(master.as_raw_fd())?;
-        debug_termios(&term);
+fn probe(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    let (OpenptyResult { master, slave }, term) = setup_pty(args)?;
+
+    let slave_path = nix::unistd::ttyname(slave.as_raw_fd())?;
+    println!("Slave {}", slave_path.display());
 
-        let env = match dotenvy::dotenv_iter() {
-            Ok(env) => {
-                let env: Result<Vec<_>, _> = env.collect();
-                env?
+    let mut winsize: Winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCGWINSZ, &mut winsize) } == -1 {
+        return Err(IoError::last_os_error().into());
+    }
+    println!("Winsize {}x{}", winsize.ws_row, winsize.ws_col);
+
+    debug_termios(&term);
+    Ok(())
+}
+
+fn run_session(
+    mut args: Args,
+    script: Option<Vec<Step>>,
+    capture: Option<&RecordArgs>,
+) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let (OpenptyResult { master, slave }, term) = setup_pty(&args)?;
+    debug_termios(&term);
+
+    if args.nonblock {
+        let flags = fcntl(master.as_raw_fd(), FcntlArg::F_GETFL)?;
+        let flags = OFlag::from_bits_truncate(flags) | OFlag::O_NONBLOCK;
+        fcntl(master.as_raw_fd(), FcntlArg::F_SETFL(flags))?;
+        println!("Master is non-blocking");
+    }
+    let slave_path = match alloc::ptsname(master.as_raw_fd()) {
+        Ok(path) => {
+            println!("Slave {}", path.display());
+            Some(path)
+        }
+        Err(e) => {
+            tracing::warn!("could not resolve the slave: {e}");
+            None
+        }
+    };
+    if args.print_pts {
+        // Holding the master is all it takes for screen, minicom and the like to attach.
+        loop {
+            std::thread::park();
+        }
+    }
+
+    let span = tracing::info_span!("session", argv = %args.argv().join(" "));
+    let _enter = span.enter();
+
+    let read_buffer = args.read_buffer.unwrap_or(1024);
+    // Not O_APPEND, which splice refuses; the readers share the offset instead.
+    let raw_log = match &args.raw_log {
+        Some(path) => Some(File::create(path).map_err(|e| format!("{}: {e}", path.display()))?),
+        None => None,
+    };
+    let recorder = match capture {
+        Some(record) => Some(Mutex::new(Recorder::create(
+            &record.output,
+            record.format(),
+            &args.argv().join(" "),
+            slave_path.as_deref(),
+            Some(Reading {
+                buffer: read_buffer,
+                strategy: args.read_strategy.name().to_string(),
+            }),
+            Some(metadata(&args, master.as_raw_fd(), Some(record))?),
+        )?)),
+        None => None,
+    };
+    if let (Some(recorder), Some(size)) = (&recorder, &args.winsize) {
+        recorder.lock().unwrap().resize(size.ws_row, size.ws_col);
+    }
+
+    let middleware = Mutex::new(middleware::Pipeline::build(&args.filters)?);
+    let watch_limits = !args.rlimits.is_empty();
+    let mut child = spawn_child(&mut args, slave)?;
+
+    let utmp = match (&slave_path, args.utmp) {
+        (Some(slave), true) => match utmp::Registration::new(slave, child.id()) {
+            Ok(registration) => {
+                println!("UTMP entry added for {}", slave.display());
+                Some(registration)
             }
-            Err(DotError::Io(e)) => {
-                if matches!(e.kind(), IoErrorKind::NotFound) {
-                    Vec::new()
-                } else {
-                    return Err(e.into());
-                }
+            Err(e) => {
+                tracing::warn!("could not add a utmp entry: {e}");
+                None
             }
-            Err(e) => return Err(e.into()),
+        },
+        _ => None,
+    };
+
+    let session = Arc::new(Session {
+        stats: Mutex::new(Stats::new()),
+        echo: args.echo_latency.then(|| Mutex::new(EchoTracker::new())),
+        tap: Mutex::new(None),
+        injection: args.injection,
+        slave_path,
+        recorder,
+        watch_limits,
+        lifecycle: Mutex::new(supervise::Lifecycle::new()),
+        middleware,
+        hooks: std::mem::take(&mut args.hooks),
+        lines: args
+            .lines
+            .map(|timeout| Mutex::new(lines::Assembler::new(timeout))),
+        classifier: args
+            .classify_echo
+            .then(|| Mutex::new(classify::Classifier::new())),
+        ldisc_notes: args.ldisc_notes,
+        baseline: args.baseline.take().map(Mutex::new),
+        read_buffer,
+        read_strategy: args.read_strategy,
+        raw_log,
+        modes: Mutex::new(modes::Tracker::new()),
+        titles: Mutex::new(title::Titles::new()),
+        regions: Mutex::new(regions::Regions::default()),
+        prompts: args
+            .prompt_detect
+            .take()
+            .map(|detect| Mutex::new(prompts::Segmenter::new(detect))),
+    });
+    session.hooks.spawned(child.id());
+
+    supervise::spawn_supervisor(child.id(), session.clone());
+
+    if let Some(addr) = &args.metrics {
+        metrics::spawn_metrics(addr, args.tls.clone(), 1, session.clone())?;
+    }
+    if args.readers > 1 {
+        // Separate fds on the same open file, like programs that inherited the master.
+        for id in 0..args.readers {
+            let fd = master.try_clone()?.into_raw_fd();
+            spawn_reader(fd, Some(id), session.clone());
+        }
+    } else {
+        spawn_reader(master.as_raw_fd(), None, session.clone());
+    }
+    jobs::spawn_pgrp_monitor(master.as_raw_fd(), session.clone());
+    if session.lines.is_some() {
+        lines::spawn_flusher(session.clone());
+    }
+    if args.status {
+        status::spawn_status(master.as_raw_fd(), child.id(), session.clone());
+    }
+
+    let writer = Writer {
+        mode: args.mode,
+        escapes: args.escapes,
+        line_ending: args.line_ending,
+        macros: args.macros,
+    };
+
+    let mut code = ExitCode::SUCCESS;
+    let mut last_status = None;
+    if let Some(steps) = script {
+        let outcome = macros::run_script(&steps, master.as_raw_fd(), &writer, &session)?;
+        if outcome.failed {
+            code = ExitCode::FAILURE;
+        }
+        if outcome.failed || !outcome.exit {
+            wait_or_kill(&mut child, Duration::from_secs(5))?;
+        }
+    } else if args.command.len() > 1 {
+        let steps = transcript::run(&args.command, master.as_raw_fd(), &session)?;
+        transcript::report(&steps);
+        last_status = steps.last().and_then(|step| step.status);
+        if steps.len() < args.command.len() {
+            wait_or_kill(&mut child, Duration::from_secs(5))?;
+        }
+    } else if args.command.is_empty() {
+        write_loop(master.as_raw_fd(), writer, &session)?;
+    }
+
+    let status = child.wait()?;
+    drop(utmp);
+    session.hooks.exited(&status.to_string(), status.code());
+    if let Some(limit) = status.signal().and_then(child::limit_signal) {
+        println!("RLIMIT child killed by {limit}");
+    }
+    if !args.command.is_empty() {
+        println!("Child exited with {status}");
+        let status = match args.command.len() {
+            1 => status.code(),
+            _ => last_status,
         };
+        code = ExitCode::from(status.unwrap_or(1) as u8);
+    }
+
+    supervise::wait_drained(&session, Duration::from_secs(1));
+    session.record(|rec| {
+        rec.exit(&status);
+        rec.finish();
+    });
+    if let Some(lines) = &session.lines {
+        lines.lock().unwrap().flush();
+    }
+
+    session.stats.lock().unwrap().report();
+    if let Some(prompts) = &session.prompts {
+        prompts.lock().unwrap().report();
+    }
+    if let Some(echo) = &session.echo {
+        echo.lock().unwrap().report();
+    }
+    report_unbalanced(&term, master.as_raw_fd(), &session);
+    if let Some(baseline) = &session.baseline {
+        if baseline.lock().unwrap().finish() && args.baseline_strict {
+            code = ExitCode::FAILURE;
+        }
+    }
 
-        let mut cmd = build_cmd(&args.shell, slave.as_raw_fd(), env);
+    Ok(code)
+}
 
-        let mut child = cmd.spawn()?;
-        drop(slave);
-        println!("Child PID {}", child.id());
+const SSH_ENV: &[&str] = &["HOME", "USER", "SSH_AUTH_SOCK", "PATH"];
+const SCOPE_ENV: &[&str] = &["XDG_RUNTIME_DIR", "DBUS_SESSION_BUS_ADDRESS"];
+const CONTAINER_ENV: &[&str] = &[
+    "HOME",
+    "USER",
+    "PATH",
+    "XDG_RUNTIME_DIR",
+    "DOCKER_HOST",
+    "DOCKER_CONFIG",
+    "DOCKER_CONTEXT",
+    "CONTAINER_HOST",
+];
 
-        spawn_reader(master.as_raw_fd());
+fn child_env(args: &Args) -> Result<Vec<(String, String)>, Box<dyn std::error::Error>> {
+    let mut env = env::compose(
+        &args.inherit_env,
+        &args.shell,
+        env::load_files(args.env_files.as_deref())?,
+        &args.env,
+    );
+    // ssh, the container runtime or systemd-run finds its keys, agent, daemon or bus
+    // through these, whatever the program is meant to see.
+    let needed = if args.ssh.is_some() {
+        SSH_ENV
+    } else if args.container.is_some() {
+        CONTAINER_ENV
+    } else {
+        &[]
+    };
+    let scope: &[&str] = if args.scope.is_some() { SCOPE_ENV } else { &[] };
+    for name in needed.iter().chain(scope) {
+        if let Ok(value) = std::env::var(name) {
+            env.insert(0, (name.to_string(), value));
+        }
+    }
+    if let Some(prompts::Detect::Sentinel) = &args.prompt_detect {
+        let mut command = prompts::SENTINEL_COMMAND.to_string();
+        let existing = env.iter().rev().find(|(name, _)| name == "PROMPT_COMMAND");
+        if let Some(value) = existing
+            .map(|(_, value)| value.clone())
+            .or_else(|| std::env::var("PROMPT_COMMAND").ok())
+        {
+            command = format!("{command}; {value}");
+        }
+        env.push(("PROMPT_COMMAND".to_string(), command));
+    }
+    Ok(env)
+}
 
-        write_loop(master.as_raw_fd(), args.mode)?;
+fn metadata(
+    args: &Args,
+    master: RawFd,
+    record: Option<&RecordArgs>,
+) -> Result<capture::Metadata, Box<dyn std::error::Error>> {
+    let (redacted, env) = child_env(args)?
+        .into_iter()
+        .partition::<Vec<_>, _>(|(name, _)| {
+            env::is_secret(name) && !record.is_some_and(|record| record.capture_env)
+        });
+    Ok(capture::Metadata {
+        command: args.argv(),
+        env,
+        redacted: redacted.into_iter().map(|(name, _)| name).collect(),
+        termios: Termios::from_fd(master)
+            .ok()
+            .map(|term| flags::Snapshot::of(&term)),
+        winsize: mitm::winsize(master).map(|size| (size.ws_row, size.ws_col)),
+        platform: format!("{} {}", std::env::consts::OS, std::env::consts::ARCH),
+        title: record.and_then(|record| record.title.clone()),
+        labels: record.map(RecordArgs::labels).unwrap_or_default(),
+        unit: args.scope.as_ref().map(|unit| format!("{unit}.scope")),
+    })
+}
 
-        child.wait()?;
+fn spawn_child(args: &mut Args, slave: OwnedFd) -> Result<Child, Box<dyn std::error::Error>> {
+    let _span = tracing::info_span!("child").entered();
+    let env = child_env(args)?;
 
-        std::thread::sleep(std::time::Duration::from_millis(1000));
+    let argv = args.argv();
+    let mut cmd = if args.inside_report {
+        // The report runs as the child itself, on the slave, and then execs argv.
+        let mut cmd = build_cmd(self_exe(), slave.as_raw_fd(), args.alloc, env)?;
+        cmd.arg("inside-report");
+        if args.login {
+            cmd.arg("--arg0").arg(login_arg0(&argv[0]));
+        }
+        cmd.arg("--").args(&argv);
+        cmd
+    } else {
+        let mut cmd = build_cmd(&argv[0], slave.as_raw_fd(), args.alloc, env)?;
+        cmd.args(&argv[1..]);
+        if args.login {
+            cmd.arg0(login_arg0(&argv[0]));
+        }
+        cmd
+    };
+    let flags = args.unshare;
+    if flags != 0 {
+        child::check_namespaces()?;
+        unsafe {
+            cmd.pre_exec(move || child::unshare(flags));
+        }
+    }
+    if let Some(root) = &args.chroot {
+        // The working directory is only reachable once inside, so it is the chroot step
+        // that changes to it.
+        let root = root
+            .canonicalize()
+            .map_err(|e| format!("{}: {e}", root.display()))?;
+        let missing = child::check_root(&root, &argv[0])?;
+        let cwd = args.cwd.clone().unwrap_or_else(|| PathBuf::from("/"));
+        println!("Chroot {}", root.display());
+        for path in missing {
+            println!("Chroot lacks {path}");
+        }
+        println!("Working directory {}", cwd.display());
+        unsafe {
+            cmd.pre_exec(move || child::chroot(&root, &cwd));
+        }
+    } else {
+        let cwd = match &args.cwd {
+            Some(cwd) => cwd
+                .canonicalize()
+                .map_err(|e| format!("{}: {e}", cwd.display()))?,
+            None => std::env::current_dir()?,
+        };
+        println!("Working directory {}", cwd.display());
+        cmd.current_dir(&cwd);
+    }
+    if flags != 0 {
+        unsafe {
+            cmd.pre_exec(move || child::mount_proc(flags));
+        }
+    }
+    if args.login {
+        println!("Login shell as {}", login_arg0(&argv[0]));
+    }
+
+    if !args.rlimits.is_empty() {
+        let limits: Vec<_> = args.rlimits.iter().map(child::Rlimit::describe).collect();
+        println!("Limits {}", limits.join(" "));
+        let rlimits = std::mem::take(&mut args.rlimits);
+        unsafe {
+            cmd.pre_exec(move || rlimits.iter().try_for_each(child::Rlimit::apply));
+        }
+    }
+    if let Some(credentials) = args.credentials.take() {
+        println!("Credentials {}", credentials.describe());
+        unsafe {
+            cmd.pre_exec(move || credentials.apply());
+        }
+    }
+
+    if args.subreaper {
+        reaper::enable()?;
+    }
+    #[cfg(target_os = "linux")]
+    if args.trace_child {
+        // Runs last, after the other pre_exec steps, right before exec.
+        unsafe {
+            cmd.pre_exec(trace::stop_before_exec);
+        }
     }
 
+    let child = cmd
+        .spawn()
+        .map_err(|e| format!("could not start {}: {e}", argv[0]))?;
+    drop(slave);
+    println!("Child PID {}", child.id());
+    tracing::debug!(pid = child.id(), ?argv, "spawned");
+    if args.subreaper {
+        reaper::spawn_reaper(child.id());
+    }
+    #[cfg(target_os = "linux")]
+    if args.trace_child {
+        trace::spawn_tracer(child.id());
+    }
+
+    Ok(child)
+}
+
+// For the helper modes that run this binary again as the child.
+fn self_exe() -> String {
+    match std::env::current_exe() {
+        Ok(path) => path.to_string_lossy().into_owned(),
+        Err(_) => std::env::args().next().unwrap_or_default(),
+    }
+}
+
+// What login(1) does: argv[0] is the basename with a leading dash.
+fn login_arg0(program: &str) -> String {
+    let name = Path::new(program).file_name().unwrap_or_default();
+    format!("-{}", name.to_string_lossy())
+}
+
+// Whatever the child changed and left that way is what breaks the next program on the
+// same terminal.
+fn report_unbalanced(initial: &Termios, master: RawFd, session: &Session) {
+    let mut unbalanced = session.modes.lock().unwrap().unbalanced();
+    if let Ok(term) = Termios::from_fd(master) {
+        unbalanced.extend(
+            flags::diff(initial, &term)
+                .into_iter()
+                .map(|change| format!("termios {change} never undone")),
+        );
+    }
+    if unbalanced.is_empty() {
+        println!("UNBALANCED none, every mode and termios flag was restored");
+    }
+    for text in unbalanced {
+        println!("UNBALANCED {text}");
+    }
+}
+
+fn wait_or_kill(child: &mut Child, timeout: Duration) -> Result<(), IoError> {
+    let start = Instant::now();
+    while child.try_wait()?.is_none() {
+        if start.elapsed() >= timeout {
+            tracing::info!("child still running after {timeout:?}, killing it");
+            return child.kill();
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
     Ok(())
 }
 
-fn open_pty() -> Result<OpenptyResult, Errno> {
-    use nix::pty::openpty;
+struct Session {
+    stats: Mutex<Stats>,
+    echo: Option<Mutex<EchoTracker>>,
+    tap: Mutex<Option<Sender<Vec<u8>>>>,
+    injection: Injection,
+    slave_path: Option<PathBuf>,
+    recorder: Option<Mutex<Recorder>>,
+    watch_limits: bool,
+    lifecycle: Mutex<supervise::Lifecycle>,
+    middleware: Mutex<middleware::Pipeline>,
+    hooks: hooks::Hooks,
+    lines: Option<Mutex<lines::Assembler>>,
+    classifier: Option<Mutex<classify::Classifier>>,
+    ldisc_notes: bool,
+    baseline: Option<Mutex<baseline::Baseline>>,
+    read_buffer: usize,
+    read_strategy: rawio::Strategy,
+    raw_log: Option<File>,
+    modes: Mutex<modes::Tracker>,
+    titles: Mutex<title::Titles>,
+    regions: Mutex<regions::Regions>,
+    prompts: Option<Mutex<prompts::Segmenter>>,
+}
+
+impl Session {
+    fn record(&self, f: impl FnOnce(&mut Recorder)) {
+        if let Some(recorder) = &self.recorder {
+            f(&mut recorder.lock().unwrap());
+        }
+    }
+
+    fn sent(&self, bytes: &[u8]) {
+        if let Some(classifier) = &self.classifier {
+            classifier.lock().unwrap().sent(bytes);
+        }
+        if let Some(prompts) = &self.prompts {
+            prompts.lock().unwrap().input(bytes);
+        }
+    }
 
-    let pty = openpty(None, None)?;
+    fn segmented(&self, events: Vec<prompts::Event>) {
+        for event in events {
+            println!("{}", event.describe());
+            self.record(|rec| event.record(rec));
+        }
+    }
+}
+
+enum Injection {
+    Master,
+    Tiocsti,
+}
+
+impl Injection {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "master" => Some(Self::Master),
+            "tiocsti" => Some(Self::Tiocsti),
+            _ => None,
+        }
+    }
+}
+
+fn open_pty(
+    alloc: alloc::Strategy,
+    winsize: Option<&Winsize>,
+) -> Result<OpenptyResult, Box<dyn std::error::Error>> {
+    let pty = alloc::open(alloc, winsize)?;
     fcntl(
         pty.master.as_raw_fd(),
         FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC),
@@ -116,55 +1089,148 @@ fn open_pty() -> Result<OpenptyResult, Errno> {
 fn build_cmd(
     shell: impl AsRef<OsStr>,
     slave: RawFd,
+    alloc: alloc::Strategy,
     env: impl IntoIterator<Item = (String, String)>,
-) -> Command {
+) -> Result<Command, IoError> {
+    // login_tty, which forkpty runs in the child, takes the controlling terminal through
+    // the slave fd it was given rather than through stdin.
+    let ctty = match alloc {
+        alloc::Strategy::Forkpty => slave,
+        _ => 0,
+    };
+    // A descriptor each, as the Command closes them when it goes and the caller closes the
+    // slave itself.
+    let slave = unsafe { BorrowedFd::borrow_raw(slave) };
     let mut cmd = Command::new(shell.as_ref());
+    cmd.stdin(slave.try_clone_to_owned()?)
+        .stdout(slave.try_clone_to_owned()?)
+        .stderr(slave.try_clone_to_owned()?);
     unsafe {
-        cmd.stdin(Stdio::from_raw_fd(slave))
-            .stdout(Stdio::from_raw_fd(slave))
-            .stderr(Stdio::from_raw_fd(slave))
-            .pre_exec(move || {
-                let res = libc::setsid();
-                if res == -1 {
-                    return Err(IoError::last_os_error());
-                }
+        cmd.pre_exec(move || {
+            let res = libc::setsid();
+            if res == -1 {
+                return Err(IoError::last_os_error());
+            }
 
-                let res = libc::ioctl(0, libc::TIOCSCTTY, 0);
-                if res == -1 {
-                    return Err(IoError::last_os_error());
-                }
+            let res = libc::ioctl(ctty, libc::TIOCSCTTY, 0);
+            if res == -1 {
+                return Err(IoError::last_os_error());
+            }
 
-                Ok(())
-            });
+            Ok(())
+        });
     }
 
     cmd.env_clear();
-    cmd.env("SHELL", shell.as_ref());
     cmd.envs(env);
 
-    cmd
+    Ok(cmd)
 }
 
-fn spawn_reader(master: RawFd) {
+fn spawn_reader(master: RawFd, id: Option<usize>, session: Arc<Session>) {
+    let span = tracing::info_span!("reader", id);
+    let header = match id {
+        Some(id) => format!("READ by reader {id}"),
+        None => "READ".to_string(),
+    };
     std::thread::spawn(move || {
-        let mut buf = [0; 1024];
+        let _enter = span.enter();
+        tracing::debug!("started");
+        let mut buf = vec![0; session.read_buffer];
+        let mut splicer = match session.raw_log.as_ref().map(File::try_clone) {
+            Some(Ok(file)) => match splice::Splicer::new(file) {
+                Ok(splicer) => Some(splicer),
+                Err(e) => {
+                    tracing::error!("could not set up the raw log: {e}");
+                    None
+                }
+            },
+            Some(Err(e)) => {
+                tracing::error!("could not set up the raw log: {e}");
+                None
+            }
+            None => None,
+        };
+        let mut decoder = utf8::Decoder::new();
         loop {
-            std::thread::sleep(std::time::Duration::from_millis(300));
-            match nix::unistd::read(master, &mut buf) {
+            // Batching reads would hide when each echo actually arrived, and would stall
+            // anything waiting on a tap.
+            if session.echo.is_none() && session.tap.lock().unwrap().is_none() {
+                std::thread::sleep(std::time::Duration::from_millis(300));
+            }
+            let res = match &mut splicer {
+                Some(splicer) => splicer.read(master, &mut buf),
+                None => rawio::read_chunk(master, &mut buf, session.read_strategy),
+            };
+            match res {
                 Ok(num_bytes) => {
-                    let buf = &buf[..num_bytes];
-                    let buf_str = String::from_utf8_lossy(buf);
-                    println!("READ");
-                    println!("{buf_str:?}");
-                    println!("{buf:02x?}");
+                    session.stats.lock().unwrap().record_read(num_bytes);
+                    session.regions.lock().unwrap().feed(&buf[..num_bytes]);
+                    let buf = session.middleware.lock().unwrap().output(&buf[..num_bytes]);
+                    let buf = &*buf;
+                    session.record(|rec| rec.output(buf));
+                    session.modes.lock().unwrap().feed(buf);
+                    let (_, titles) = session.titles.lock().unwrap().scan(buf, false);
+
+                    let text = decoder.decode(buf);
+                    print_text(&header, &text, buf);
+                    if decoder.pending() > 0 {
+                        println!(
+                            "({} bytes of a split character carried over)",
+                            decoder.pending()
+                        );
+                    }
+                    for title in titles {
+                        let text = format!("TITLE {title:?}");
+                        println!("{text}");
+                        session.record(|rec| rec.note(text));
+                    }
+                    if session.ldisc_notes {
+                        if let Ok(term) = Termios::from_fd(master) {
+                            for note in ldisc::output_notes(&term, buf) {
+                                println!("LDISC {note}");
+                            }
+                        }
+                    }
+                    if let Some(classifier) = &session.classifier {
+                        classifier.lock().unwrap().print(buf);
+                    }
+                    if let Some(prompts) = &session.prompts {
+                        let events = prompts.lock().unwrap().output(buf);
+                        session.segmented(events);
+                    }
+                    session.hooks.output(buf);
+                    if session.watch_limits {
+                        for message in child::limit_errors(&String::from_utf8_lossy(buf)) {
+                            println!("RLIMIT {message:?} in the output");
+                        }
+                    }
+                    if let Some(tap) = &*session.tap.lock().unwrap() {
+                        let _ = tap.send(buf.to_vec());
+                    }
+                    if let Some(echo) = &session.echo {
+                        for (byte, latency) in echo.lock().unwrap().observed(buf) {
+                            println!("ECHO {byte:02x} after {latency:?}");
+                        }
+                    }
                     println!();
+                    if let Some(lines) = &session.lines {
+                        lines.lock().unwrap().push(&text);
+                    }
+                }
+                Err(Errno::EAGAIN) => {
+                    // What an event loop does with a non-blocking master.
+                    let since = Instant::now();
+                    rawio::wait(master, libc::POLLIN);
+                    session.stats.lock().unwrap().record_eagain(since.elapsed());
                 }
                 Err(Errno::EIO) => {
-                    println!("Got Errno::EIO");
+                    session.lifecycle.lock().unwrap().master_closed();
                     break;
                 }
                 Err(e) => {
-                    println!("Could not read the master: {e:?}");
+                    tracing::error!("could not read the master: {e:?}");
+                    session.lifecycle.lock().unwrap().master_closed();
                     break;
                 }
             }
@@ -172,42 +1238,165 @@ fn spawn_reader(master: RawFd) {
     });
 }
 
-fn execute(cmd: &[u8], master: RawFd) -> Result<(), IoError> {
+fn print_chunk(header: &str, buf: &[u8]) {
+    print_text(header, &String::from_utf8_lossy(buf), buf);
+}
+
+// The text may start with bytes carried over from the previous chunk; the hex view always
+// shows exactly the bytes of this one.
+fn print_text(header: &str, text: &str, buf: &[u8]) {
+    println!("{header}");
+    println!("{text:?}");
+    println!("{buf:02x?}");
+}
+
+fn execute(cmd: &[u8], master: RawFd, session: &Session) -> Result<(), IoError> {
+    let cmd = session.middleware.lock().unwrap().input(cmd);
+    let mut cmd = &*cmd;
+    if let (Injection::Tiocsti, Some(slave)) = (&session.injection, &session.slave_path) {
+        println!("STI {cmd:02x?}");
+        match sti::inject(slave, cmd, session.echo.as_ref()) {
+            Ok(()) => {
+                session.stats.lock().unwrap().record_write(cmd.len());
+                session.record(|rec| rec.input(cmd));
+                session.sent(cmd);
+                return Ok(());
+            }
+            Err((queued, e)) => {
+                println!(
+                    "TIOCSTI failed after {queued} bytes: {e}{}, writing the rest to the master",
+                    sti::hint(&e)
+                );
+                session.stats.lock().unwrap().record_write(queued);
+                session.record(|rec| rec.input(&cmd[..queued]));
+                session.sent(&cmd[..queued]);
+                cmd = &cmd[queued..];
+            }
+        }
+    }
+
     println!("> {cmd:02x?}");
-    if let Err(e) = nix::unistd::write(master, cmd) {
-        println!("Error when writing to the master: {e:?}");
-        Err(IoError::from_raw_os_error(e as _))
-    } else {
+
+    if let Some(echo) = &session.echo {
+        // One write per byte, so that every key gets its own timestamp.
+        for &byte in cmd {
+            echo.lock().unwrap().injected(byte);
+            write_master(&[byte], master, session)?;
+        }
         Ok(())
+    } else {
+        write_master(cmd, master, session)
+    }
+}
+
+fn write_master(cmd: &[u8], master: RawFd, session: &Session) -> Result<(), IoError> {
+    // Before the write: the echo can be read before the write returns.
+    session.sent(cmd);
+    if session.ldisc_notes {
+        if let Ok(term) = Termios::from_fd(master) {
+            for note in ldisc::input_notes(&term, cmd) {
+                println!("LDISC {note}");
+            }
+        }
+    }
+    match rawio::write_all(master, cmd) {
+        Ok(short) => {
+            let mut stats = session.stats.lock().unwrap();
+            stats.record_write(cmd.len());
+            if short > 0 {
+                stats.record_short_writes(short);
+                println!("SHORT write: {} bytes took {} writes", cmd.len(), short + 1);
+            }
+            session.record(|rec| rec.input(cmd));
+            Ok(())
+        }
+        Err((written, e)) => {
+            session.stats.lock().unwrap().record_write(written);
+            session.record(|rec| rec.input(&cmd[..written]));
+            tracing::error!(
+                "could not write to the master after {written} of {} bytes: {e:?}",
+                cmd.len()
+            );
+            Err(IoError::from_raw_os_error(e as _))
+        }
     }
 }
 
+#[derive(Clone, Copy)]
 enum WriterMode {
     String,
     Bytes,
+    Base64,
 }
 
-fn write_loop(master: RawFd, mode: WriterMode) -> Result<(), IoError> {
-    let stdin = std::io::stdin();
+impl WriterMode {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "str" => Some(Self::String),
+            "bytes" => Some(Self::Bytes),
+            "b64" => Some(Self::Base64),
+            _ => None,
+        }
+    }
 
-    loop {
-        std::thread::sleep(std::time::Duration::from_millis(1000));
+    fn name(self) -> &'static str {
+        match self {
+            Self::String => "str",
+            Self::Bytes => "bytes",
+            Self::Base64 => "b64",
+        }
+    }
+}
 
-        let mut buf = String::new();
-        stdin.read_line(&mut buf)?;
+struct Writer {
+    mode: WriterMode,
+    escapes: bool,
+    line_ending: LineEnding,
+    macros: Macros,
+}
 
-        let mut cmd = match mode {
-            WriterMode::String => buf.into_bytes(),
-            WriterMode::Bytes => parse_bytes(&buf),
-        };
+impl Writer {
+    fn interpret(&self, line: &str) -> Result<Vec<u8>, ParseError> {
+        match self.mode {
+            WriterMode::String if self.escapes => Ok(input::unescape(line)),
+            WriterMode::String => Ok(line.as_bytes().to_vec()),
+            WriterMode::Bytes => input::parse_bytes(line),
+            WriterMode::Base64 => input::decode_base64(line),
+        }
+    }
 
-        if !cmd.ends_with(b"\n") {
-            cmd.push(b'\n');
+    fn default_ending(&self) -> LineEnding {
+        // Base64 payloads are sent exactly as decoded.
+        match self.mode {
+            WriterMode::Base64 => LineEnding::None,
+            _ => self.line_ending,
         }
+    }
+}
+
+fn write_loop(master: RawFd, mut writer: Writer, session: &Session) -> Result<(), IoError> {
+    let _span = tracing::info_span!("writer").entered();
+    if !std::io::stdin().is_terminal() {
+        return pipe_loop(master, writer, session);
+    }
+    let mut editor = LineEditor::new()?;
+
+    loop {
+        std::thread::sleep(std::time::Duration::from_millis(1000));
 
-        execute(&cmd, master.as_raw_fd())?;
+        let Some(line) = editor.read_line()? else {
+            println!("EOF on stdin");
+            break;
+        };
+        let line = line.as_str();
+
+        let exit = if let Some(command) = line.trim_end().strip_prefix(':') {
+            command::run(command, master, &mut writer, session)?
+        } else {
+            send_line(line, writer.default_ending(), master, &writer, session)?
+        };
 
-        if cmd.ends_with(b"exit\n") {
+        if exit {
             break;
         }
     }
@@ -215,55 +1404,69 @@ fn write_loop(master: RawFd, mode: WriterMode) -> Result<(), IoError> {
     Ok(())
 }
 
-fn parse_bytes(buf: &str) -> Vec<u8> {
-    let mut cmd = Vec::new();
-    let buf = if let Some(buf) = buf.strip_suffix('\n') {
-        buf
-    } else {
-        buf
-    };
+// Piped input is taken line by line at the pace of typed lines, without the editor, and
+// its end becomes an end of file for the program too, so that the session ends with it.
+fn pipe_loop(master: RawFd, mut writer: Writer, session: &Session) -> Result<(), IoError> {
+    println!("Stdin is not a terminal, streaming it");
+    let mut stdin = std::io::stdin().lock();
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        if stdin.read_until(b'\n', &mut buf)? == 0 {
+            println!("EOF on stdin, sending ^D");
+            execute(b"\x04", master, session)?;
+            return Ok(());
+        }
+        let line = String::from_utf8_lossy(&buf);
+        let line = line.strip_suffix('\n').unwrap_or(&line);
 
-    for byte in buf.split(' ') {
-        if let Ok(byte) = u8::from_str_radix(byte, 16) {
-            cmd.push(byte);
+        let exit = if let Some(command) = line.trim_end().strip_prefix(':') {
+            command::run(command, master, &mut writer, session)?
+        } else {
+            send_line(line, writer.default_ending(), master, &writer, session)?
+        };
+        if exit {
+            return Ok(());
+        }
+        std::thread::sleep(Duration::from_millis(1000));
+    }
+}
+
+fn send_line(
+    line: &str,
+    ending: LineEnding,
+    master: RawFd,
+    writer: &Writer,
+    session: &Session,
+) -> Result<bool, IoError> {
+    let mut cmd = match writer.interpret(line) {
+        Ok(cmd) => cmd,
+        Err(e) => {
+            println!("Not sent: {e}");
+            return Ok(false);
         }
+    };
+
+    let exit = cmd.ends_with(b"exit");
+    if !cmd.ends_with(ending.as_bytes()) {
+        cmd.extend_from_slice(ending.as_bytes());
     }
 
-    cmd
+    execute(&cmd, master.as_raw_fd(), session)?;
+
+    Ok(exit)
 }
 
 fn debug_termios(term: &Termios) {
-    use ::termios::os::target::VSWTC as VSWTCH;
-    use ::termios::os::target::*;
+    use ::termios::{cc_t, tcflag_t};
     use std::collections::BTreeMap;
     use std::fmt;
 
-    macro_rules! flag_list {
-        ($($flag: ident,)*) => {{
-            [ $( ($flag, stringify!($flag)), )* ]
-        }};
-    }
-
-    let iflags = flag_list![
-        IGNBRK, BRKINT, IGNPAR, PARMRK, INPCK, ISTRIP, INLCR, ICRNL, IUCLC, IXON, IXANY, IXOFF,
-        IMAXBEL, IUTF8,
-    ];
-    let oflags = flag_list![
-        OPOST, OLCUC, ONLCR, OCRNL, ONOCR, ONLRET, OFILL, OFDEL, NLDLY, CRDLY, TABDLY, BSDLY,
-        VTDLY, FFDLY,
-    ];
-    let cflags = flag_list![
-        CBAUD, CBAUDEX, CSIZE, CSTOPB, CREAD, PARENB, PARODD, HUPCL, CLOCAL, CIBAUD, CMSPAR,
-        CRTSCTS,
-    ];
-    let lflags = flag_list![
-        ISIG, ICANON, XCASE, ECHO, ECHOE, ECHOK, ECHONL, ECHOCTL, ECHOPRT, ECHOKE, FLUSHO, NOFLSH,
-        TOSTOP, PENDIN, IEXTEN,
-    ];
-    let cc = flag_list![
-        VDISCARD, VEOF, VEOL, VEOL2, VERASE, VINTR, VKILL, VLNEXT, VMIN, VQUIT, VREPRINT, VSTART,
-        VSTOP, VSUSP, VSWTCH, VTIME, VWERASE,
-    ];
+    let iflags = flags::IFLAGS;
+    let oflags = flags::OFLAGS;
+    let cflags = flags::CFLAGS;
+    let lflags = flags::LFLAGS;
+    let cc = flags::CC;
 
     #[derive(Debug)]
     struct Flag {
@@ -307,11 +1510,11 @@ fn debug_termios(term: &Termios) {
     }
 
     let new_dbg = || DebugTermios {
-        c_iflag: split(term.c_iflag, &iflags),
-        c_oflag: split(term.c_oflag, &oflags),
-        c_cflag: split(term.c_cflag, &cflags),
-        c_lflag: split(term.c_lflag, &lflags),
-        c_cc: special_char_map(&term.c_cc, &cc),
+        c_iflag: split(term.c_iflag, iflags),
+        c_oflag: split(term.c_oflag, oflags),
+        c_cflag: split(term.c_cflag, cflags),
+        c_lflag: split(term.c_lflag, lflags),
+        c_cc: special_char_map(&term.c_cc, cc),
     };
 
     impl fmt::Debug for DebugTermios {
@@ -0,0 +1,117 @@
+use crate::Session;
+
+use rustls::pki_types::pem::PemObject as _;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+
+use std::io::{BufRead as _, BufReader, Error as IoError, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+// A client that stops half way through a request is dropped after this long.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+// Just enough HTTP for a scraper: one request per connection, GET /metrics, and GET /pty
+// for the slave path.
+pub fn spawn_metrics(
+    addr: &str,
+    tls: Option<Arc<ServerConfig>>,
+    spawns: usize,
+    session: Arc<Session>,
+) -> Result<(), IoError> {
+    let listener = TcpListener::bind(addr)?;
+    let scheme = if tls.is_some() { "https" } else { "http" };
+    println!("Metrics on {scheme}://{}/metrics", listener.local_addr()?);
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let session = session.clone();
+            let tls = tls.clone();
+            std::thread::spawn(move || {
+                let result = stream
+                    .set_read_timeout(Some(TIMEOUT))
+                    .and_then(|()| stream.set_write_timeout(Some(TIMEOUT)))
+                    .and_then(|()| match tls {
+                        Some(config) => respond_tls(config, stream, spawns, &session),
+                        None => respond(stream, spawns, &session),
+                    });
+                if let Err(e) = result {
+                    tracing::warn!("metrics request failed: {e}");
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+// The certificate chain and its key, both PEM.
+pub fn tls_config(cert: &Path, key: &Path) -> Result<Arc<ServerConfig>, IoError> {
+    let pem_error = |path: &Path, e| IoError::other(format!("{}: {e}", path.display()));
+    let certs = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| pem_error(cert, e))?;
+    let key = PrivateKeyDer::from_pem_file(key).map_err(|e| pem_error(key, e))?;
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(IoError::other)?;
+    Ok(Arc::new(config))
+}
+
+fn respond_tls(
+    config: Arc<ServerConfig>,
+    stream: TcpStream,
+    spawns: usize,
+    session: &Session,
+) -> Result<(), IoError> {
+    let conn = ServerConnection::new(config).map_err(IoError::other)?;
+    let mut stream = StreamOwned::new(conn, stream);
+    respond(&mut stream, spawns, session)?;
+    stream.conn.send_close_notify();
+    stream.flush()
+}
+
+fn respond(stream: impl Read + Write, spawns: usize, session: &Session) -> Result<(), IoError> {
+    let mut reader = BufReader::new(stream);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // The headers are of no interest, but have to be read before answering.
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let stream = reader.get_mut();
+    match (request.split_whitespace().nth(1), &session.slave_path) {
+        (Some("/metrics"), _) => {}
+        (Some("/pty"), Some(slave)) => {
+            let body = format!("{}\n", slave.display());
+            return write!(
+                stream,
+                "HTTP/1.0 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n{body}",
+                body.len()
+            );
+        }
+        _ => return stream.write_all(b"HTTP/1.0 404 Not Found\r\nContent-Length: 0\r\n\r\n"),
+    }
+
+    let mut body = session.stats.lock().unwrap().metrics();
+    let name = "debug_pty_child_spawns_total";
+    body.push_str(&format!(
+        "# HELP {name} Children started.\n# TYPE {name} counter\n{name} {spawns}\n"
+    ));
+    let name = "debug_pty_child_running";
+    let running = !session.lifecycle.lock().unwrap().child_exited_yet();
+    body.push_str(&format!(
+        "# HELP {name} Whether the child is running.\n# TYPE {name} gauge\n{name} {}\n",
+        running as u8
+    ));
+
+    write!(
+        stream,
+        "HTTP/1.0 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\r\n{body}",
+        body.len()
+    )
+}
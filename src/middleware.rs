@@ -0,0 +1,228 @@
+use crate::input;
+
+use std::borrow::Cow;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub trait Middleware: Send {
+    fn on_output<'a>(&mut self, buf: &'a [u8]) -> Cow<'a, [u8]> {
+        Cow::Borrowed(buf)
+    }
+
+    fn on_input<'a>(&mut self, buf: &'a [u8]) -> Cow<'a, [u8]> {
+        Cow::Borrowed(buf)
+    }
+}
+
+type Constructor = fn(&str) -> Result<Box<dyn Middleware>, String>;
+
+pub const REGISTRY: &[(&str, &str, Constructor)] = &[
+    (
+        "redact",
+        "TEXT: replace TEXT with asterisks both ways",
+        Redact::build,
+    ),
+    (
+        "rewrite",
+        "FROM=>TO: replace FROM with TO in the output",
+        Rewrite::build,
+    ),
+    (
+        "ratelimit",
+        "BYTES: pass at most BYTES of output per second",
+        RateLimit::build,
+    ),
+    (
+        "drop",
+        "PERCENT: lose that share of output chunks",
+        DropChunks::build,
+    ),
+];
+
+// Stages run in the order given, on input and output alike.
+#[derive(Default)]
+pub struct Pipeline {
+    stages: Vec<Box<dyn Middleware>>,
+}
+
+impl Pipeline {
+    pub fn build(specs: &[String]) -> Result<Self, String> {
+        let mut pipeline = Self::default();
+        for spec in specs {
+            let (name, arg) = spec.split_once('=').unwrap_or((spec, ""));
+            let Some((_, _, build)) = REGISTRY.iter().find(|(known, _, _)| *known == name) else {
+                let known: Vec<_> = REGISTRY.iter().map(|(name, _, _)| *name).collect();
+                return Err(format!(
+                    "unknown filter `{name}` (one of {})",
+                    known.join(", ")
+                ));
+            };
+            pipeline.register(build(arg).map_err(|e| format!("{name}: {e}"))?);
+        }
+        Ok(pipeline)
+    }
+
+    pub fn register(&mut self, stage: Box<dyn Middleware>) {
+        self.stages.push(stage);
+    }
+
+    pub fn output<'a>(&mut self, buf: &'a [u8]) -> Cow<'a, [u8]> {
+        self.run(buf, |stage, buf| stage.on_output(buf))
+    }
+
+    pub fn input<'a>(&mut self, buf: &'a [u8]) -> Cow<'a, [u8]> {
+        self.run(buf, |stage, buf| stage.on_input(buf))
+    }
+
+    fn run<'a>(
+        &mut self,
+        buf: &'a [u8],
+        mut f: impl for<'b> FnMut(&mut dyn Middleware, &'b [u8]) -> Cow<'b, [u8]>,
+    ) -> Cow<'a, [u8]> {
+        let mut buf = Cow::Borrowed(buf);
+        for stage in &mut self.stages {
+            let owned = match f(stage.as_mut(), &buf) {
+                Cow::Owned(owned) => Some(owned),
+                Cow::Borrowed(_) => None,
+            };
+            if let Some(owned) = owned {
+                buf = Cow::Owned(owned);
+            }
+        }
+        buf
+    }
+}
+
+fn replace<'a>(buf: &'a [u8], from: &[u8], to: &[u8]) -> Cow<'a, [u8]> {
+    if from.is_empty() || !buf.windows(from.len()).any(|window| window == from) {
+        return Cow::Borrowed(buf);
+    }
+    let mut replaced = Vec::with_capacity(buf.len());
+    let mut rest = buf;
+    while !rest.is_empty() {
+        if rest.starts_with(from) {
+            replaced.extend_from_slice(to);
+            rest = &rest[from.len()..];
+        } else {
+            replaced.push(rest[0]);
+            rest = &rest[1..];
+        }
+    }
+    Cow::Owned(replaced)
+}
+
+// Occurrences split across two reads are not caught.
+struct Redact {
+    text: Vec<u8>,
+    mask: Vec<u8>,
+}
+
+impl Redact {
+    fn build(arg: &str) -> Result<Box<dyn Middleware>, String> {
+        if arg.is_empty() {
+            return Err("expected redact=TEXT".into());
+        }
+        let text = input::unescape(arg);
+        let mask = vec![b'*'; text.len()];
+        Ok(Box::new(Self { text, mask }))
+    }
+}
+
+impl Middleware for Redact {
+    fn on_output<'a>(&mut self, buf: &'a [u8]) -> Cow<'a, [u8]> {
+        replace(buf, &self.text, &self.mask)
+    }
+
+    fn on_input<'a>(&mut self, buf: &'a [u8]) -> Cow<'a, [u8]> {
+        replace(buf, &self.text, &self.mask)
+    }
+}
+
+struct Rewrite {
+    from: Vec<u8>,
+    to: Vec<u8>,
+}
+
+impl Rewrite {
+    fn build(arg: &str) -> Result<Box<dyn Middleware>, String> {
+        let Some((from, to)) = arg.split_once("=>") else {
+            return Err("expected rewrite=FROM=>TO".into());
+        };
+        Ok(Box::new(Self {
+            from: input::unescape(from),
+            to: input::unescape(to),
+        }))
+    }
+}
+
+impl Middleware for Rewrite {
+    fn on_output<'a>(&mut self, buf: &'a [u8]) -> Cow<'a, [u8]> {
+        replace(buf, &self.from, &self.to)
+    }
+}
+
+struct RateLimit {
+    bytes_per_sec: f64,
+    next: Instant,
+}
+
+impl RateLimit {
+    fn build(arg: &str) -> Result<Box<dyn Middleware>, String> {
+        match arg.parse::<f64>() {
+            Ok(bytes_per_sec) if bytes_per_sec > 0.0 => Ok(Box::new(Self {
+                bytes_per_sec,
+                next: Instant::now(),
+            })),
+            _ => Err(format!("invalid rate `{arg}`")),
+        }
+    }
+}
+
+impl Middleware for RateLimit {
+    fn on_output<'a>(&mut self, buf: &'a [u8]) -> Cow<'a, [u8]> {
+        // Holds back the reader, so the child blocks once the pty buffer is full.
+        std::thread::sleep(self.next.saturating_duration_since(Instant::now()));
+        let cost = Duration::from_secs_f64(buf.len() as f64 / self.bytes_per_sec);
+        self.next = self.next.max(Instant::now()) + cost;
+        Cow::Borrowed(buf)
+    }
+}
+
+struct DropChunks {
+    percent: f64,
+    state: u64,
+}
+
+impl DropChunks {
+    fn build(arg: &str) -> Result<Box<dyn Middleware>, String> {
+        let percent = match arg.trim_end_matches('%').parse::<f64>() {
+            Ok(percent) if (0.0..=100.0).contains(&percent) => percent,
+            _ => return Err(format!("invalid percentage `{arg}`")),
+        };
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        Ok(Box::new(Self {
+            percent,
+            state: seed | 1,
+        }))
+    }
+
+    // xorshift64; the point is losing chunks unpredictably, not statistics.
+    fn roll(&mut self) -> f64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state % 10_000) as f64 / 100.0
+    }
+}
+
+impl Middleware for DropChunks {
+    fn on_output<'a>(&mut self, buf: &'a [u8]) -> Cow<'a, [u8]> {
+        if self.roll() < self.percent {
+            Cow::Owned(Vec::new())
+        } else {
+            Cow::Borrowed(buf)
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use crate::capture::{Reading, Recorder};
+use crate::cli::RecordArgs;
+use crate::escape::{self, caret, Escape, Segment};
+use crate::middleware::Pipeline;
+use crate::prompts::Segmenter;
+use crate::stats::Stats;
+use crate::{
+    alloc, flags, hooks, hostterm, modes, open_pty, rawio, regions, spawn_child, supervise, title,
+    Args, Injection, Session, Writer,
+};
+
+use nix::errno::Errno;
+use nix::pty::Winsize;
+
+use termios::Termios;
+
+use std::io::Write as _;
+use std::os::fd::{AsRawFd as _, RawFd};
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const WINSIZE_POLL: Duration = Duration::from_millis(200);
+
+// Sits between the real terminal and the program like script(1): everything is passed
+// through unchanged and only the capture shows what went by.
+pub fn run(mut args: Args, record: &RecordArgs) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    if !nix::unistd::isatty(0).unwrap_or(false) {
+        return Err("wrap needs a terminal on stdin".into());
+    }
+    let host = Termios::from_fd(0)?;
+    // An explicit --winsize stays fixed, otherwise the pty follows the real terminal.
+    let follow = args.winsize.is_none();
+    if follow {
+        args.winsize = winsize(0);
+    }
+
+    let pty = open_pty(args.alloc, args.winsize.as_ref())?;
+    let master = pty.master.as_raw_fd();
+    // The program starts with the settings of the real terminal, like it would without us.
+    let mut term = host;
+    for spec in &args.termios {
+        flags::apply(&mut term, spec)?;
+    }
+    termios::tcsetattr(master, termios::TCSANOW, &term)?;
+
+    let slave = alloc::ptsname(master).ok();
+    let recorder = Mutex::new(Recorder::create(
+        &record.output,
+        record.format(),
+        &args.argv().join(" "),
+        slave.as_deref(),
+        Some(Reading {
+            buffer: args.read_buffer.unwrap_or(4096),
+            strategy: args.read_strategy.name().to_string(),
+        }),
+        Some(crate::metadata(&args, master, Some(record))?),
+    )?);
+    if let Some(size) = &args.winsize {
+        recorder.lock().unwrap().resize(size.ws_row, size.ws_col);
+    }
+    let middleware = Mutex::new(Pipeline::build(&args.filters)?);
+    let mut child = spawn_child(&mut args, pty.slave)?;
+    // Only what the prompt needs; the output is passed through rather than analysed.
+    let session = Arc::new(Session {
+        stats: Mutex::new(Stats::new()),
+        echo: None,
+        tap: Mutex::new(None),
+        injection: std::mem::replace(&mut args.injection, Injection::Master),
+        slave_path: slave,
+        recorder: Some(recorder),
+        watch_limits: false,
+        lifecycle: Mutex::new(supervise::Lifecycle::new()),
+        middleware,
+        hooks: hooks::Hooks::default(),
+        lines: None,
+        classifier: None,
+        ldisc_notes: false,
+        baseline: None,
+        read_buffer: args.read_buffer.unwrap_or(4096),
+        read_strategy: args.read_strategy,
+        raw_log: None,
+        modes: Mutex::new(modes::Tracker::new()),
+        titles: Mutex::new(title::Titles::new()),
+        regions: Mutex::new(regions::Regions::default()),
+        // The terminal is in raw mode meanwhile, so the commands only go in the capture.
+        prompts: args
+            .prompt_detect
+            .take()
+            .map(|detect| Mutex::new(Segmenter::new(detect))),
+    });
+    let writer = Writer {
+        mode: args.mode,
+        escapes: args.escapes,
+        line_ending: args.line_ending,
+        macros: std::mem::take(&mut args.macros),
+    };
+    println!(
+        "Capturing to {}, until the program exits",
+        record.output.display()
+    );
+    if let Some(key) = args.escape_char {
+        println!("Escape character is {}, then : for a prompt", caret(key));
+    }
+
+    let mut raw = host;
+    termios::cfmakeraw(&mut raw);
+    let raw = hostterm::Guard::enter(0, &raw)?;
+    spawn_input(
+        master,
+        host,
+        Escape::new(args.escape_char),
+        writer,
+        session.clone(),
+    );
+    if follow {
+        spawn_winsize(master, args.winsize, session.clone());
+    }
+
+    let mut stdout = std::io::stdout();
+    let mut modes = modes::Tracker::new();
+    let mut titles = title::Titles::new();
+    let mut buf = vec![0; args.read_buffer.unwrap_or(4096)];
+    loop {
+        match rawio::read_chunk(master, &mut buf, args.read_strategy) {
+            Ok(0) | Err(Errno::EIO) => break,
+            Ok(num_bytes) => {
+                let buf = session.middleware.lock().unwrap().output(&buf[..num_bytes]);
+                let buf = &*buf;
+                session.record(|rec| rec.output(buf));
+                modes.feed(buf);
+                if let Some(prompts) = &session.prompts {
+                    for event in prompts.lock().unwrap().output(buf) {
+                        session.record(|rec| event.record(rec));
+                    }
+                }
+                let (buf, set) = titles.scan(buf, !args.host_title);
+                for title in set {
+                    session.record(|rec| rec.note(format!("TITLE {title:?}")));
+                }
+                stdout.write_all(&buf)?;
+                stdout.flush()?;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    // The terminal is the user's own, so whatever the program left on stays on after it.
+    let leftover = modes.leftover();
+    if args.reset_on_exit {
+        for leftover in &leftover {
+            stdout.write_all(&leftover.reset)?;
+        }
+        stdout.flush()?;
+    }
+    drop(raw);
+    if let Some(prompts) = &session.prompts {
+        prompts.lock().unwrap().report();
+    }
+    let label = if args.reset_on_exit { "RESET" } else { "DIRTY" };
+    for leftover in leftover {
+        let text = format!("{label} {} left by the program", leftover.name);
+        println!("{text}");
+        session.record(|rec| rec.note(text));
+    }
+
+    let status = child.wait()?;
+    println!("Child exited with {status}");
+    session.record(|rec| {
+        rec.exit(&status);
+        rec.finish();
+    });
+    Ok(ExitCode::from(status.code().unwrap_or(1) as u8))
+}
+
+fn spawn_input(
+    master: RawFd,
+    host: Termios,
+    mut escape: Escape,
+    mut writer: Writer,
+    session: Arc<Session>,
+) {
+    std::thread::spawn(move || {
+        let mut buf = [0; 1024];
+        loop {
+            let num_bytes = match rawio::read(0, &mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(num_bytes) => num_bytes,
+            };
+            for segment in escape.split(&buf[..num_bytes]) {
+                match segment {
+                    Segment::Bytes(bytes) if bytes.is_empty() => {}
+                    Segment::Bytes(bytes) => {
+                        let input = session.middleware.lock().unwrap().input(&bytes);
+                        session.record(|rec| rec.input(&input));
+                        if let Some(prompts) = &session.prompts {
+                            prompts.lock().unwrap().input(&input);
+                        }
+                        if rawio::write_all(master, &input).is_err() {
+                            return;
+                        }
+                    }
+                    Segment::Repl => escape::repl(master, &host, &mut writer, &session),
+                }
+            }
+        }
+    });
+}
+
+// Polled rather than caught as SIGWINCH; the kernel signals the program when the size of
+// the master changes.
+fn spawn_winsize(master: RawFd, mut last: Option<Winsize>, session: Arc<Session>) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(WINSIZE_POLL);
+        let Some(size) = winsize(0) else {
+            continue;
+        };
+        let changed =
+            last.is_none_or(|last| (last.ws_row, last.ws_col) != (size.ws_row, size.ws_col));
+        if changed {
+            unsafe { libc::ioctl(master, libc::TIOCSWINSZ, &size) };
+            let note = format!("WINSIZE {}x{}", size.ws_row, size.ws_col);
+            session.record(|rec| {
+                rec.resize(size.ws_row, size.ws_col);
+                rec.note(note);
+            });
+            last = Some(size);
+        }
+    });
+}
+
+pub fn winsize(fd: RawFd) -> Option<Winsize> {
+    let mut winsize: Winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut winsize) } == -1 {
+        return None;
+    }
+    Some(winsize)
+}
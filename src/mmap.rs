@@ -0,0 +1,220 @@
+use crate::capture::{Event, Record};
+
+use std::fs::File;
+use std::io::Error as IoError;
+use std::os::fd::AsRawFd as _;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+// Layout: a 64-byte header (magic, version, length of the records as of the last sync
+// point), then records of a 16-byte head (payload length, kind, time) and a payload padded
+// to 8 bytes. Output and input payloads are the raw bytes, every other event is JSON.
+const MAGIC: &[u8; 8] = b"DPTYMMAP";
+const VERSION: u32 = 1;
+const HEADER: usize = 64;
+const COMMITTED: usize = 16;
+const RECORD_HEAD: usize = 16;
+
+const KIND_JSON: u8 = 1;
+const KIND_OUTPUT: u8 = 2;
+const KIND_INPUT: u8 = 3;
+
+const INITIAL_SIZE: usize = 1 << 20;
+const SYNC_BYTES: usize = 1 << 20;
+const SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct Writer {
+    file: File,
+    map: *mut u8,
+    capacity: usize,
+    len: usize,
+    synced: usize,
+    last_sync: Instant,
+}
+
+// The mapping is only touched through &mut self.
+unsafe impl Send for Writer {}
+
+impl Writer {
+    pub fn create(path: &Path) -> Result<Self, IoError> {
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        let mut writer = Self {
+            file,
+            map: std::ptr::null_mut(),
+            capacity: 0,
+            len: HEADER,
+            synced: HEADER,
+            last_sync: Instant::now(),
+        };
+        writer.remap(INITIAL_SIZE)?;
+        writer.bytes()[..MAGIC.len()].copy_from_slice(MAGIC);
+        writer.bytes()[8..12].copy_from_slice(&VERSION.to_le_bytes());
+        writer.commit()?;
+        Ok(writer)
+    }
+
+    // Output and input go straight from the caller's buffer into the mapping.
+    pub fn output(&mut self, t: f64, data: &[u8]) -> Result<(), IoError> {
+        self.append(KIND_OUTPUT, t, data)
+    }
+
+    pub fn input(&mut self, t: f64, data: &[u8]) -> Result<(), IoError> {
+        self.append(KIND_INPUT, t, data)
+    }
+
+    pub fn event(&mut self, t: f64, event: &Event) -> Result<(), IoError> {
+        match event {
+            Event::Output { data } => self.output(t, data),
+            Event::Input { data, client: None } => self.input(t, data),
+            event => self.append(KIND_JSON, t, &serde_json::to_vec(event)?),
+        }
+    }
+
+    fn append(&mut self, kind: u8, t: f64, payload: &[u8]) -> Result<(), IoError> {
+        let size = RECORD_HEAD + payload.len().next_multiple_of(8);
+        if self.len + size > self.capacity {
+            self.sync()?;
+            self.remap((self.len + size).next_power_of_two())?;
+        }
+
+        let at = self.len;
+        let record = &mut self.bytes()[at..at + size];
+        record[..4].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+        record[4] = kind;
+        record[8..16].copy_from_slice(&t.to_le_bytes());
+        record[RECORD_HEAD..RECORD_HEAD + payload.len()].copy_from_slice(payload);
+        self.len += size;
+
+        if self.len - self.synced >= SYNC_BYTES || self.last_sync.elapsed() >= SYNC_INTERVAL {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    // A sync point: the records reach the disk before the header says they are there, so
+    // a crash leaves at worst some records past the committed length.
+    pub fn sync(&mut self) -> Result<(), IoError> {
+        if self.synced < self.len {
+            self.msync()?;
+            self.synced = self.len;
+            self.commit()?;
+        }
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<(), IoError> {
+        let len = (self.synced - HEADER) as u64;
+        self.bytes()[COMMITTED..COMMITTED + 8].copy_from_slice(&len.to_le_bytes());
+        self.msync()
+    }
+
+    fn msync(&self) -> Result<(), IoError> {
+        match unsafe { libc::msync(self.map.cast(), self.capacity, libc::MS_SYNC) } {
+            -1 => Err(IoError::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+
+    fn remap(&mut self, capacity: usize) -> Result<(), IoError> {
+        self.unmap();
+        self.file.set_len(capacity as u64)?;
+        let map = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                capacity,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                self.file.as_raw_fd(),
+                0,
+            )
+        };
+        if map == libc::MAP_FAILED {
+            return Err(IoError::last_os_error());
+        }
+        self.map = map.cast();
+        self.capacity = capacity;
+        Ok(())
+    }
+
+    fn unmap(&mut self) {
+        if !self.map.is_null() {
+            unsafe { libc::munmap(self.map.cast(), self.capacity) };
+            self.map = std::ptr::null_mut();
+        }
+    }
+
+    fn bytes(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.map, self.capacity) }
+    }
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        if let Err(e) = self.sync() {
+            tracing::warn!("could not sync the capture: {e}");
+        }
+        self.unmap();
+        // The preallocated tail goes.
+        let _ = self.file.set_len(self.len as u64);
+    }
+}
+
+pub fn is_mmap(path: &Path) -> Result<bool, IoError> {
+    let mut magic = [0; MAGIC.len()];
+    let mut file = File::open(path)?;
+    Ok(std::io::Read::read_exact(&mut file, &mut magic).is_ok() && magic == *MAGIC)
+}
+
+// Also returns how many records were found past the last sync point, which a crash
+// leaves behind: they are taken as long as they parse.
+pub fn read(path: &Path) -> Result<(Vec<Record>, usize), Box<dyn std::error::Error>> {
+    let bytes = std::fs::read(path)?;
+    if bytes.len() < HEADER || bytes[..MAGIC.len()] != *MAGIC {
+        return Err(format!("{}: not an mmap capture", path.display()).into());
+    }
+    let version = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+    if version != VERSION {
+        return Err(format!("{}: unknown version {version}", path.display()).into());
+    }
+    let committed = u64::from_le_bytes(bytes[COMMITTED..COMMITTED + 8].try_into().unwrap());
+    let committed = HEADER + committed as usize;
+
+    let mut records = Vec::new();
+    let mut recovered = 0;
+    let mut at = HEADER;
+    while let Some((record, size)) = bytes.get(at..).and_then(parse) {
+        if at >= committed {
+            recovered += 1;
+        }
+        records.push(record);
+        at += size;
+    }
+    if at < committed.min(bytes.len()) {
+        return Err(format!("{}: corrupt record at offset {at}", path.display()).into());
+    }
+    Ok((records, recovered))
+}
+
+fn parse(bytes: &[u8]) -> Option<(Record, usize)> {
+    let head = bytes.get(..RECORD_HEAD)?;
+    let len = u32::from_le_bytes(head[..4].try_into().unwrap()) as usize;
+    let t = f64::from_le_bytes(head[8..16].try_into().unwrap());
+    let payload = bytes.get(RECORD_HEAD..RECORD_HEAD + len)?.to_vec();
+    let event = match head[4] {
+        KIND_OUTPUT => Event::Output { data: payload },
+        KIND_INPUT => Event::Input {
+            data: payload,
+            client: None,
+        },
+        KIND_JSON => serde_json::from_slice(&payload).ok()?,
+        // Zeroes: the end of what was written.
+        _ => return None,
+    };
+    Some((Record { t, event }, RECORD_HEAD + len.next_multiple_of(8)))
+}
@@ -0,0 +1,173 @@
+use std::collections::BTreeMap;
+
+// DEC private modes a program is expected to put back before it exits, with what each
+// means when left on and whether it is on in a fresh terminal.
+const KNOWN: &[(u16, &str, bool)] = &[
+    (1, "application cursor keys", false),
+    (7, "autowrap", true),
+    (25, "cursor visible", true),
+    (47, "alternate screen", false),
+    (1000, "mouse tracking", false),
+    (1002, "mouse button tracking", false),
+    (1003, "mouse motion tracking", false),
+    (1004, "focus reporting", false),
+    (1006, "SGR mouse mode", false),
+    (1015, "urxvt mouse mode", false),
+    (1047, "alternate screen", false),
+    (1049, "alternate screen", false),
+    (2004, "bracketed paste", false),
+];
+
+enum State {
+    Ground,
+    Escape,
+    Csi,
+}
+
+pub struct Leftover {
+    pub name: String,
+    pub reset: Vec<u8>,
+}
+
+// Follows the escape sequences in the output, across reads, to know which modes the
+// program has left set.
+pub struct Tracker {
+    state: State,
+    params: Vec<u8>,
+    private: bool,
+    // Each mode the program touched, as it was before and as it is now.
+    modes: BTreeMap<u16, (bool, bool)>,
+    sgr: bool,
+}
+
+impl Tracker {
+    pub fn new() -> Self {
+        Self {
+            state: State::Ground,
+            params: Vec::new(),
+            private: false,
+            modes: BTreeMap::new(),
+            sgr: false,
+        }
+    }
+
+    pub fn feed(&mut self, buf: &[u8]) {
+        for &byte in buf {
+            match self.state {
+                State::Ground if byte == 0x1b => self.state = State::Escape,
+                State::Ground => {}
+                State::Escape => {
+                    self.state = match byte {
+                        b'[' => {
+                            self.params.clear();
+                            self.private = false;
+                            State::Csi
+                        }
+                        // RIS, a full reset.
+                        b'c' => {
+                            self.modes.clear();
+                            self.sgr = false;
+                            State::Ground
+                        }
+                        0x1b => State::Escape,
+                        _ => State::Ground,
+                    }
+                }
+                State::Csi => match byte {
+                    b'?' if self.params.is_empty() => self.private = true,
+                    0x30..=0x3f => self.params.push(byte),
+                    0x20..=0x2f => {}
+                    0x40..=0x7e => {
+                        self.dispatch(byte);
+                        self.state = State::Ground;
+                    }
+                    0x1b => self.state = State::Escape,
+                    _ => {}
+                },
+            }
+        }
+    }
+
+    fn dispatch(&mut self, last: u8) {
+        let params = String::from_utf8_lossy(&self.params).into_owned();
+        match (self.private, last) {
+            (true, b'h' | b'l') => {
+                for mode in params.split(';').filter_map(|mode| mode.parse().ok()) {
+                    let on = last == b'h';
+                    // A mode we know nothing about is taken to have been the other way.
+                    let before = default(mode).unwrap_or(!on);
+                    self.modes.entry(mode).or_insert((before, on)).1 = on;
+                }
+            }
+            (false, b'm') => {
+                let mut params = params.split(';');
+                while let Some(param) = params.next() {
+                    match param {
+                        "" | "0" => self.sgr = false,
+                        // Extended colors: the index or the components that follow are not
+                        // attributes of their own.
+                        "38" | "48" | "58" => {
+                            let skip = match params.next() {
+                                Some("5") => 1,
+                                Some("2") => 3,
+                                _ => 0,
+                            };
+                            if skip > 0 {
+                                params.nth(skip - 1);
+                            }
+                            self.sgr = true;
+                        }
+                        _ => self.sgr = true,
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Everything that is not as a fresh terminal has it, with the sequence that puts it back.
+    pub fn leftover(&self) -> Vec<Leftover> {
+        let mut leftover: Vec<Leftover> = self
+            .modes
+            .iter()
+            .filter_map(|(&mode, &(_, on))| {
+                let &(_, name, default) = KNOWN.iter().find(|(known, _, _)| *known == mode)?;
+                (on != default).then(|| Leftover {
+                    name: format!("{name} ({}) {}", mode, if on { "on" } else { "off" }),
+                    reset: format!("\x1b[?{mode}{}", if default { 'h' } else { 'l' }).into_bytes(),
+                })
+            })
+            .collect();
+        if self.sgr {
+            leftover.push(Leftover {
+                name: "SGR attributes not reset".to_string(),
+                reset: b"\x1b[0m".to_vec(),
+            });
+        }
+        leftover
+    }
+
+    // Every mode the program changed and did not change back, known or not.
+    pub fn unbalanced(&self) -> Vec<String> {
+        self.modes
+            .iter()
+            .filter(|(_, (before, on))| before != on)
+            .map(|(&mode, &(_, on))| {
+                let name = KNOWN
+                    .iter()
+                    .find(|(known, _, _)| *known == mode)
+                    .map(|(_, name, _)| format!(" ({name})"))
+                    .unwrap_or_default();
+                let (change, undo) = if on { ('h', 'l') } else { ('l', 'h') };
+                format!("mode {mode}{name} {change} without {undo}")
+            })
+            .collect()
+    }
+}
+
+fn default(mode: u16) -> Option<bool> {
+    KNOWN
+        .iter()
+        .find(|(known, _, _)| *known == mode)
+        .map(|&(_, _, default)| default)
+}
@@ -0,0 +1,49 @@
+// What differs between the systems we run on: the termios flags each one has, and where
+// it puts the slaves. The rest of the Linux-only parts (ptrace, /proc, namespaces) are
+// gated where they are used.
+
+use std::path::Path;
+
+macro_rules! flag_list {
+    ($($flag: ident,)*) => {{
+        &[ $( ($flag, stringify!($flag)), )* ]
+    }};
+}
+
+#[cfg(target_os = "linux")]
+mod linux;
+#[cfg(target_os = "linux")]
+pub use linux::*;
+
+#[cfg(target_os = "macos")]
+mod macos;
+#[cfg(target_os = "macos")]
+pub use macos::*;
+
+#[cfg(target_os = "freebsd")]
+mod freebsd;
+#[cfg(target_os = "freebsd")]
+pub use freebsd::*;
+
+// What a special character holds when it is turned off: 0 on Linux, 0xff on the BSDs.
+pub const VDISABLE: libc::cc_t = libc::_POSIX_VDISABLE;
+
+pub fn slave_path(index: u32) -> String {
+    format!("{SLAVE_PREFIX}{index:0SLAVE_DIGITS$}")
+}
+
+pub fn slave_index(path: &Path) -> Option<u32> {
+    path.to_str()?.strip_prefix(SLAVE_PREFIX)?.parse().ok()
+}
+
+pub fn slaves() -> Result<Vec<u32>, std::io::Error> {
+    let (dir, stem) = SLAVE_PREFIX.rsplit_once('/').unwrap();
+    let mut indices: Vec<u32> = std::fs::read_dir(dir)?
+        .filter_map(|entry| {
+            let name = entry.ok()?.file_name();
+            name.to_str()?.strip_prefix(stem)?.parse().ok()
+        })
+        .collect();
+    indices.sort();
+    Ok(indices)
+}
@@ -0,0 +1,23 @@
+use libc::*;
+
+pub const SLAVE_PREFIX: &str = "/dev/pts/";
+pub const SLAVE_DIGITS: usize = 0;
+
+pub const IFLAGS: &[(tcflag_t, &str)] = flag_list![
+    IGNBRK, BRKINT, IGNPAR, PARMRK, INPCK, ISTRIP, INLCR, IGNCR, ICRNL, IXON, IXANY, IXOFF,
+    IMAXBEL,
+];
+pub const OFLAGS: &[(tcflag_t, &str)] =
+    flag_list![OPOST, ONLCR, TABDLY, ONOEOT, OCRNL, ONOCR, ONLRET,];
+pub const CFLAGS: &[(tcflag_t, &str)] = flag_list![
+    CIGNORE, CSIZE, CSTOPB, CREAD, PARENB, PARODD, HUPCL, CLOCAL, CCTS_OFLOW, CRTS_IFLOW,
+    CDTR_IFLOW, CDSR_OFLOW, CCAR_OFLOW,
+];
+pub const LFLAGS: &[(tcflag_t, &str)] = flag_list![
+    ISIG, ICANON, ALTWERASE, ECHO, ECHOE, ECHOK, ECHONL, ECHOCTL, ECHOPRT, ECHOKE, FLUSHO, NOFLSH,
+    NOKERNINFO, TOSTOP, PENDIN, IEXTEN, EXTPROC,
+];
+pub const CC: &[(usize, &str)] = flag_list![
+    VDISCARD, VDSUSP, VEOF, VEOL, VEOL2, VERASE, VERASE2, VINTR, VKILL, VLNEXT, VMIN, VQUIT,
+    VREPRINT, VSTART, VSTATUS, VSTOP, VSUSP, VTIME, VWERASE,
+];
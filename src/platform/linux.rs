@@ -0,0 +1,32 @@
+use ::termios::os::linux::VSWTC as VSWTCH;
+use ::termios::os::linux::{
+    tcflag_t, BRKINT, BSDLY, CBAUD, CBAUDEX, CIBAUD, CLOCAL, CMSPAR, CRDLY, CREAD, CRTSCTS, CSIZE,
+    CSTOPB, ECHO, ECHOCTL, ECHOE, ECHOK, ECHOKE, ECHONL, ECHOPRT, FFDLY, FLUSHO, HUPCL, ICANON,
+    ICRNL, IEXTEN, IGNBRK, IGNPAR, IMAXBEL, INLCR, INPCK, ISIG, ISTRIP, IUCLC, IUTF8, IXANY, IXOFF,
+    IXON, NLDLY, NOFLSH, OCRNL, OFDEL, OFILL, OLCUC, ONLCR, ONLRET, ONOCR, OPOST, PARENB, PARMRK,
+    PARODD, PENDIN, TABDLY, TOSTOP, VDISCARD, VEOF, VEOL, VEOL2, VERASE, VINTR, VKILL, VLNEXT,
+    VMIN, VQUIT, VREPRINT, VSTART, VSTOP, VSUSP, VTDLY, VTIME, VWERASE, XCASE,
+};
+
+pub const SLAVE_PREFIX: &str = "/dev/pts/";
+pub const SLAVE_DIGITS: usize = 0;
+
+pub const IFLAGS: &[(tcflag_t, &str)] = flag_list![
+    IGNBRK, BRKINT, IGNPAR, PARMRK, INPCK, ISTRIP, INLCR, ICRNL, IUCLC, IXON, IXANY, IXOFF,
+    IMAXBEL, IUTF8,
+];
+pub const OFLAGS: &[(tcflag_t, &str)] = flag_list![
+    OPOST, OLCUC, ONLCR, OCRNL, ONOCR, ONLRET, OFILL, OFDEL, NLDLY, CRDLY, TABDLY, BSDLY, VTDLY,
+    FFDLY,
+];
+pub const CFLAGS: &[(tcflag_t, &str)] = flag_list![
+    CBAUD, CBAUDEX, CSIZE, CSTOPB, CREAD, PARENB, PARODD, HUPCL, CLOCAL, CIBAUD, CMSPAR, CRTSCTS,
+];
+pub const LFLAGS: &[(tcflag_t, &str)] = flag_list![
+    ISIG, ICANON, XCASE, ECHO, ECHOE, ECHOK, ECHONL, ECHOCTL, ECHOPRT, ECHOKE, FLUSHO, NOFLSH,
+    TOSTOP, PENDIN, IEXTEN,
+];
+pub const CC: &[(usize, &str)] = flag_list![
+    VDISCARD, VEOF, VEOL, VEOL2, VERASE, VINTR, VKILL, VLNEXT, VMIN, VQUIT, VREPRINT, VSTART,
+    VSTOP, VSUSP, VSWTCH, VTIME, VWERASE,
+];
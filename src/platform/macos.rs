@@ -0,0 +1,26 @@
+use libc::*;
+
+// /dev/ttys000 and up; the masters have no directory of their own.
+pub const SLAVE_PREFIX: &str = "/dev/ttys";
+pub const SLAVE_DIGITS: usize = 3;
+
+pub const IFLAGS: &[(tcflag_t, &str)] = flag_list![
+    IGNBRK, BRKINT, IGNPAR, PARMRK, INPCK, ISTRIP, INLCR, IGNCR, ICRNL, IXON, IXANY, IXOFF,
+    IMAXBEL, IUTF8,
+];
+pub const OFLAGS: &[(tcflag_t, &str)] = flag_list![
+    OPOST, ONLCR, OXTABS, ONOEOT, OCRNL, ONOCR, ONLRET, OFILL, OFDEL, NLDLY, CRDLY, TABDLY, BSDLY,
+    VTDLY, FFDLY,
+];
+pub const CFLAGS: &[(tcflag_t, &str)] = flag_list![
+    CIGNORE, CSIZE, CSTOPB, CREAD, PARENB, PARODD, HUPCL, CLOCAL, CCTS_OFLOW, CRTS_IFLOW,
+    CDTR_IFLOW, CDSR_OFLOW, CCAR_OFLOW,
+];
+pub const LFLAGS: &[(tcflag_t, &str)] = flag_list![
+    ISIG, ICANON, ALTWERASE, ECHO, ECHOE, ECHOK, ECHONL, ECHOCTL, ECHOPRT, ECHOKE, FLUSHO, NOFLSH,
+    NOKERNINFO, TOSTOP, PENDIN, IEXTEN, EXTPROC,
+];
+pub const CC: &[(usize, &str)] = flag_list![
+    VDISCARD, VDSUSP, VEOF, VEOL, VEOL2, VERASE, VINTR, VKILL, VLNEXT, VMIN, VQUIT, VREPRINT,
+    VSTART, VSTATUS, VSTOP, VSUSP, VTIME, VWERASE,
+];
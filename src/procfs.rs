@@ -0,0 +1,86 @@
+use std::path::PathBuf;
+
+pub struct Stat {
+    pub comm: String,
+    pub state: char,
+    pub ppid: u32,
+    pub pgrp: i32,
+    pub session: i32,
+    pub tty_nr: i32,
+    pub tpgid: i32,
+}
+
+pub fn stat(pid: u32) -> Option<String> {
+    std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()
+}
+
+pub fn state(pid: u32) -> Option<char> {
+    parse(pid).map(|stat| stat.state)
+}
+
+pub fn parse(pid: u32) -> Option<Stat> {
+    let stat = stat(pid)?;
+    // The command name may contain spaces and parentheses, so skip past the last `)`.
+    let (head, rest) = stat.rsplit_once(')')?;
+    let (_, comm) = head.split_once('(')?;
+    let mut fields = rest.split_whitespace();
+
+    Some(Stat {
+        comm: comm.to_string(),
+        state: fields.next()?.chars().next()?,
+        ppid: fields.next()?.parse().ok()?,
+        pgrp: fields.next()?.parse().ok()?,
+        session: fields.next()?.parse().ok()?,
+        tty_nr: fields.next()?.parse().ok()?,
+        tpgid: fields.next()?.parse().ok()?,
+    })
+}
+
+pub struct OpenFile {
+    pub fd: u32,
+    pub target: PathBuf,
+    pub flags: i32,
+    pub tty_index: Option<u32>,
+}
+
+pub fn pids() -> Vec<u32> {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    let mut pids: Vec<u32> = entries
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .collect();
+    pids.sort();
+    pids
+}
+
+// Processes of other users are silently skipped unless we are root.
+pub fn open_files(pid: u32) -> Vec<OpenFile> {
+    let Ok(entries) = std::fs::read_dir(format!("/proc/{pid}/fd")) else {
+        return Vec::new();
+    };
+
+    let mut files: Vec<_> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let fd = entry.file_name().to_str()?.parse().ok()?;
+            let target = std::fs::read_link(entry.path()).ok()?;
+            let info = std::fs::read_to_string(format!("/proc/{pid}/fdinfo/{fd}")).ok()?;
+            let field = |name: &str| {
+                info.lines()
+                    .find_map(|line| line.strip_prefix(name))
+                    .map(str::trim)
+            };
+
+            Some(OpenFile {
+                fd,
+                target,
+                flags: i32::from_str_radix(field("flags:")?, 8).ok()?,
+                // Only ptmx descriptors have it, which tells the masters apart.
+                tty_index: field("tty-index:").and_then(|index| index.parse().ok()),
+            })
+        })
+        .collect();
+    files.sort_by_key(|file| file.fd);
+    files
+}
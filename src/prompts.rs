@@ -0,0 +1,605 @@
+use crate::capture::Recorder;
+use crate::utf8;
+
+use regex::Regex;
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+// Payload of the private OSC put in front of the prompt by --prompt-detect sentinel,
+// through PROMPT_COMMAND rather than PS1, which startup files tend to set, followed by the
+// status of the last command.
+const SENTINEL: &[u8] = b"7770;prompt;";
+pub const SENTINEL_COMMAND: &str = "printf '\\033]7770;prompt;%s\\007' $?";
+// An OSC left unterminated this long is not one.
+const MAX_OSC: usize = 256;
+// Longer is not a prompt line anyway.
+const MAX_LINE: usize = 4096;
+
+pub enum Detect {
+    // The last line of output matching, escape sequences left out.
+    Regex(Regex),
+    Sentinel,
+    // Shell integration marks: A where a prompt starts, D;STATUS where a command ended.
+    Osc133,
+}
+
+impl Detect {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "sentinel" => Some(Self::Sentinel),
+            "osc133" => Some(Self::Osc133),
+            _ => Regex::new(s.strip_prefix("regex:")?).ok().map(Self::Regex),
+        }
+    }
+}
+
+pub enum Event {
+    Command {
+        n: usize,
+        text: String,
+    },
+    Done {
+        n: usize,
+        text: String,
+        duration: Duration,
+        status: Option<i32>,
+    },
+}
+
+impl Event {
+    pub fn describe(&self) -> String {
+        match self {
+            Self::Command { n, text } => format!("COMMAND {n} {text:?}"),
+            Self::Done {
+                n,
+                text,
+                duration,
+                status,
+            } => format!(
+                "DONE {n} {text:?} {} after {:.3}s",
+                describe_status(*status),
+                duration.as_secs_f64()
+            ),
+        }
+    }
+
+    // As markers, so that split --at markers cuts the capture into commands.
+    pub fn record(&self, rec: &mut Recorder) {
+        match self {
+            Self::Command { n, text } => rec.mark(format!("command {n}: {text}")),
+            Self::Done {
+                n,
+                duration,
+                status,
+                ..
+            } => rec.mark(format!(
+                "done {n} {} after {:.3}s",
+                describe_status(*status),
+                duration.as_secs_f64()
+            )),
+        }
+    }
+}
+
+struct Running {
+    n: usize,
+    text: String,
+    start: Instant,
+}
+
+// Cuts a session into commands: from the line entered at a prompt to the next prompt. The
+// line is taken from its echo, so that history and completion show up as what they put
+// there, and from the keys typed only when nothing was echoed.
+pub struct Segmenter {
+    detect: Detect,
+    at_prompt: bool,
+    // Once Enter is typed, until the echo of the line ends.
+    submitted: bool,
+    seen: bool,
+    echo: EchoLine,
+    // Where the prompt ends on its last line, as of the first key typed.
+    prompt_len: Option<usize>,
+    typed: Vec<u8>,
+    in_escape: bool,
+    // The output since the last newline.
+    line: Vec<u8>,
+    running: Option<Running>,
+    count: usize,
+    finished: Vec<(String, Duration, Option<i32>)>,
+    // Of the last command, as the shell reported it.
+    status: Option<i32>,
+    // The unfinished last line of output, or the start of an OSC split across reads.
+    pending: Vec<u8>,
+}
+
+impl Segmenter {
+    pub fn new(detect: Detect) -> Self {
+        Self {
+            detect,
+            at_prompt: false,
+            submitted: false,
+            seen: false,
+            echo: EchoLine::default(),
+            prompt_len: None,
+            typed: Vec::new(),
+            in_escape: false,
+            line: Vec::new(),
+            running: None,
+            count: 0,
+            finished: Vec::new(),
+            status: None,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn output(&mut self, bytes: &[u8]) -> Vec<Event> {
+        let prompted = self.prompted(bytes);
+        let mut events = Vec::new();
+        if self.at_prompt {
+            events.extend(self.echoed(bytes));
+        }
+        match bytes.iter().rposition(|&b| b == b'\n') {
+            Some(i) => self.line = bytes[i + 1..].to_vec(),
+            None => self.line.extend_from_slice(bytes),
+        }
+        if self.line.len() > MAX_LINE {
+            self.line.drain(..self.line.len() - MAX_LINE);
+        }
+        // Only the first of the reads that look like a prompt counts, not its redraws.
+        if prompted && !self.at_prompt {
+            events.extend(self.prompt());
+        }
+        events
+    }
+
+    fn prompt(&mut self) -> Option<Event> {
+        self.at_prompt = true;
+        self.seen = true;
+        self.submitted = false;
+        self.prompt_len = None;
+        self.typed.clear();
+        self.echo = EchoLine::default();
+        self.echo.feed(&self.line);
+        let status = self.status.take();
+        let running = self.running.take()?;
+        let duration = running.start.elapsed();
+        self.finished.push((running.text.clone(), duration, status));
+        Some(Event::Done {
+            n: running.n,
+            text: running.text,
+            duration,
+            status,
+        })
+    }
+
+    // The echo at a prompt, up to the end of the line entered, if it ends here.
+    fn echoed(&mut self, bytes: &[u8]) -> Option<Event> {
+        let mut rest = bytes;
+        while let Some(i) = rest.iter().position(|&b| b == b'\n') {
+            self.echo.feed(&rest[..i]);
+            rest = &rest[i + 1..];
+            if std::mem::take(&mut self.submitted) {
+                if let Some(event) = self.submit() {
+                    return Some(event);
+                }
+            }
+            // A blank line, a listing of completions or a prompt of several lines.
+            self.echo = EchoLine::default();
+        }
+        self.echo.feed(rest);
+        None
+    }
+
+    fn submit(&mut self) -> Option<Event> {
+        let mut text = self.echo.text(self.prompt_len.unwrap_or(0));
+        if text.is_empty() {
+            text = String::from_utf8_lossy(&self.typed).trim().to_string();
+        }
+        self.typed.clear();
+        if text.is_empty() {
+            return None;
+        }
+        self.count += 1;
+        self.at_prompt = false;
+        self.running = Some(Running {
+            n: self.count,
+            text: text.clone(),
+            start: Instant::now(),
+        });
+        Some(Event::Command {
+            n: self.count,
+            text,
+        })
+    }
+
+    // The keys typed, with the sequences that move around or recall history left out.
+    pub fn input(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            if !self.at_prompt || self.submitted {
+                continue;
+            }
+            if self.prompt_len.is_none() {
+                self.prompt_len = Some(self.echo.cursor);
+            }
+            if self.in_escape {
+                self.in_escape = !(0x40..=0x7e).contains(&byte) || byte == b'[' || byte == b'O';
+                continue;
+            }
+            match byte {
+                b'\r' | b'\n' => self.submitted = true,
+                0x1b => self.in_escape = true,
+                0x7f | 0x08 => {
+                    self.typed.pop();
+                }
+                0x03 | 0x15 => self.typed.clear(),
+                0x20.. => self.typed.push(byte),
+                _ => {}
+            }
+        }
+    }
+
+    // Each command from submission to the next prompt, then the same command line over
+    // its runs.
+    pub fn report(&self) {
+        if !self.seen {
+            match self.detect {
+                Detect::Sentinel => println!(
+                    "TIMING no prompt seen; the shell's startup files may have replaced \
+                     PROMPT_COMMAND, or the shell is not bash"
+                ),
+                _ => println!("TIMING no prompt seen"),
+            }
+            return;
+        }
+        if self.finished.is_empty() {
+            return;
+        }
+        println!("TIMING {} commands", self.finished.len());
+        for (n, (text, duration, status)) in (1..).zip(&self.finished) {
+            let status = status.map_or("?".to_string(), |status| status.to_string());
+            println!(
+                "  {n:>4} {:>10.3}s {status:>4}  {text}",
+                duration.as_secs_f64()
+            );
+        }
+        let failed = self
+            .finished
+            .iter()
+            .filter(|(_, _, status)| status.is_some_and(|status| status != 0))
+            .count();
+        if failed > 0 {
+            println!("  {failed} failed");
+        }
+
+        let mut runs: BTreeMap<&str, Vec<Duration>> = BTreeMap::new();
+        for (text, duration, _) in &self.finished {
+            runs.entry(text.as_str()).or_default().push(*duration);
+        }
+        println!("  runs       mean        min        max  command");
+        for (text, durations) in runs {
+            let total: Duration = durations.iter().sum();
+            let mean = total / durations.len() as u32;
+            let min = durations.iter().min().unwrap();
+            let max = durations.iter().max().unwrap();
+            println!(
+                "  {:>4} {:>9.3}s {:>9.3}s {:>9.3}s  {text}",
+                durations.len(),
+                mean.as_secs_f64(),
+                min.as_secs_f64(),
+                max.as_secs_f64()
+            );
+        }
+    }
+
+    fn prompted(&mut self, bytes: &[u8]) -> bool {
+        self.pending.extend_from_slice(bytes);
+        match &self.detect {
+            Detect::Regex(re) => {
+                if let Some(i) = self.pending.iter().rposition(|&b| b == b'\n') {
+                    self.pending.drain(..=i);
+                }
+                if self.pending.len() > MAX_LINE {
+                    self.pending.drain(..self.pending.len() - MAX_LINE);
+                }
+                let line = String::from_utf8_lossy(&strip_escapes(&self.pending)).into_owned();
+                re.is_match(line.trim_end_matches('\r'))
+            }
+            Detect::Sentinel => {
+                let mut prompted = false;
+                for osc in self.oscs() {
+                    if let Some(status) = osc.strip_prefix(SENTINEL) {
+                        self.status = parse_status(status);
+                        prompted = true;
+                    }
+                }
+                prompted
+            }
+            Detect::Osc133 => {
+                let mut prompted = false;
+                for osc in self.oscs() {
+                    if let Some(rest) = osc.strip_prefix(b"133;D") {
+                        self.status = rest.strip_prefix(b";").and_then(parse_status);
+                    } else if osc.starts_with(b"133;A") {
+                        prompted = true;
+                    }
+                }
+                prompted
+            }
+        }
+    }
+
+    // The payloads of the OSCs complete in what was read, keeping an unfinished one for
+    // the next read, though not forever.
+    fn oscs(&mut self) -> Vec<Vec<u8>> {
+        let mut oscs = Vec::new();
+        let mut keep = self.pending.len();
+        let mut from = 0;
+        while let Some(start) = find(&self.pending[from..], b"\x1b]").map(|i| from + i) {
+            let body = &self.pending[start + 2..];
+            match body.iter().position(|&b| b == 0x07 || b == 0x1b) {
+                Some(end) => {
+                    oscs.push(body[..end].to_vec());
+                    from = start + 2 + end;
+                }
+                None => {
+                    keep = start;
+                    break;
+                }
+            }
+        }
+        if self.pending.len() - keep > MAX_OSC {
+            keep = self.pending.len();
+        }
+        if keep == self.pending.len() && self.pending.last() == Some(&0x1b) {
+            keep -= 1;
+        }
+        self.pending.drain(..keep);
+        oscs
+    }
+}
+
+// The line as the terminal shows it, from its echo: enough of the cursor movement that line
+// editors redraw with, and no more.
+struct EchoLine {
+    decoder: utf8::Decoder,
+    text: Vec<char>,
+    cursor: usize,
+    escape: Option<String>,
+}
+
+impl Default for EchoLine {
+    fn default() -> Self {
+        Self {
+            decoder: utf8::Decoder::new(),
+            text: Vec::new(),
+            cursor: 0,
+            escape: None,
+        }
+    }
+}
+
+impl EchoLine {
+    fn feed(&mut self, bytes: &[u8]) {
+        for c in self.decoder.decode(bytes).chars() {
+            if let Some(seq) = &mut self.escape {
+                seq.push(c);
+                if escape_done(seq) {
+                    let seq = self.escape.take().unwrap();
+                    self.csi(&seq);
+                }
+                continue;
+            }
+            match c {
+                '\x1b' => self.escape = Some(String::new()),
+                '\r' => self.cursor = 0,
+                '\x08' => self.cursor = self.cursor.saturating_sub(1),
+                c if c.is_control() => {}
+                c => {
+                    if self.cursor >= self.text.len() {
+                        self.text.resize(self.cursor, ' ');
+                        self.text.push(c);
+                    } else {
+                        self.text[self.cursor] = c;
+                    }
+                    self.cursor += 1;
+                }
+            }
+        }
+    }
+
+    fn csi(&mut self, seq: &str) {
+        let Some(params) = seq.strip_prefix('[') else {
+            return;
+        };
+        let Some(end) = params.chars().last() else {
+            return;
+        };
+        let params = &params[..params.len() - end.len_utf8()];
+        let n = params.parse::<usize>().unwrap_or(1).max(1);
+        let at = self.cursor.min(self.text.len());
+        match end {
+            'C' => self.cursor += n,
+            'D' => self.cursor = self.cursor.saturating_sub(n),
+            'G' => self.cursor = n - 1,
+            'K' if params == "2" => self.text.clear(),
+            'K' => self.text.truncate(at),
+            'P' => {
+                self.text.drain(at..(at + n).min(self.text.len()));
+            }
+            '@' => {
+                self.text.splice(at..at, std::iter::repeat_n(' ', n));
+            }
+            _ => {}
+        }
+    }
+
+    fn text(&self, from: usize) -> String {
+        let from = from.min(self.text.len());
+        self.text[from..]
+            .iter()
+            .collect::<String>()
+            .trim()
+            .to_string()
+    }
+}
+
+// CSI up to its final byte, OSC up to BEL or ST, and the other escapes after a byte or two.
+fn escape_done(seq: &str) -> bool {
+    let mut chars = seq.chars();
+    match chars.next() {
+        Some('[') => seq.len() > 1 && seq.ends_with(|c| ('@'..='~').contains(&c)),
+        Some(']') => seq.ends_with('\x07') || seq.ends_with("\x1b\\"),
+        Some('(' | ')' | '#') => seq.len() > 1,
+        _ => true,
+    }
+}
+
+fn parse_status(bytes: &[u8]) -> Option<i32> {
+    std::str::from_utf8(bytes)
+        .ok()?
+        .split(';')
+        .next()?
+        .parse()
+        .ok()
+}
+
+fn describe_status(status: Option<i32>) -> String {
+    match status {
+        Some(status) => format!("status {status}"),
+        None => "status unknown".to_string(),
+    }
+}
+
+// CSI and OSC sequences, which colored prompts are full of.
+fn strip_escapes(bytes: &[u8]) -> Vec<u8> {
+    let mut text = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        match (bytes[i], bytes.get(i + 1)) {
+            (0x1b, Some(b'[')) => {
+                i += 2;
+                while i < bytes.len() && !(0x40..=0x7e).contains(&bytes[i]) {
+                    i += 1;
+                }
+                i += 1;
+            }
+            (0x1b, Some(b']')) => {
+                i += 2;
+                while i < bytes.len() && bytes[i] != 0x07 && bytes[i] != 0x1b {
+                    i += 1;
+                }
+                // BEL, or the ESC \ of ST.
+                i += if bytes.get(i) == Some(&0x1b) { 2 } else { 1 };
+            }
+            (byte, _) => {
+                text.push(byte);
+                i += 1;
+            }
+        }
+    }
+    text
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commands(events: Vec<Event>) -> Vec<String> {
+        events
+            .into_iter()
+            .filter_map(|event| match event {
+                Event::Command { text, .. } => Some(text),
+                Event::Done { .. } => None,
+            })
+            .collect()
+    }
+
+    fn sentinel(status: u8) -> Vec<u8> {
+        format!("\x1b]7770;prompt;{status}\x07$ ").into_bytes()
+    }
+
+    #[test]
+    fn command_from_echo() {
+        let mut seg = Segmenter::new(Detect::Sentinel);
+        assert!(seg.output(&sentinel(0)).is_empty());
+        seg.input(b"l\x1b[A\r");
+        // Up recalls a line from history, which only the echo shows.
+        assert!(seg.output(b"l").is_empty());
+        assert!(seg.output(b"\x08\x1b[Kls -la").is_empty());
+        assert_eq!(commands(seg.output(b"\r\n")), ["ls -la"]);
+    }
+
+    #[test]
+    fn echo_edits() {
+        let mut echo = EchoLine::default();
+        echo.feed(b"$ git stauts\x08\x08\x08\x1b[2Ptus");
+        assert_eq!(echo.text(2), "git status");
+        echo.feed(b"\r\x1b[2C\x1b[3@cd  \x1b[K");
+        assert_eq!(echo.text(2), "cd");
+        echo.feed("\x1b]0;tïtle\x07\x1b[5G é".as_bytes());
+        assert_eq!(echo.text(2), "cd é");
+    }
+
+    #[test]
+    fn falls_back_to_keys() {
+        let mut seg = Segmenter::new(Detect::Regex(Regex::new(r"\$ $").unwrap()));
+        seg.output(b"motd\r\n$ ");
+        seg.input(b"pwd\x1b[D\x1bOC\r");
+        // Nothing echoed, with echo off.
+        assert_eq!(commands(seg.output(b"\r\n")), ["pwd"]);
+    }
+
+    #[test]
+    fn done_with_status() {
+        let mut seg = Segmenter::new(Detect::Sentinel);
+        seg.output(&sentinel(0));
+        seg.input(b"false\r");
+        seg.output(b"false\r\n");
+        let events = seg.output(b"\x1b]7770;pro");
+        assert!(events.is_empty());
+        match seg.output(b"mpt;1\x07$ ").as_slice() {
+            [Event::Done {
+                n, text, status, ..
+            }] => {
+                assert_eq!((*n, text.as_str(), *status), (1, "false", Some(1)))
+            }
+            events => panic!("{}", events.len()),
+        }
+    }
+
+    #[test]
+    fn oscs_across_reads() {
+        let mut seg = Segmenter::new(Detect::Osc133);
+        seg.pending.extend_from_slice(b"out\x1b]133;D;2\x07\x1b");
+        assert_eq!(seg.oscs(), [b"133;D;2".to_vec()]);
+        assert_eq!(seg.pending, b"\x1b");
+        seg.pending.extend_from_slice(b"]133;A\x1b\\");
+        assert_eq!(seg.oscs(), [b"133;A".to_vec()]);
+        seg.pending.extend_from_slice(b"\x1b]0;unterminated");
+        assert!(seg.oscs().is_empty());
+        assert_eq!(seg.pending, b"\x1b]0;unterminated");
+    }
+
+    #[test]
+    fn strips_escapes() {
+        assert_eq!(
+            strip_escapes(b"\x1b[1;32muser\x1b[0m@\x1b]0;title\x07host\x1b]2;t\x1b\\ $ "),
+            b"user@host $ "
+        );
+        assert_eq!(strip_escapes(b"cut\x1b["), b"cut");
+    }
+
+    #[test]
+    fn no_prompt_seen() {
+        let mut seg = Segmenter::new(Detect::Sentinel);
+        seg.output(b"bash-5.2$ ");
+        seg.input(b"ls\r");
+        assert!(commands(seg.output(b"ls\r\n")).is_empty());
+        assert!(!seg.seen);
+    }
+}
@@ -0,0 +1,98 @@
+use nix::errno::Errno;
+
+use std::os::fd::RawFd;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Strategy {
+    // One read per wakeup, so chunks show how the output trickled in.
+    Once,
+    // Read on while more is ready at once, like an event loop that drains until EAGAIN.
+    Drain,
+}
+
+impl Strategy {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "once" => Some(Self::Once),
+            "drain" => Some(Self::Drain),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Once => "once",
+            Self::Drain => "drain",
+        }
+    }
+}
+
+// Interrupted calls are restarted. EAGAIN still reaches the caller, which may want to
+// account for it before waiting.
+pub fn read(fd: RawFd, buf: &mut [u8]) -> Result<usize, Errno> {
+    loop {
+        match nix::unistd::read(fd, buf) {
+            Err(Errno::EINTR) => continue,
+            res => return res,
+        }
+    }
+}
+
+// EAGAIN or EIO after the first read are left for the next call to report.
+pub fn read_chunk(fd: RawFd, buf: &mut [u8], strategy: Strategy) -> Result<usize, Errno> {
+    let mut filled = read(fd, buf)?;
+    if strategy == Strategy::Drain {
+        while filled > 0 && filled < buf.len() && ready(fd) {
+            match read(fd, &mut buf[filled..]) {
+                Ok(0) | Err(_) => break,
+                Ok(num_bytes) => filled += num_bytes,
+            }
+        }
+    }
+    Ok(filled)
+}
+
+fn ready(fd: RawFd) -> bool {
+    let mut fds = [libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    }];
+    let ready = unsafe { libc::poll(fds.as_mut_ptr(), 1, 0) };
+    ready > 0 && fds[0].revents & libc::POLLIN != 0
+}
+
+// A write to the master takes only what fits in the input queue, so this keeps going
+// until all of `buf` is out. Returns how many writes came up short, or how far it got.
+pub fn write_all(fd: RawFd, buf: &[u8]) -> Result<usize, (usize, Errno)> {
+    let mut written = 0;
+    let mut short = 0;
+    while written < buf.len() {
+        match nix::unistd::write(fd, &buf[written..]) {
+            Ok(num_bytes) => {
+                written += num_bytes;
+                if written < buf.len() {
+                    short += 1;
+                }
+            }
+            Err(Errno::EINTR) => {}
+            Err(Errno::EAGAIN) => wait(fd, libc::POLLOUT),
+            Err(e) => return Err((written, e)),
+        }
+    }
+    Ok(short)
+}
+
+// Blocks until a non-blocking fd is ready, or has hung up.
+pub fn wait(fd: RawFd, events: libc::c_short) {
+    let mut fds = [libc::pollfd {
+        fd,
+        events,
+        revents: 0,
+    }];
+    while unsafe { libc::poll(fds.as_mut_ptr(), 1, -1) } == -1 {
+        if Errno::last() != Errno::EINTR {
+            break;
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use crate::procfs;
+
+use std::io::Error as IoError;
+use std::os::unix::process::ExitStatusExt as _;
+use std::process::ExitStatus;
+
+// Orphaned descendants of the child are reparented to us instead of init, so pipelines
+// the shell lost track of still show up with their exit status.
+#[cfg(target_os = "linux")]
+pub fn enable() -> Result<(), IoError> {
+    if unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1) } == -1 {
+        return Err(IoError::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "freebsd")]
+pub fn enable() -> Result<(), IoError> {
+    let res = unsafe {
+        libc::procctl(
+            libc::P_PID,
+            0,
+            libc::PROC_REAP_ACQUIRE,
+            std::ptr::null_mut(),
+        )
+    };
+    if res == -1 {
+        return Err(IoError::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+pub fn enable() -> Result<(), IoError> {
+    Err(IoError::from(std::io::ErrorKind::Unsupported))
+}
+
+pub fn spawn_reaper(child: u32) {
+    std::thread::spawn(move || loop {
+        // WNOWAIT leaves the zombie in place, so the direct child is still there for
+        // `Child::wait` and the others can be looked up in /proc before they are reaped.
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        let res = unsafe { libc::waitid(libc::P_ALL, 0, &mut info, libc::WEXITED | libc::WNOWAIT) };
+        if res == -1 {
+            let e = IoError::last_os_error();
+            if e.raw_os_error() == Some(libc::EINTR) {
+                continue;
+            }
+            break;
+        }
+
+        let pid = unsafe { info.si_pid() };
+        if pid as u32 == child {
+            break;
+        }
+
+        let stat = procfs::parse(pid as u32);
+        let mut status = 0;
+        if unsafe { libc::waitpid(pid, &mut status, 0) } == -1 {
+            break;
+        }
+        let status = ExitStatus::from_raw(status);
+        match stat {
+            Some(stat) => println!(
+                "REAPED {pid} ({}) pgrp {} session {} tty {:#x}: {status}",
+                stat.comm, stat.pgrp, stat.session, stat.tty_nr
+            ),
+            None => println!("REAPED {pid}: {status}"),
+        }
+    });
+}
@@ -0,0 +1,43 @@
+use crate::input;
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+// Output read from the master while a region is open, before any filter touches it.
+#[derive(Default)]
+pub struct Regions {
+    open: BTreeMap<String, Vec<u8>>,
+}
+
+impl Regions {
+    // Starting a region that is already open starts it over.
+    pub fn start(&mut self, name: &str) {
+        self.open.insert(name.to_string(), Vec::new());
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) {
+        for region in self.open.values_mut() {
+            region.extend_from_slice(bytes);
+        }
+    }
+
+    pub fn end(&mut self, name: &str, path: &Path) -> Result<usize, String> {
+        let bytes = self
+            .open
+            .remove(name)
+            .ok_or_else(|| format!("no capture {name} was started"))?;
+        std::fs::write(path, &bytes).map_err(|e| format!("{}: {e}", path.display()))?;
+        Ok(bytes.len())
+    }
+}
+
+// `NAME --to PATH`
+pub fn parse_end(arg: &str) -> Result<(String, PathBuf), String> {
+    let (name, rest) = input::split_token(arg);
+    let path = rest
+        .strip_prefix("--to")
+        .map(str::trim)
+        .filter(|path| !path.is_empty())
+        .ok_or("expected NAME --to PATH")?;
+    Ok((name.to_string(), PathBuf::from(input::unquote(path))))
+}
@@ -0,0 +1,120 @@
+use crate::capture::{self, Event};
+use crate::{flags, open_pty, print_text, rawio, spawn_child, utf8, Args};
+
+use nix::errno::Errno;
+use nix::pty::Winsize;
+
+use termios::Termios;
+
+use std::os::fd::AsRawFd as _;
+use std::path::Path;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+// Runs the captured program again on a fresh pty set up like the original one, and types
+// the captured input into it with the original timing. Options given on the command line
+// win over what the capture says.
+pub fn run(
+    path: &Path,
+    speed: f64,
+    mut args: Args,
+) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    if speed.is_nan() || speed <= 0.0 {
+        return Err(format!("invalid speed {speed}").into());
+    }
+    let mut records = capture::records(path)?;
+    let Some(Event::Header { meta, .. }) = records.next().transpose()?.map(|record| record.event)
+    else {
+        return Err(format!("{}: no header", path.display()).into());
+    };
+    let inputs: Vec<(f64, Vec<u8>)> = records
+        .filter_map(|record| match record {
+            Ok(capture::Record {
+                t,
+                event: Event::Input { data, .. },
+            }) => Some(Ok((t, data))),
+            Ok(_) => None,
+            Err(e) => Some(Err(e)),
+        })
+        .collect::<Result<_, _>>()?;
+
+    let snapshot = match meta {
+        Some(meta) => {
+            if args.exec.is_empty() && args.command.is_empty() {
+                args.exec = meta.command;
+            }
+            if args.winsize.is_none() {
+                args.winsize = meta.winsize.map(|(rows, cols)| Winsize {
+                    ws_row: rows,
+                    ws_col: cols,
+                    ws_xpixel: 0,
+                    ws_ypixel: 0,
+                });
+            }
+            for name in &meta.redacted {
+                if !args.env.iter().any(|(given, _)| given == name) {
+                    println!("WARNING {name} was left out of the capture, give it with --env");
+                }
+            }
+            let mut env = meta.env;
+            env.append(&mut args.env);
+            args.env = env;
+            meta.termios
+        }
+        None => {
+            println!("WARNING the capture has no metadata, running with the current settings");
+            None
+        }
+    };
+
+    let pty = open_pty(args.alloc, args.winsize.as_ref())?;
+    let master = pty.master.as_raw_fd();
+    let mut term = Termios::from_fd(master)?;
+    if let Some(snapshot) = &snapshot {
+        for missing in snapshot.restore(&mut term) {
+            println!("WARNING termios {missing} cannot be set on this platform");
+        }
+    }
+    for spec in &args.termios {
+        flags::apply(&mut term, spec)?;
+    }
+    termios::tcsetattr(master, termios::TCSANOW, &term)?;
+    if let Some(size) = &args.winsize {
+        println!("Winsize {}x{}", size.ws_row, size.ws_col);
+    }
+    crate::debug_termios(&term);
+    let mut child = spawn_child(&mut args, pty.slave)?;
+
+    std::thread::spawn(move || {
+        let start = Instant::now();
+        for (t, data) in inputs {
+            let due = Duration::from_secs_f64(t / speed);
+            std::thread::sleep(due.saturating_sub(start.elapsed()));
+            println!("> {data:02x?}");
+            if rawio::write_all(master, &data).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut decoder = utf8::Decoder::new();
+    let mut buf = vec![0; args.read_buffer.unwrap_or(4096)];
+    loop {
+        match rawio::read_chunk(master, &mut buf, args.read_strategy) {
+            Ok(0) | Err(Errno::EIO) => break,
+            Ok(num_bytes) => {
+                print_text(
+                    "READ",
+                    &decoder.decode(&buf[..num_bytes]),
+                    &buf[..num_bytes],
+                );
+                println!();
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let status = child.wait()?;
+    println!("Child exited with {status}");
+    Ok(ExitCode::from(status.code().unwrap_or(1) as u8))
+}
@@ -0,0 +1,118 @@
+use crate::capture::{self, Event};
+use crate::cli::SearchArgs;
+use crate::input;
+
+use regex::bytes::Regex;
+
+// Names for the bytes and introducers of control sequences, for --seq.
+const NAMES: &[(&str, &[u8])] = &[
+    ("ESC", b"\x1b"),
+    ("CSI", b"\x1b["),
+    ("OSC", b"\x1b]"),
+    ("DCS", b"\x1bP"),
+    ("SS3", b"\x1bO"),
+    ("ST", b"\x1b\\"),
+    ("BEL", b"\x07"),
+    ("BS", b"\x08"),
+    ("HT", b"\t"),
+    ("LF", b"\n"),
+    ("CR", b"\r"),
+    ("NUL", b"\0"),
+    ("DEL", b"\x7f"),
+];
+
+struct Stream {
+    label: &'static str,
+    bytes: Vec<u8>,
+    // Where each event starts in `bytes`, with its time.
+    events: Vec<(usize, f64)>,
+}
+
+impl Stream {
+    fn new(label: &'static str) -> Self {
+        Self {
+            label,
+            bytes: Vec::new(),
+            events: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, t: f64, data: &[u8]) {
+        self.events.push((self.bytes.len(), t));
+        self.bytes.extend_from_slice(data);
+    }
+}
+
+// Looks through the output, and the input with --input, as one stream each, so that a
+// sequence split across reads is still found.
+pub fn run(args: &SearchArgs) -> Result<bool, Box<dyn std::error::Error>> {
+    let pattern = match (&args.seq, &args.regex, &args.bytes) {
+        (Some(seq), _, _) => Regex::new(&literal(&parse_seq(seq)))?,
+        (_, Some(regex), _) => Regex::new(regex)?,
+        (_, _, Some(bytes)) => Regex::new(&literal(&input::parse_bytes(bytes)?))?,
+        _ => return Err("give one of --seq, --regex or --bytes".into()),
+    };
+
+    let mut output = Stream::new("output");
+    let mut input = Stream::new("input");
+    for record in capture::records(&args.file)? {
+        let record = record?;
+        match &record.event {
+            Event::Output { data } => output.push(record.t, data),
+            Event::Input { data, .. } if args.input => input.push(record.t, data),
+            _ => {}
+        }
+    }
+
+    let mut found = 0;
+    for stream in [&output, &input] {
+        for m in pattern.find_iter(&stream.bytes) {
+            found += 1;
+            let event = stream
+                .events
+                .partition_point(|&(start, _)| start <= m.start())
+                .saturating_sub(1);
+            let (start, t) = stream.events[event];
+            println!(
+                "MATCH {found} at {t:.3}s in {} event {}, byte {}",
+                stream.label,
+                event + 1,
+                m.start() - start
+            );
+            let before = &stream.bytes[m.start().saturating_sub(args.context)..m.start()];
+            let after = &stream.bytes[m.end()..(m.end() + args.context).min(stream.bytes.len())];
+            println!("  before {:?}", String::from_utf8_lossy(before));
+            println!("  match  {:?}", String::from_utf8_lossy(m.as_bytes()));
+            println!("  after  {:?}", String::from_utf8_lossy(after));
+        }
+    }
+    if found == 0 {
+        println!("No match in {}", args.file.display());
+    }
+    Ok(found > 0)
+}
+
+// `CSI ? 1049 h`: names from the table, 0xNN for any byte, and everything else as it is
+// written, all run together.
+fn parse_seq(seq: &str) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for token in seq.split_whitespace() {
+        if let Some(&(_, named)) = NAMES.iter().find(|(name, _)| *name == token) {
+            bytes.extend_from_slice(named);
+        } else if let Some(byte) = token
+            .strip_prefix("0x")
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            bytes.push(byte);
+        } else {
+            bytes.extend_from_slice(token.as_bytes());
+        }
+    }
+    bytes
+}
+
+// Raw bytes rather than UTF-8, so that any byte can be looked for.
+fn literal(bytes: &[u8]) -> String {
+    let escaped: String = bytes.iter().map(|byte| format!("\\x{byte:02x}")).collect();
+    format!("(?-u){escaped}")
+}
@@ -0,0 +1,147 @@
+use nix::errno::Errno;
+use nix::pty::Winsize;
+use nix::sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal};
+
+use termios::{Termios, ECHO, TCSANOW};
+
+use std::io::{Error as IoError, Write as _};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+const HELP: &str = "\
+commands:
+  echo on|off     toggle ECHO on the tty
+  raw             switch to raw mode and report every byte until q
+  partial         write an escape sequence in two halves, 500ms apart
+  ignore-winch    ignore SIGWINCH
+  watch-winch     report SIGWINCH with the new size (the default)
+  exit [CODE]     exit with CODE";
+
+static WINCH: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_winch(_: libc::c_int) {
+    WINCH.store(true, Ordering::Relaxed);
+}
+
+// Runs as the child on the slave: stdin, stdout and the tty are the same thing here.
+pub fn run() -> Result<(), IoError> {
+    watch_winch(true)?;
+    println!("self-test ready, type help");
+
+    let mut line = Vec::new();
+    loop {
+        prompt()?;
+        line.clear();
+        if !read_line(&mut line)? {
+            return Ok(());
+        }
+
+        let line = String::from_utf8_lossy(&line);
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next()) {
+            (None, _) => {}
+            (Some("help"), _) => println!("{HELP}"),
+            (Some("echo"), Some(state @ ("on" | "off"))) => {
+                let mut term = Termios::from_fd(0)?;
+                if state == "on" {
+                    term.c_lflag |= ECHO;
+                } else {
+                    term.c_lflag &= !ECHO;
+                }
+                termios::tcsetattr(0, TCSANOW, &term)?;
+                println!("ECHO {state}");
+            }
+            (Some("raw"), _) => raw()?,
+            (Some("partial"), _) => {
+                let mut stdout = std::io::stdout();
+                stdout.write_all(b"\x1b[")?;
+                stdout.flush()?;
+                std::thread::sleep(Duration::from_millis(500));
+                stdout.write_all(b"1mbold\x1b[0m\n")?;
+            }
+            (Some("ignore-winch"), _) => {
+                watch_winch(false)?;
+                println!("ignoring SIGWINCH");
+            }
+            (Some("watch-winch"), _) => {
+                watch_winch(true)?;
+                println!("watching SIGWINCH");
+            }
+            (Some("exit"), code) => {
+                let code = code.and_then(|code| code.parse().ok()).unwrap_or(0);
+                std::process::exit(code);
+            }
+            (Some(word), _) => println!("unknown command {word:?}, type help"),
+        }
+    }
+}
+
+fn prompt() -> Result<(), IoError> {
+    let mut stdout = std::io::stdout();
+    stdout.write_all(b"self-test> ")?;
+    stdout.flush()
+}
+
+// Reads byte by byte with read(2) so that a SIGWINCH interrupts it and is reported
+// right away. Returns false at EOF.
+fn read_line(line: &mut Vec<u8>) -> Result<bool, IoError> {
+    let mut byte = [0];
+    loop {
+        match nix::unistd::read(0, &mut byte) {
+            Ok(0) => return Ok(!line.is_empty()),
+            Ok(_) if byte[0] == b'\n' => return Ok(true),
+            Ok(_) => line.push(byte[0]),
+            Err(Errno::EINTR) => report_winch(),
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+fn raw() -> Result<(), IoError> {
+    let saved = Termios::from_fd(0)?;
+    let mut term = saved;
+    termios::cfmakeraw(&mut term);
+    termios::tcsetattr(0, TCSANOW, &term)?;
+    // OPOST is off, so every line ends with an explicit \r.
+    print!("raw mode, q to leave\r\n");
+
+    let mut byte = [0];
+    let result = loop {
+        match nix::unistd::read(0, &mut byte) {
+            Ok(0) => break Ok(()),
+            Ok(_) if byte[0] == b'q' => break Ok(()),
+            Ok(_) => print!("byte {:#04x}\r\n", byte[0]),
+            Err(Errno::EINTR) => report_winch(),
+            Err(e) => break Err(e.into()),
+        }
+        let _ = std::io::stdout().flush();
+    };
+
+    termios::tcsetattr(0, TCSANOW, &saved)?;
+    println!("cooked mode");
+    result
+}
+
+fn watch_winch(watch: bool) -> Result<(), IoError> {
+    // No SA_RESTART: the pending read returns EINTR and the size is printed at once.
+    let handler = if watch {
+        SigHandler::Handler(on_winch)
+    } else {
+        SigHandler::SigIgn
+    };
+    let action = SigAction::new(handler, SaFlags::empty(), SigSet::empty());
+    unsafe { signal::sigaction(Signal::SIGWINCH, &action) }?;
+    Ok(())
+}
+
+fn report_winch() {
+    if !WINCH.swap(false, Ordering::Relaxed) {
+        return;
+    }
+    let mut winsize: Winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(0, libc::TIOCGWINSZ, &mut winsize) } == -1 {
+        println!("\r\nSIGWINCH, size unavailable\r");
+    } else {
+        println!("\r\nSIGWINCH {}x{}\r", winsize.ws_row, winsize.ws_col);
+    }
+}
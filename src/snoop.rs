@@ -0,0 +1,119 @@
+use nix::errno::Errno;
+use nix::sys::ptrace::{self, Options};
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::Pid;
+
+use std::fs::File;
+use std::os::unix::fs::FileExt as _;
+use std::path::PathBuf;
+
+pub struct Syscall {
+    pub nr: i64,
+    pub args: [u64; 3],
+    // None at syscall entry.
+    pub ret: Option<i64>,
+}
+
+#[cfg(target_arch = "x86_64")]
+pub fn syscall(pid: Pid) -> Result<Syscall, Errno> {
+    let regs = ptrace::getregs(pid)?;
+    // The kernel sets rax to -ENOSYS on entry, which tells the two stops apart even when
+    // we attached in the middle of a blocking call.
+    let entry = regs.rax as i64 == -(libc::ENOSYS as i64);
+    Ok(Syscall {
+        nr: regs.orig_rax as i64,
+        args: [regs.rdi, regs.rsi, regs.rdx],
+        ret: (!entry).then_some(regs.rax as i64),
+    })
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+pub fn syscall(pid: Pid) -> Result<Syscall, Errno> {
+    Err(Errno::ENOTSUP)
+}
+
+pub fn tty_path(pid: Pid, fd: i32) -> Option<PathBuf> {
+    let target = std::fs::read_link(format!("/proc/{pid}/fd/{fd}")).ok()?;
+    let name = target.to_str()?;
+    let is_tty = name.starts_with("/dev/pts/")
+        || name.starts_with("/dev/tty")
+        || name == "/dev/ptmx"
+        || name == "/dev/console";
+    is_tty.then_some(target)
+}
+
+pub fn read_memory(pid: Pid, addr: u64, len: usize) -> Option<Vec<u8>> {
+    let mem = File::open(format!("/proc/{pid}/mem")).ok()?;
+    let mut buf = vec![0; len];
+    mem.read_exact_at(&mut buf, addr).ok()?;
+    Some(buf)
+}
+
+// Resumes the tracee until its next syscall stop, passing on any signal it stopped with.
+pub fn resume(status: WaitStatus) -> Result<(), Errno> {
+    match status {
+        WaitStatus::Stopped(pid, signal) => ptrace::syscall(pid, signal),
+        WaitStatus::PtraceSyscall(pid) | WaitStatus::PtraceEvent(pid, _, _) => {
+            ptrace::syscall(pid, None)
+        }
+        _ => Ok(()),
+    }
+}
+
+// Only the thread PID is traced; reads and writes from its other threads are not seen.
+pub fn run(pid: i32) -> Result<(), Box<dyn std::error::Error>> {
+    let pid = Pid::from_raw(pid);
+    ptrace::seize(pid, Options::PTRACE_O_TRACESYSGOOD)
+        .map_err(|e| format!("could not attach to {pid}: {e} (check ptrace_scope)"))?;
+    ptrace::interrupt(pid)?;
+    println!("SNOOP attached to {pid}, detaching when this process exits");
+    println!();
+
+    loop {
+        let status = waitpid(pid, None)?;
+        match status {
+            WaitStatus::Exited(_, code) => {
+                println!("SNOOP {pid} exited with status {code}");
+                break;
+            }
+            WaitStatus::Signaled(_, signal, _) => {
+                println!("SNOOP {pid} killed by {signal}");
+                break;
+            }
+            WaitStatus::PtraceSyscall(_) => {
+                let call = syscall(pid)?;
+                if let Some(ret) = call.ret {
+                    report_io("SNOOP", pid, &call, ret);
+                }
+            }
+            _ => {}
+        }
+        resume(status)?;
+    }
+
+    Ok(())
+}
+
+pub fn report_io(prefix: &str, pid: Pid, call: &Syscall, ret: i64) {
+    let what = match call.nr {
+        libc::SYS_read => "read",
+        libc::SYS_write => "write",
+        _ => return,
+    };
+    if ret <= 0 {
+        return;
+    }
+    let fd = call.args[0] as i32;
+    let Some(tty) = tty_path(pid, fd) else {
+        return;
+    };
+    let Some(buf) = read_memory(pid, call.args[1], ret as usize) else {
+        return;
+    };
+
+    crate::print_chunk(
+        &format!("{prefix} {pid} {what} fd {fd} {}", tty.display()),
+        &buf,
+    );
+    println!();
+}
@@ -0,0 +1,139 @@
+use crate::rawio;
+
+use nix::errno::Errno;
+use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+
+use std::fs::File;
+use std::io::Write as _;
+use std::os::fd::{AsRawFd as _, FromRawFd as _, OwnedFd, RawFd};
+
+// The default capacity of a pipe; a splice into an empty one never comes up short.
+const PIPE_SIZE: usize = 1 << 16;
+
+// Writes the raw output to a file on the way to the decoded view: the bytes are spliced
+// from the master into a pipe, teed into a second one for the view and spliced on to the
+// file, so that the file never costs a copy through userspace. The copy the view makes
+// is the same one a plain read would.
+pub struct Splicer {
+    file: File,
+    staged: (OwnedFd, OwnedFd),
+    view: (OwnedFd, OwnedFd),
+    // Before 6.5 ttys cannot be spliced from; then the file is written with plain copies.
+    copying: bool,
+}
+
+impl Splicer {
+    pub fn new(file: File) -> Result<Self, Errno> {
+        Ok(Self {
+            file,
+            staged: pipe()?,
+            view: pipe()?,
+            copying: cfg!(not(target_os = "linux")),
+        })
+    }
+
+    // Like read(2), also writing what was read to the file.
+    pub fn read(&mut self, master: RawFd, buf: &mut [u8]) -> Result<usize, Errno> {
+        if !self.copying {
+            match self.splice_read(master, buf) {
+                Err(Errno::EINVAL) => {
+                    tracing::info!("the master cannot be spliced from, copying the raw log");
+                    self.copying = true;
+                }
+                res => return res,
+            }
+        }
+
+        let num_bytes = rawio::read(master, buf)?;
+        if let Err(e) = self.file.write_all(&buf[..num_bytes]) {
+            tracing::warn!("could not write the raw log: {e}");
+        }
+        Ok(num_bytes)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn splice_read(&mut self, master: RawFd, buf: &mut [u8]) -> Result<usize, Errno> {
+        let (staged_out, staged_in) = (self.staged.0.as_raw_fd(), self.staged.1.as_raw_fd());
+        let (view_out, view_in) = (self.view.0.as_raw_fd(), self.view.1.as_raw_fd());
+
+        let len = buf.len().min(PIPE_SIZE);
+        let num_bytes = retry(|| unsafe {
+            libc::splice(
+                master,
+                std::ptr::null_mut(),
+                staged_in,
+                std::ptr::null_mut(),
+                len,
+                0,
+            )
+        })?;
+        if num_bytes == 0 {
+            return Ok(0);
+        }
+
+        // The view pipe is emptied below every time, so the tee takes everything.
+        let teed = retry(|| unsafe { libc::tee(staged_out, view_in, num_bytes, 0) })?;
+
+        let mut moved = 0;
+        while moved < num_bytes {
+            let res = retry(|| unsafe {
+                libc::splice(
+                    staged_out,
+                    std::ptr::null_mut(),
+                    self.file.as_raw_fd(),
+                    std::ptr::null_mut(),
+                    num_bytes - moved,
+                    libc::SPLICE_F_MOVE,
+                )
+            });
+            match res {
+                Ok(spliced) => moved += spliced,
+                Err(e) => {
+                    // What is left must still leave the pipe, or the next splice blocks.
+                    tracing::warn!("could not splice into the raw log: {e}");
+                    let mut rest = vec![0; num_bytes - moved];
+                    read_exact(staged_out, &mut rest)?;
+                    let _ = self.file.write_all(&rest);
+                    break;
+                }
+            }
+        }
+
+        read_exact(view_out, &mut buf[..teed])?;
+        Ok(teed)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn splice_read(&mut self, _: RawFd, _: &mut [u8]) -> Result<usize, Errno> {
+        Err(Errno::EINVAL)
+    }
+}
+
+fn pipe() -> Result<(OwnedFd, OwnedFd), Errno> {
+    let (read, write) = nix::unistd::pipe()?;
+    let fds = unsafe { (OwnedFd::from_raw_fd(read), OwnedFd::from_raw_fd(write)) };
+    // Not for the programs the session starts later (hooks).
+    for fd in [read, write] {
+        fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+    }
+    Ok(fds)
+}
+
+fn retry(mut f: impl FnMut() -> isize) -> Result<usize, Errno> {
+    loop {
+        match Errno::result(f()) {
+            Err(Errno::EINTR) => continue,
+            res => return res.map(|num_bytes| num_bytes as usize),
+        }
+    }
+}
+
+fn read_exact(fd: RawFd, mut buf: &mut [u8]) -> Result<(), Errno> {
+    while !buf.is_empty() {
+        match rawio::read(fd, buf)? {
+            0 => return Err(Errno::EPIPE),
+            num_bytes => buf = &mut buf[num_bytes..],
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,211 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+const GAP_BUCKETS: &[f64] = &[0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+
+pub struct Stats {
+    started: Instant,
+    written: usize,
+    write_count: usize,
+    short_writes: usize,
+    read: usize,
+    read_count: usize,
+    chunks: BTreeMap<usize, usize>,
+    gaps: Vec<Duration>,
+    last_read: Option<Instant>,
+    longest_stall: Option<(Duration, Duration)>,
+    eagain_waits: Vec<Duration>,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            written: 0,
+            write_count: 0,
+            short_writes: 0,
+            read: 0,
+            read_count: 0,
+            chunks: BTreeMap::new(),
+            gaps: Vec::new(),
+            last_read: None,
+            longest_stall: None,
+            eagain_waits: Vec::new(),
+        }
+    }
+
+    pub fn record_write(&mut self, num_bytes: usize) {
+        self.written += num_bytes;
+        self.write_count += 1;
+    }
+
+    pub fn record_short_writes(&mut self, count: usize) {
+        self.short_writes += count;
+    }
+
+    pub fn record_read(&mut self, num_bytes: usize) {
+        let now = Instant::now();
+
+        self.read += num_bytes;
+        self.read_count += 1;
+        let bucket = num_bytes.next_power_of_two();
+        *self.chunks.entry(bucket).or_default() += 1;
+
+        if let Some(last) = self.last_read {
+            let gap = now.duration_since(last);
+            self.gaps.push(gap);
+            let longest = self.longest_stall.map(|(gap, _)| gap).unwrap_or_default();
+            if gap > longest {
+                self.longest_stall = Some((gap, last.duration_since(self.started)));
+            }
+        }
+        self.last_read = Some(now);
+    }
+
+    // A read on the non-blocking master that found nothing, and how long it then took
+    // for the master to become readable.
+    pub fn record_eagain(&mut self, wait: Duration) {
+        self.eagain_waits.push(wait);
+    }
+
+    pub fn totals(&self) -> (usize, usize) {
+        (self.read, self.written)
+    }
+
+    pub fn report(&self) {
+        println!("STATS");
+        println!("elapsed {:?}", self.started.elapsed());
+        println!(
+            "written {} bytes in {} writes ({} short)",
+            self.written, self.write_count, self.short_writes
+        );
+        println!("read {} bytes in {} reads", self.read, self.read_count);
+
+        if !self.chunks.is_empty() {
+            println!("chunk sizes:");
+            for (&bucket, &count) in &self.chunks {
+                println!("  <= {bucket:>6}: {count}");
+            }
+        }
+
+        if !self.gaps.is_empty() {
+            let mut sorted = self.gaps.clone();
+            sorted.sort();
+            println!(
+                "inter-read gaps: p50 {:?}, p90 {:?}, p99 {:?}, max {:?}",
+                percentile(&sorted, 50.0),
+                percentile(&sorted, 90.0),
+                percentile(&sorted, 99.0),
+                sorted[sorted.len() - 1],
+            );
+        }
+
+        if let Some((gap, at)) = self.longest_stall {
+            println!("longest stall {gap:?} starting at +{at:?}");
+        }
+
+        if !self.eagain_waits.is_empty() {
+            let mut sorted = self.eagain_waits.clone();
+            sorted.sort();
+            println!(
+                "EAGAIN {} times, waited p50 {:?}, p90 {:?}, max {:?} for data",
+                sorted.len(),
+                percentile(&sorted, 50.0),
+                percentile(&sorted, 90.0),
+                sorted[sorted.len() - 1],
+            );
+        }
+        println!();
+    }
+
+    // Prometheus text exposition format.
+    pub fn metrics(&self) -> String {
+        let mut out = String::new();
+        let mut counter = |name: &str, help: &str, value: usize| {
+            out.push_str(&format!(
+                "# HELP {name} {help}\n# TYPE {name} counter\n{name} {value}\n"
+            ));
+        };
+        counter(
+            "debug_pty_read_bytes_total",
+            "Bytes read from the master.",
+            self.read,
+        );
+        counter(
+            "debug_pty_reads_total",
+            "Reads from the master.",
+            self.read_count,
+        );
+        counter(
+            "debug_pty_written_bytes_total",
+            "Bytes written to the master.",
+            self.written,
+        );
+        counter(
+            "debug_pty_writes_total",
+            "Writes to the master.",
+            self.write_count,
+        );
+        counter(
+            "debug_pty_short_writes_total",
+            "Writes to the master that took only part of the bytes.",
+            self.short_writes,
+        );
+        counter(
+            "debug_pty_read_eagain_total",
+            "Reads from the non-blocking master that returned EAGAIN.",
+            self.eagain_waits.len(),
+        );
+
+        let name = "debug_pty_read_chunk_bytes";
+        out.push_str(&format!(
+            "# HELP {name} Size of each read.\n# TYPE {name} histogram\n"
+        ));
+        let mut cumulative = 0;
+        for (&bucket, &count) in &self.chunks {
+            cumulative += count;
+            out.push_str(&format!("{name}_bucket{{le=\"{bucket}\"}} {cumulative}\n"));
+        }
+        out.push_str(&format!(
+            "{name}_bucket{{le=\"+Inf\"}} {}\n",
+            self.read_count
+        ));
+        out.push_str(&format!(
+            "{name}_sum {}\n{name}_count {}\n",
+            self.read, self.read_count
+        ));
+
+        let name = "debug_pty_read_gap_seconds";
+        out.push_str(&format!(
+            "# HELP {name} Time between consecutive reads.\n# TYPE {name} histogram\n"
+        ));
+        for le in GAP_BUCKETS {
+            let count = self
+                .gaps
+                .iter()
+                .filter(|gap| gap.as_secs_f64() <= *le)
+                .count();
+            out.push_str(&format!("{name}_bucket{{le=\"{le}\"}} {count}\n"));
+        }
+        let sum: Duration = self.gaps.iter().sum();
+        out.push_str(&format!(
+            "{name}_bucket{{le=\"+Inf\"}} {}\n",
+            self.gaps.len()
+        ));
+        out.push_str(&format!(
+            "{name}_sum {}\n{name}_count {}\n",
+            sum.as_secs_f64(),
+            self.gaps.len()
+        ));
+
+        out
+    }
+}
+
+pub fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = (p / 100.0 * (sorted.len() - 1) as f64).round() as usize;
+    sorted[rank.min(sorted.len() - 1)]
+}
@@ -0,0 +1,66 @@
+use crate::procfs;
+use crate::supervise;
+use crate::Session;
+
+use termios::{tcflag_t, Termios, ECHO, ICANON, ISIG};
+
+use std::os::fd::RawFd;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const INTERVAL: Duration = Duration::from_secs(1);
+
+pub fn spawn_status(master: RawFd, pid: u32, session: Arc<Session>) {
+    std::thread::spawn(move || {
+        let mut last = Instant::now();
+        let (mut last_read, mut last_written) = session.stats.lock().unwrap().totals();
+
+        loop {
+            std::thread::sleep(INTERVAL);
+
+            let now = Instant::now();
+            let (read, written) = session.stats.lock().unwrap().totals();
+            let secs = now.duration_since(last).as_secs_f64();
+            let read_rate = (read - last_read) as f64 / secs;
+            let write_rate = (written - last_written) as f64 / secs;
+            (last, last_read, last_written) = (now, read, written);
+
+            let mut child = describe(pid);
+            if let Some(signal) = supervise::stop_signal(pid) {
+                child = format!("{child} on {signal}");
+            }
+            let foreground = match unsafe { libc::tcgetpgrp(master) } {
+                -1 => "none".to_string(),
+                pgrp => format!("{pgrp} {}", describe(pgrp as u32)),
+            };
+            let flags = match Termios::from_fd(master) {
+                Ok(term) => format!(
+                    "ECHO {} ICANON {} ISIG {}",
+                    on_off(term.c_lflag, ECHO),
+                    on_off(term.c_lflag, ICANON),
+                    on_off(term.c_lflag, ISIG),
+                ),
+                Err(e) => format!("termios unavailable: {e}"),
+            };
+
+            println!(
+                "STATUS read {read_rate:.0} B/s | written {write_rate:.0} B/s | pid {pid} {child} | fg {foreground} | {flags}"
+            );
+        }
+    });
+}
+
+fn describe(pid: u32) -> String {
+    match procfs::parse(pid) {
+        Some(stat) => format!("({}) {}", stat.comm, stat.state),
+        None => "exited".to_string(),
+    }
+}
+
+fn on_off(flags: tcflag_t, flag: tcflag_t) -> &'static str {
+    if flags & flag == 0 {
+        "off"
+    } else {
+        "on"
+    }
+}
@@ -0,0 +1,43 @@
+use crate::latency::EchoTracker;
+
+use std::fs::OpenOptions;
+use std::io::Error as IoError;
+use std::os::fd::AsRawFd as _;
+use std::os::unix::fs::OpenOptionsExt as _;
+use std::path::Path;
+use std::sync::Mutex;
+
+pub fn inject(
+    slave: &Path,
+    cmd: &[u8],
+    echo: Option<&Mutex<EchoTracker>>,
+) -> Result<(), (usize, IoError)> {
+    // Opened per injection: holding the slave open would keep the master from seeing EIO
+    // once the child is gone.
+    let tty = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(slave)
+        .map_err(|e| (0, e))?;
+
+    for (i, byte) in cmd.iter().enumerate() {
+        if let Some(echo) = echo {
+            echo.lock().unwrap().injected(*byte);
+        }
+        let res = unsafe { libc::ioctl(tty.as_raw_fd(), libc::TIOCSTI, byte as *const u8) };
+        if res == -1 {
+            return Err((i, IoError::last_os_error()));
+        }
+    }
+
+    Ok(())
+}
+
+pub fn hint(e: &IoError) -> &'static str {
+    match e.raw_os_error() {
+        Some(libc::EPERM) => " (needs CAP_SYS_ADMIN unless the pty is our controlling terminal)",
+        Some(libc::EIO) => " (disabled by the kernel, see sysctl dev.tty.legacy_tiocsti)",
+        _ => "",
+    }
+}
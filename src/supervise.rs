@@ -0,0 +1,142 @@
+use crate::Session;
+
+use nix::sys::signal::Signal;
+
+use std::io::Error as IoError;
+use std::os::fd::{AsRawFd as _, FromRawFd as _, OwnedFd};
+use std::os::unix::process::ExitStatusExt as _;
+use std::process::ExitStatus;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub struct Lifecycle {
+    started: Instant,
+    child_exit: Option<Instant>,
+    master_eio: Option<Instant>,
+}
+
+impl Lifecycle {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            child_exit: None,
+            master_eio: None,
+        }
+    }
+
+    pub fn child_exited_yet(&self) -> bool {
+        self.child_exit.is_some()
+    }
+
+    pub fn closed(&self) -> bool {
+        self.master_eio.is_some()
+    }
+
+    pub fn master_closed(&mut self) {
+        // With --readers every reader gets its own EIO.
+        if self.master_eio.is_some() {
+            return;
+        }
+        let now = Instant::now();
+        self.master_eio = Some(now);
+        match self.child_exit {
+            Some(exit) => println!(
+                "Got Errno::EIO at +{:?}, {:?} after the child exited",
+                now - self.started,
+                now - exit
+            ),
+            None => println!(
+                "Got Errno::EIO at +{:?}, the child is still running",
+                now - self.started
+            ),
+        }
+    }
+
+    fn child_exited(&mut self, status: ExitStatus) {
+        let now = Instant::now();
+        self.child_exit = Some(now);
+        match self.master_eio {
+            Some(eio) => println!(
+                "CHILD exited at +{:?} ({status}), {:?} after EIO on the master",
+                now - self.started,
+                now - eio
+            ),
+            None => println!(
+                "CHILD exited at +{:?} ({status}), output may still be pending",
+                now - self.started
+            ),
+        }
+    }
+}
+
+pub fn spawn_supervisor(pid: u32, session: Arc<Session>) {
+    std::thread::spawn(move || match wait_exit(pid) {
+        Ok(status) => session.lifecycle.lock().unwrap().child_exited(status),
+        Err(e) => tracing::error!("could not watch the child: {e}"),
+    });
+}
+
+// Whatever the child wrote before exiting can still be read from the master until EIO.
+pub fn wait_drained(session: &Session, timeout: Duration) {
+    let start = Instant::now();
+    while !session.lifecycle.lock().unwrap().closed() && start.elapsed() < timeout {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+// Observes the exit without reaping, so `Child::wait` still gets the status afterwards.
+fn wait_exit(pid: u32) -> Result<ExitStatus, IoError> {
+    #[cfg(target_os = "linux")]
+    poll_pidfd(pid)?;
+
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    retry(|| unsafe { libc::waitid(libc::P_PID, pid, &mut info, libc::WEXITED | libc::WNOWAIT) })?;
+
+    let status = unsafe { info.si_status() };
+    Ok(match info.si_code {
+        libc::CLD_EXITED => ExitStatus::from_raw(status << 8),
+        libc::CLD_DUMPED => ExitStatus::from_raw(status | 0x80),
+        _ => ExitStatus::from_raw(status),
+    })
+}
+
+// Elsewhere waitid blocks on its own, just without a pidfd to poll first.
+#[cfg(target_os = "linux")]
+fn poll_pidfd(pid: u32) -> Result<(), IoError> {
+    let pidfd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+    if pidfd >= 0 {
+        let pidfd = unsafe { OwnedFd::from_raw_fd(pidfd as _) };
+        let mut fds = [libc::pollfd {
+            fd: pidfd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        }];
+        retry(|| unsafe { libc::poll(fds.as_mut_ptr(), 1, -1) })?;
+    }
+    Ok(())
+}
+
+// Only works for the direct child; for anything else stopped shows up as a bare `T`.
+pub fn stop_signal(pid: u32) -> Option<Signal> {
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    let flags = libc::WSTOPPED | libc::WNOHANG | libc::WNOWAIT;
+    if unsafe { libc::waitid(libc::P_PID, pid, &mut info, flags) } == -1 {
+        return None;
+    }
+    if unsafe { info.si_pid() } == 0 || info.si_code != libc::CLD_STOPPED {
+        return None;
+    }
+    Signal::try_from(unsafe { info.si_status() }).ok()
+}
+
+fn retry(mut f: impl FnMut() -> libc::c_int) -> Result<(), IoError> {
+    loop {
+        if f() != -1 {
+            return Ok(());
+        }
+        let e = IoError::last_os_error();
+        if e.raw_os_error() != Some(libc::EINTR) {
+            return Err(e);
+        }
+    }
+}
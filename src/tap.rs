@@ -0,0 +1,137 @@
+use crate::Session;
+
+use regex::bytes::Regex;
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant};
+
+pub struct Tap<'a> {
+    session: &'a Session,
+    rx: Receiver<Vec<u8>>,
+    buf: VecDeque<u8>,
+    // Bound by expect-re, for the steps after it.
+    pub vars: BTreeMap<String, String>,
+}
+
+impl<'a> Tap<'a> {
+    pub fn new(session: &'a Session) -> Self {
+        let (tx, rx) = mpsc::channel();
+        *session.tap.lock().unwrap() = Some(tx);
+
+        Self {
+            session,
+            rx,
+            buf: VecDeque::new(),
+            vars: BTreeMap::new(),
+        }
+    }
+
+    pub fn read_byte(&mut self, timeout: Duration) -> Option<u8> {
+        self.fill(Instant::now() + timeout, 1);
+        self.buf.pop_front()
+    }
+
+    pub fn read_exact(&mut self, len: usize, timeout: Duration) -> Option<Vec<u8>> {
+        self.fill(Instant::now() + timeout, len);
+        if self.buf.len() < len {
+            return None;
+        }
+        Some(self.buf.drain(..len).collect())
+    }
+
+    pub fn expect(&mut self, needle: &[u8], timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let buf = self.buf.make_contiguous();
+            if let Some(i) = find(buf, needle) {
+                self.buf.drain(..i + needle.len());
+                return true;
+            }
+
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match self.rx.recv_timeout(timeout) {
+                Ok(bytes) => self.buf.extend(bytes),
+                Err(_) => return false,
+            }
+        }
+    }
+
+    // The groups of the first match, consuming up to its end.
+    pub fn expect_re(&mut self, re: &Regex, timeout: Duration) -> Option<Vec<Option<Vec<u8>>>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let buf = self.buf.make_contiguous();
+            if let Some(captures) = re.captures(buf) {
+                let groups: Vec<_> = captures
+                    .iter()
+                    .map(|group| group.map(|group| group.as_bytes().to_vec()))
+                    .collect();
+                let end = captures.get(0).map_or(0, |whole| whole.end());
+                self.buf.drain(..end);
+                return Some(groups);
+            }
+
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match self.rx.recv_timeout(timeout) {
+                Ok(bytes) => self.buf.extend(bytes),
+                Err(_) => return None,
+            }
+        }
+    }
+
+    // Which of the patterns shows up first in the output, consuming up to its end.
+    pub fn expect_any(&mut self, needles: &[&[u8]], timeout: Duration) -> Option<usize> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let buf = self.buf.make_contiguous();
+            if let Some((i, at)) = earliest(buf, needles) {
+                self.buf.drain(..at + needles[i].len());
+                return Some(i);
+            }
+
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match self.rx.recv_timeout(timeout) {
+                Ok(bytes) => self.buf.extend(bytes),
+                Err(_) => return None,
+            }
+        }
+    }
+
+    pub fn drain(&mut self, quiet: Duration) {
+        self.buf.clear();
+        while self.rx.recv_timeout(quiet).is_ok() {}
+    }
+
+    fn fill(&mut self, deadline: Instant, len: usize) {
+        while self.buf.len() < len {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match self.rx.recv_timeout(timeout) {
+                Ok(bytes) => self.buf.extend(bytes),
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+impl Drop for Tap<'_> {
+    fn drop(&mut self) {
+        *self.session.tap.lock().unwrap() = None;
+    }
+}
+
+// The index of the pattern found first and where.
+pub fn earliest(haystack: &[u8], needles: &[&[u8]]) -> Option<(usize, usize)> {
+    needles
+        .iter()
+        .enumerate()
+        .filter_map(|(i, needle)| Some((i, find(haystack, needle)?)))
+        .min_by_key(|&(_, at)| at)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return Some(0);
+    }
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
@@ -0,0 +1,158 @@
+// Finds the window title sequences (OSC 0 and OSC 2) in the output, across reads, and can
+// take them out of what goes on to the host terminal.
+
+const MAX_OSC: usize = 4096;
+
+enum State {
+    Ground,
+    Escape,
+    Osc,
+    OscEscape,
+}
+
+pub struct Titles {
+    state: State,
+    // The sequence so far, held back until it is known whether to pass it on.
+    held: Vec<u8>,
+}
+
+impl Titles {
+    pub fn new() -> Self {
+        Self {
+            state: State::Ground,
+            held: Vec::new(),
+        }
+    }
+
+    // Returns the bytes to pass on and the titles set. With `strip` the title sequences are
+    // dropped, and an unfinished OSC waits for the next read.
+    pub fn scan(&mut self, buf: &[u8], strip: bool) -> (Vec<u8>, Vec<String>) {
+        let mut out = Vec::with_capacity(buf.len());
+        let mut titles = Vec::new();
+        for &byte in buf {
+            self.state = match self.state {
+                State::Ground if byte == 0x1b => {
+                    self.held.push(byte);
+                    State::Escape
+                }
+                State::Ground => {
+                    out.push(byte);
+                    State::Ground
+                }
+                State::Escape if byte == b']' => {
+                    self.held.push(byte);
+                    State::Osc
+                }
+                State::Escape => {
+                    out.append(&mut self.held);
+                    if byte == 0x1b {
+                        self.held.push(byte);
+                        State::Escape
+                    } else {
+                        out.push(byte);
+                        State::Ground
+                    }
+                }
+                State::Osc if byte == 0x07 => {
+                    self.held.push(byte);
+                    self.finish(&mut out, &mut titles, strip, 1);
+                    State::Ground
+                }
+                State::Osc if byte == 0x1b => {
+                    self.held.push(byte);
+                    State::OscEscape
+                }
+                State::Osc if self.held.len() >= MAX_OSC => {
+                    // Not a sequence any terminal would take; let it through as it is.
+                    out.append(&mut self.held);
+                    out.push(byte);
+                    State::Ground
+                }
+                State::Osc => {
+                    self.held.push(byte);
+                    State::Osc
+                }
+                State::OscEscape if byte == b'\\' => {
+                    self.held.push(byte);
+                    self.finish(&mut out, &mut titles, strip, 2);
+                    State::Ground
+                }
+                State::OscEscape => {
+                    out.append(&mut self.held);
+                    out.push(byte);
+                    State::Ground
+                }
+            };
+        }
+
+        if strip {
+            (out, titles)
+        } else {
+            (buf.to_vec(), titles)
+        }
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>, titles: &mut Vec<String>, strip: bool, end: usize) {
+        let body = &self.held[2..self.held.len() - end];
+        let title = match body.iter().position(|&byte| byte == b';') {
+            Some(i) if matches!(&body[..i], b"0" | b"2") => {
+                Some(String::from_utf8_lossy(&body[i + 1..]).into_owned())
+            }
+            _ => None,
+        };
+        if let Some(title) = title {
+            titles.push(title);
+            if strip {
+                self.held.clear();
+            }
+        }
+        out.append(&mut self.held);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_titles() {
+        let mut titles = Titles::new();
+        let (out, set) = titles.scan(b"a\x1b]0;one\x07b\x1b]2;two\x1b\\c\x1b]1;icon\x07", true);
+        assert_eq!(out, b"abc\x1b]1;icon\x07");
+        assert_eq!(set, ["one", "two"]);
+    }
+
+    #[test]
+    fn passes_titles() {
+        let mut titles = Titles::new();
+        let input = b"\x1b]0;one\x07\x1b[1m";
+        assert_eq!(
+            titles.scan(input, false),
+            (input.to_vec(), vec!["one".to_string()])
+        );
+    }
+
+    #[test]
+    fn title_across_reads() {
+        let mut titles = Titles::new();
+        assert_eq!(titles.scan(b"x\x1b", true), (b"x".to_vec(), vec![]));
+        assert_eq!(titles.scan(b"]2;ti", true), (Vec::new(), vec![]));
+        assert_eq!(titles.scan(b"tle\x1b", true), (Vec::new(), vec![]));
+        assert_eq!(
+            titles.scan(b"\\y", true),
+            (b"y".to_vec(), vec!["title".to_string()])
+        );
+        assert_eq!(titles.scan(b"\x1b", true), (Vec::new(), vec![]));
+        assert_eq!(titles.scan(b"[A", true), (b"\x1b[A".to_vec(), vec![]));
+    }
+
+    #[test]
+    fn unterminated_osc() {
+        let mut titles = Titles::new();
+        let mut input = b"\x1b]0;".to_vec();
+        input.resize(MAX_OSC + 1, b'a');
+        let (out, set) = titles.scan(&input, true);
+        assert_eq!(out, input);
+        assert!(set.is_empty());
+    }
+}
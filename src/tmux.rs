@@ -0,0 +1,129 @@
+use crate::capture::Recorder;
+use crate::cli::TmuxArgs;
+use crate::middleware::Pipeline;
+use crate::{print_text, title, utf8};
+
+use std::io::{BufRead as _, BufReader};
+use std::process::{Command, ExitCode, Stdio};
+
+// Follows one pane of a running tmux through a control mode client, which gets every pane's
+// output as `%output %ID DATA` lines, and runs it through what a session does with a read.
+pub fn run(args: &TmuxArgs) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let pane = match &args.pane {
+        Some(pane) if pane.starts_with('%') => pane.clone(),
+        Some(pane) => return Err(format!("invalid pane {pane:?}, expected an id like %3").into()),
+        None => active_pane(args.session.as_deref())?,
+    };
+
+    let mut cmd = Command::new("tmux");
+    cmd.args(["-C", "attach-session", "-r"]);
+    if let Some(session) = &args.session {
+        cmd.arg("-t").arg(session);
+    }
+    let mut client = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("could not run tmux: {e}"))?;
+    // Closing stdin detaches the client, so it is held until the end.
+    let stdin = client.stdin.take();
+    let stdout = BufReader::new(client.stdout.take().unwrap());
+
+    let mut pipeline = Pipeline::build(&args.filters)?;
+    let mut recorder = match &args.output {
+        Some(log) => Some(Recorder::create(
+            log,
+            args.format(),
+            &format!("tmux pane {pane}"),
+            None,
+            None,
+            None,
+        )?),
+        None => None,
+    };
+    println!("Following tmux pane {pane}, until it or the session goes away");
+
+    let header = format!("READ from pane {pane}");
+    let mut decoder = utf8::Decoder::new();
+    let mut titles = title::Titles::new();
+    for line in stdout.split(b'\n') {
+        let line = line?;
+        if line.starts_with(b"%exit") {
+            break;
+        }
+        let Some(rest) = line.strip_prefix(b"%output ") else {
+            continue;
+        };
+        let Some(space) = rest.iter().position(|&byte| byte == b' ') else {
+            continue;
+        };
+        if rest[..space] != *pane.as_bytes() {
+            continue;
+        }
+
+        let data = unescape(&rest[space + 1..]);
+        let buf = pipeline.output(&data);
+        if let Some(recorder) = &mut recorder {
+            recorder.output(&buf);
+        }
+        print_text(&header, &decoder.decode(&buf), &buf);
+        let (_, set) = titles.scan(&buf, false);
+        for title in set {
+            let text = format!("TITLE {title:?}");
+            println!("{text}");
+            if let Some(recorder) = &mut recorder {
+                recorder.note(text);
+            }
+        }
+        println!();
+    }
+
+    if let Some(recorder) = &mut recorder {
+        recorder.finish();
+    }
+    drop(stdin);
+    let _ = client.wait();
+    Ok(ExitCode::SUCCESS)
+}
+
+fn active_pane(session: Option<&str>) -> Result<String, Box<dyn std::error::Error>> {
+    let mut cmd = Command::new("tmux");
+    cmd.args(["display-message", "-p"]);
+    if let Some(session) = session {
+        cmd.arg("-t").arg(session);
+    }
+    let output = cmd
+        .arg("#{pane_id}")
+        .output()
+        .map_err(|e| format!("could not run tmux: {e}"))?;
+    if !output.status.success() {
+        let error = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("could not find the active pane: {}", error.trim()).into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+// tmux writes bytes below a space, and the backslash, as \ooo.
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        let octal = data
+            .get(i + 1..i + 4)
+            .filter(|digits| data[i] == b'\\' && digits.iter().all(|d| (b'0'..=b'7').contains(d)));
+        match octal {
+            Some(digits) => {
+                let value = digits
+                    .iter()
+                    .fold(0u32, |value, d| value * 8 + u32::from(d - b'0'));
+                out.push(value as u8);
+                i += 4;
+            }
+            None => {
+                out.push(data[i]);
+                i += 1;
+            }
+        }
+    }
+    out
+}
@@ -0,0 +1,210 @@
+use crate::flags;
+use crate::snoop::{self, Syscall};
+
+use nix::sys::ptrace::{self, Options};
+use nix::sys::signal::Signal;
+use nix::sys::wait::{waitpid, WaitPidFlag};
+use nix::unistd::Pid;
+
+use termios::os::target::tcflag_t;
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+// The request type is not u64 everywhere.
+#[allow(clippy::unnecessary_cast)]
+const IOCTLS: &[(u64, &str)] = &[
+    (libc::TCGETS as u64, "TCGETS"),
+    (libc::TCSETS as u64, "TCSETS"),
+    (libc::TCSETSW as u64, "TCSETSW"),
+    (libc::TCSETSF as u64, "TCSETSF"),
+    (libc::TCSBRK as u64, "TCSBRK"),
+    (libc::TCXONC as u64, "TCXONC"),
+    (libc::TCFLSH as u64, "TCFLSH"),
+    (libc::TIOCSCTTY as u64, "TIOCSCTTY"),
+    (libc::TIOCNOTTY as u64, "TIOCNOTTY"),
+    (libc::TIOCGPGRP as u64, "TIOCGPGRP"),
+    (libc::TIOCSPGRP as u64, "TIOCSPGRP"),
+    (libc::TIOCGSID as u64, "TIOCGSID"),
+    (libc::TIOCGWINSZ as u64, "TIOCGWINSZ"),
+    (libc::TIOCSWINSZ as u64, "TIOCSWINSZ"),
+    (libc::TIOCSTI as u64, "TIOCSTI"),
+    (libc::TIOCOUTQ as u64, "TIOCOUTQ"),
+    (libc::FIONREAD as u64, "FIONREAD"),
+    (libc::TIOCGETD as u64, "TIOCGETD"),
+    (libc::TIOCSETD as u64, "TIOCSETD"),
+    (libc::TIOCMGET as u64, "TIOCMGET"),
+];
+
+// The kernel's struct termios, which is what TCGETS/TCSETS* copy: four flag words, c_line
+// and 19 control characters.
+const KERNEL_TERMIOS_LEN: usize = 4 * 4 + 1 + 19;
+
+struct KernelTermios {
+    flags: [tcflag_t; 4],
+    cc: [u8; 19],
+}
+
+impl KernelTermios {
+    fn read(pid: Pid, addr: u64) -> Option<Self> {
+        let buf = snoop::read_memory(pid, addr, KERNEL_TERMIOS_LEN)?;
+        let word = |i: usize| tcflag_t::from_ne_bytes(buf[i * 4..i * 4 + 4].try_into().unwrap());
+        Some(Self {
+            flags: [word(0), word(1), word(2), word(3)],
+            cc: buf[17..].try_into().unwrap(),
+        })
+    }
+
+    // Against the last termios seen on the same tty, or every set flag the first time.
+    fn describe(&self, last: Option<&Self>) -> String {
+        let tables = [flags::IFLAGS, flags::OFLAGS, flags::CFLAGS, flags::LFLAGS];
+        let mut changes = Vec::new();
+        for (i, table) in tables.iter().enumerate() {
+            for &(flag, name) in *table {
+                let now = self.flags[i] & flag != 0;
+                let before = last.map(|last| last.flags[i] & flag != 0);
+                match (before, now) {
+                    (Some(before), now) if before == now => {}
+                    (_, true) => changes.push(format!("+{name}")),
+                    (Some(_), false) => changes.push(format!("-{name}")),
+                    (None, false) => {}
+                }
+            }
+        }
+        for &(index, name) in flags::CC {
+            let Some(&now) = self.cc.get(index) else {
+                continue;
+            };
+            if last.is_none_or(|last| last.cc[index] != now) {
+                changes.push(format!("{name}={now:#04x}"));
+            }
+        }
+
+        if changes.is_empty() {
+            "no changes".to_string()
+        } else {
+            changes.join(" ")
+        }
+    }
+}
+
+// The child stops itself before exec (see `stop_before_exec`), so its first tcsetattr is
+// not missed. Forked descendants are followed too, since the interesting calls usually
+// come from programs the shell starts.
+pub fn spawn_tracer(child: u32) {
+    std::thread::spawn(move || {
+        if let Err(e) = trace(Pid::from_raw(child as i32)) {
+            tracing::error!("tracing the child stopped: {e}");
+        }
+    });
+}
+
+pub fn stop_before_exec() -> Result<(), std::io::Error> {
+    nix::sys::signal::raise(Signal::SIGSTOP)?;
+    Ok(())
+}
+
+fn trace(child: Pid) -> Result<(), nix::errno::Errno> {
+    wait_event(libc::P_PID, child, libc::WSTOPPED)?;
+    let options = Options::PTRACE_O_TRACESYSGOOD
+        | Options::PTRACE_O_TRACEFORK
+        | Options::PTRACE_O_TRACEVFORK
+        | Options::PTRACE_O_TRACECLONE;
+    ptrace::seize(child, options)?;
+    nix::sys::signal::kill(child, Signal::SIGCONT)?;
+
+    let mut termios: HashMap<PathBuf, KernelTermios> = HashMap::new();
+    loop {
+        // Peeked first, so that the exit of the child itself is left for `Child::wait`.
+        let (pid, code) = wait_event(
+            libc::P_ALL,
+            Pid::from_raw(0),
+            libc::WEXITED | libc::WSTOPPED,
+        )?;
+        if matches!(code, libc::CLD_EXITED | libc::CLD_KILLED | libc::CLD_DUMPED) {
+            if pid == child {
+                return Ok(());
+            }
+            waitpid(pid, Some(WaitPidFlag::__WALL))?;
+            continue;
+        }
+
+        let status = waitpid(pid, Some(WaitPidFlag::__WALL))?;
+        if let nix::sys::wait::WaitStatus::PtraceSyscall(pid) = status {
+            let call = snoop::syscall(pid)?;
+            if let Some(ret) = call.ret {
+                report(pid, &call, ret, &mut termios);
+            }
+        }
+        // A tracee may be gone between the two waits.
+        let _ = snoop::resume(status);
+    }
+}
+
+fn wait_event(
+    idtype: libc::idtype_t,
+    pid: Pid,
+    flags: libc::c_int,
+) -> Result<(Pid, i32), nix::errno::Errno> {
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    let flags = flags | libc::WNOWAIT | libc::__WALL;
+    loop {
+        let res = unsafe { libc::waitid(idtype, pid.as_raw() as libc::id_t, &mut info, flags) };
+        match nix::errno::Errno::result(res) {
+            Ok(_) => return Ok((Pid::from_raw(unsafe { info.si_pid() }), info.si_code)),
+            Err(nix::errno::Errno::EINTR) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn report(pid: Pid, call: &Syscall, ret: i64, termios: &mut HashMap<PathBuf, KernelTermios>) {
+    if call.nr != libc::SYS_ioctl {
+        snoop::report_io("TRACE", pid, call, ret);
+        return;
+    }
+
+    let fd = call.args[0] as i32;
+    let Some(tty) = snoop::tty_path(pid, fd) else {
+        return;
+    };
+    let request = call.args[1];
+    let name = match IOCTLS.iter().find(|&&(known, _)| known == request) {
+        Some((_, name)) => name.to_string(),
+        None => format!("ioctl {request:#x}"),
+    };
+    let result = match ret {
+        0.. => ret.to_string(),
+        _ => nix::errno::Errno::from_i32(-ret as i32).to_string(),
+    };
+
+    let detail = match name.as_str() {
+        "TCSETS" | "TCSETSW" | "TCSETSF" | "TCGETS" if ret == 0 => {
+            KernelTermios::read(pid, call.args[2]).map(|new| {
+                let detail = new.describe(termios.get(&tty));
+                termios.insert(tty.clone(), new);
+                detail
+            })
+        }
+        "TIOCSWINSZ" | "TIOCGWINSZ" if ret == 0 => {
+            snoop::read_memory(pid, call.args[2], 4).map(|ws| {
+                let rows = u16::from_ne_bytes([ws[0], ws[1]]);
+                let cols = u16::from_ne_bytes([ws[2], ws[3]]);
+                format!("{rows}x{cols}")
+            })
+        }
+        "TIOCSPGRP" | "TIOCGPGRP" if ret == 0 => snoop::read_memory(pid, call.args[2], 4)
+            .map(|pgrp| format!("pgrp {}", i32::from_ne_bytes(pgrp.try_into().unwrap()))),
+        _ => None,
+    };
+
+    // Reads of an unchanged termios are common and only noise.
+    if name == "TCGETS" && detail.as_deref() == Some("no changes") {
+        return;
+    }
+    println!(
+        "TRACE {pid} {name} fd {fd} {} = {result}{}",
+        tty.display(),
+        detail.map(|d| format!(": {d}")).unwrap_or_default()
+    );
+}
@@ -0,0 +1,80 @@
+use crate::tap::Tap;
+use crate::{execute, Session};
+
+use std::io::Error as IoError;
+use std::os::fd::RawFd;
+use std::time::{Duration, Instant};
+
+// Commands run as long as they like; this only keeps a wedged one from hanging forever.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+const SENTINEL: &str = "__debug_pty_done";
+
+pub struct Step {
+    pub command: String,
+    pub status: Option<i32>,
+    pub duration: Duration,
+}
+
+// Types the commands into the shell one after another, each followed by a printf that
+// reports its status. The printf is split so that its echo never looks like its output.
+// A command that reads its stdin gets the printf too, like anything typed ahead.
+pub fn run(commands: &[String], master: RawFd, session: &Session) -> Result<Vec<Step>, IoError> {
+    let mut tap = Tap::new(session);
+    let mut steps = Vec::new();
+    for (n, command) in (1..).zip(commands) {
+        println!("COMMAND {n} {command:?}");
+        session.record(|rec| rec.mark(format!("command {n} start: {command}")));
+        let start = Instant::now();
+        let line = format!("{command}\nprintf '%s_%d %d\\n' {SENTINEL} {n} $?\n");
+        execute(line.as_bytes(), master, session)?;
+
+        let needle = format!("{SENTINEL}_{n} ");
+        let status = if tap.expect(needle.as_bytes(), COMMAND_TIMEOUT) {
+            read_status(&mut tap)
+        } else {
+            None
+        };
+        let duration = start.elapsed();
+        let shown = match status {
+            Some(status) => format!("status {status}"),
+            None => "unknown status".to_string(),
+        };
+        println!("COMMAND {n} exited with {shown} after {duration:.3?}");
+        session.record(|rec| rec.mark(format!("command {n} end: {shown}")));
+        steps.push(Step {
+            command: command.clone(),
+            status,
+            duration,
+        });
+        if status.is_none() {
+            break;
+        }
+    }
+    execute(b"exit\n", master, session)?;
+    Ok(steps)
+}
+
+pub fn report(steps: &[Step]) {
+    for (n, step) in (1..).zip(steps) {
+        let status = match step.status {
+            Some(status) => status.to_string(),
+            None => "?".to_string(),
+        };
+        println!(
+            "TRANSCRIPT {n} status {status} in {:.3}s {:?}",
+            step.duration.as_secs_f64(),
+            step.command
+        );
+    }
+}
+
+fn read_status(tap: &mut Tap) -> Option<i32> {
+    let mut digits = String::new();
+    while let Some(byte) = tap.read_byte(Duration::from_secs(1)) {
+        if !byte.is_ascii_digit() {
+            break;
+        }
+        digits.push(byte as char);
+    }
+    digits.parse().ok()
+}
@@ -0,0 +1,88 @@
+use libc::{c_char, utmpx};
+use nix::unistd::{Uid, User};
+
+use std::io::Error as IoError;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const WTMP: &[u8] = b"/var/log/wtmp\0";
+
+// Not in the libc crate.
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+extern "C" {
+    fn updwtmpx(file: *const c_char, entry: *const utmpx);
+}
+
+// A USER_PROCESS entry for the pty, turned into a DEAD_PROCESS one on drop the way
+// terminal emulators do when their window closes.
+pub struct Registration {
+    entry: utmpx,
+}
+
+impl Registration {
+    pub fn new(slave: &Path, pid: u32) -> Result<Self, IoError> {
+        let line = slave
+            .strip_prefix("/dev")
+            .unwrap_or(slave)
+            .to_string_lossy();
+        let user = User::from_uid(Uid::current())
+            .ok()
+            .flatten()
+            .map(|user| user.name)
+            .unwrap_or_default();
+
+        let mut entry: utmpx = unsafe { std::mem::zeroed() };
+        entry.ut_type = libc::USER_PROCESS;
+        entry.ut_pid = pid as _;
+        copy(&mut entry.ut_line, line.as_bytes());
+        // The id is what matches the DEAD_PROCESS entry to this one; `pts/3` becomes `ts/3`.
+        copy(
+            &mut entry.ut_id,
+            &line.as_bytes()[line.len().saturating_sub(4)..],
+        );
+        copy(&mut entry.ut_user, user.as_bytes());
+        copy(&mut entry.ut_host, b"debug-pty");
+
+        let mut registration = Self { entry };
+        registration.write()?;
+        Ok(registration)
+    }
+
+    fn write(&mut self) -> Result<(), IoError> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        self.entry.ut_tv.tv_sec = now.as_secs() as _;
+        self.entry.ut_tv.tv_usec = now.subsec_micros() as _;
+
+        unsafe {
+            libc::setutxent();
+            let res = libc::pututxline(&self.entry);
+            let e = IoError::last_os_error();
+            libc::endutxent();
+            if res.is_null() {
+                return Err(e);
+            }
+            // pututxline already appends to wtmpx on the BSDs.
+            #[cfg(all(target_os = "linux", target_env = "gnu"))]
+            updwtmpx(WTMP.as_ptr().cast(), &self.entry);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.entry.ut_type = libc::DEAD_PROCESS;
+        self.entry.ut_user.fill(0);
+        self.entry.ut_host.fill(0);
+        match self.write() {
+            Ok(()) => println!("UTMP entry removed"),
+            Err(e) => tracing::warn!("could not remove the utmp entry: {e}"),
+        }
+    }
+}
+
+fn copy(field: &mut [c_char], value: &[u8]) {
+    for (dst, &src) in field.iter_mut().zip(value) {
+        *dst = src as c_char;
+    }
+}
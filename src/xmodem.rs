@@ -0,0 +1,422 @@
+use crate::tap::Tap;
+use crate::{write_master, Session};
+
+use std::io::Error as IoError;
+use std::os::fd::RawFd;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+const POLL_CRC: u8 = b'C';
+const PAD: u8 = 0x1a;
+
+const RETRIES: usize = 10;
+const TIMEOUT: Duration = Duration::from_secs(10);
+const START_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy)]
+pub enum Protocol {
+    Xmodem,
+    Ymodem,
+}
+
+impl Protocol {
+    fn name(self) -> &'static str {
+        match self {
+            Protocol::Xmodem => "XMODEM",
+            Protocol::Ymodem => "YMODEM",
+        }
+    }
+}
+
+enum Error {
+    Io(IoError),
+    Protocol(String),
+}
+
+impl From<IoError> for Error {
+    fn from(e: IoError) -> Self {
+        Error::Io(e)
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+enum Frame {
+    Block(u8, Vec<u8>),
+    Eot,
+    Cancel,
+    Bad,
+    Timeout,
+}
+
+pub fn run(
+    protocol: Protocol,
+    arg: &str,
+    master: RawFd,
+    session: &Session,
+) -> std::result::Result<(), IoError> {
+    let name = protocol.name();
+    let (direction, path) = match arg.split_once(char::is_whitespace) {
+        Some((direction, path)) => (direction, path.trim()),
+        None => (arg, ""),
+    };
+
+    let mut peer = Peer {
+        protocol,
+        tap: Tap::new(session),
+        master,
+        session,
+    };
+    let result = match (direction, path) {
+        (_, "") => Err(Error::Protocol(format!(
+            "usage: :{} send|recv PATH",
+            name.to_lowercase()
+        ))),
+        ("send", path) => peer.send(path),
+        ("recv", path) => peer.recv(path),
+        (direction, _) => Err(Error::Protocol(format!("unknown direction {direction}"))),
+    };
+
+    match result {
+        Ok(()) => println!("{name} done"),
+        Err(Error::Io(e)) => return Err(e),
+        Err(Error::Protocol(msg)) => println!("{name} failed: {msg}"),
+    }
+    println!();
+
+    Ok(())
+}
+
+struct Peer<'a> {
+    protocol: Protocol,
+    tap: Tap<'a>,
+    master: RawFd,
+    session: &'a Session,
+}
+
+impl Peer<'_> {
+    fn log(&self, msg: impl std::fmt::Display) {
+        println!("{} {msg}", self.protocol.name());
+    }
+
+    fn put(&self, bytes: &[u8]) -> Result<()> {
+        write_master(bytes, self.master, self.session)?;
+        Ok(())
+    }
+
+    fn cancel<T>(&self, reason: String) -> Result<T> {
+        self.put(&[CAN, CAN])?;
+        Err(Error::Protocol(reason))
+    }
+
+    fn send(&mut self, path: &str) -> Result<()> {
+        let data = std::fs::read(path)
+            .map_err(|e| Error::Protocol(format!("could not read {path}: {e}")))?;
+        self.log(format_args!("sending {path} ({} bytes)", data.len()));
+
+        match self.protocol {
+            Protocol::Xmodem => {
+                let crc = self.wait_start()?;
+                self.send_blocks(&data, 128, crc)?;
+                self.send_eot()
+            }
+            Protocol::Ymodem => {
+                let name = Path::new(path)
+                    .file_name()
+                    .map_or_else(|| path.into(), |name| name.to_string_lossy());
+                let mut header = format!("{name}\0{}\0", data.len()).into_bytes();
+                header.resize(if header.len() > 128 { 1024 } else { 128 }, 0);
+
+                self.wait_start()?;
+                self.send_block(0, &header, true)?;
+                self.wait_start()?;
+                self.send_blocks(&data, 1024, true)?;
+                self.send_eot()?;
+
+                // An empty header ends the batch.
+                self.wait_start()?;
+                self.send_block(0, &[0; 128], true)
+            }
+        }
+    }
+
+    fn wait_start(&mut self) -> Result<bool> {
+        let deadline = Instant::now() + START_TIMEOUT;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match self.tap.read_byte(timeout) {
+                Some(POLL_CRC) => {
+                    self.log("<- C (CRC mode)");
+                    return Ok(true);
+                }
+                Some(NAK) => {
+                    self.log("<- NAK (checksum mode)");
+                    return Ok(false);
+                }
+                Some(CAN) => return Err(Error::Protocol("cancelled by receiver".into())),
+                Some(_) => continue,
+                None => return Err(Error::Protocol("timed out waiting for receiver".into())),
+            }
+        }
+    }
+
+    fn wait_reply(&mut self) -> Option<u8> {
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match self.tap.read_byte(timeout)? {
+                reply @ (ACK | NAK | CAN) => return Some(reply),
+                _ => continue,
+            }
+        }
+    }
+
+    fn send_blocks(&mut self, data: &[u8], size: usize, crc: bool) -> Result<()> {
+        for (i, chunk) in data.chunks(size).enumerate() {
+            let mut block = chunk.to_vec();
+            block.resize(size, PAD);
+            self.send_block((i + 1) as u8, &block, crc)?;
+        }
+        Ok(())
+    }
+
+    fn send_block(&mut self, num: u8, data: &[u8], crc: bool) -> Result<()> {
+        let mut frame = vec![if data.len() == 1024 { STX } else { SOH }, num, !num];
+        frame.extend_from_slice(data);
+        if crc {
+            frame.extend_from_slice(&crc16(data).to_be_bytes());
+        } else {
+            frame.push(checksum(data));
+        }
+
+        for attempt in 1..=RETRIES {
+            self.log(format_args!(
+                "-> block {num} ({} bytes, attempt {attempt})",
+                data.len()
+            ));
+            self.put(&frame)?;
+
+            match self.wait_reply() {
+                Some(ACK) => {
+                    self.log("<- ACK");
+                    return Ok(());
+                }
+                Some(CAN) => return Err(Error::Protocol("cancelled by receiver".into())),
+                Some(_) => self.log("<- NAK"),
+                None => self.log("timed out waiting for ACK"),
+            }
+        }
+
+        self.cancel(format!("block {num} not acknowledged"))
+    }
+
+    fn send_eot(&mut self) -> Result<()> {
+        for _ in 0..RETRIES {
+            self.log("-> EOT");
+            self.put(&[EOT])?;
+
+            match self.wait_reply() {
+                Some(ACK) => {
+                    self.log("<- ACK");
+                    return Ok(());
+                }
+                Some(CAN) => return Err(Error::Protocol("cancelled by receiver".into())),
+                Some(_) => self.log("<- NAK"),
+                None => self.log("timed out waiting for ACK"),
+            }
+        }
+
+        self.cancel("EOT not acknowledged".into())
+    }
+
+    fn recv(&mut self, path: &str) -> Result<()> {
+        match self.protocol {
+            Protocol::Xmodem => {
+                let mut data = self.recv_blocks()?;
+                while data.last() == Some(&PAD) {
+                    data.pop();
+                }
+                self.save(Path::new(path), &data)
+            }
+            Protocol::Ymodem => {
+                while let Some((name, size)) = self.recv_header()? {
+                    let mut data = self.recv_blocks()?;
+                    if let Some(size) = size {
+                        data.truncate(size);
+                    }
+                    self.save(&Path::new(path).join(name), &data)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn save(&self, path: &Path, data: &[u8]) -> Result<()> {
+        std::fs::write(path, data)
+            .map_err(|e| Error::Protocol(format!("could not write {}: {e}", path.display())))?;
+        self.log(format_args!(
+            "saved {} ({} bytes)",
+            path.display(),
+            data.len()
+        ));
+        Ok(())
+    }
+
+    fn recv_frame(&mut self) -> Frame {
+        let size = match self.tap.read_byte(TIMEOUT) {
+            Some(SOH) => 128,
+            Some(STX) => 1024,
+            Some(EOT) => return Frame::Eot,
+            Some(CAN) => return Frame::Cancel,
+            Some(_) => return Frame::Bad,
+            None => return Frame::Timeout,
+        };
+
+        let Some(rest) = self.tap.read_exact(size + 4, TIMEOUT) else {
+            return Frame::Bad;
+        };
+        let (num, inv) = (rest[0], rest[1]);
+        let data = &rest[2..size + 2];
+        let crc = u16::from_be_bytes([rest[size + 2], rest[size + 3]]);
+
+        if num != !inv || crc16(data) != crc {
+            return Frame::Bad;
+        }
+        Frame::Block(num, data.to_vec())
+    }
+
+    fn recv_header(&mut self) -> Result<Option<(String, Option<usize>)>> {
+        for _ in 0..RETRIES {
+            self.log("-> C");
+            self.put(&[POLL_CRC])?;
+
+            match self.recv_frame() {
+                Frame::Block(0, header) => {
+                    self.log("<- header block");
+                    self.put(&[ACK])?;
+
+                    let mut fields = header.split(|&b| b == 0);
+                    let name = fields.next().unwrap_or_default();
+                    if name.is_empty() {
+                        self.log("end of batch");
+                        return Ok(None);
+                    }
+
+                    // Only the final path component is honoured, so a peer can't write
+                    // outside the target directory.
+                    let name = String::from_utf8_lossy(name);
+                    let name = Path::new(&*name)
+                        .file_name()
+                        .map_or_else(|| "unnamed".into(), |name| name.to_string_lossy())
+                        .into_owned();
+                    let size = fields
+                        .next()
+                        .and_then(|info| info.split(|&b| b == b' ').next())
+                        .and_then(|size| std::str::from_utf8(size).ok()?.parse().ok());
+
+                    self.log(format_args!("receiving {name} ({size:?} bytes)"));
+                    return Ok(Some((name, size)));
+                }
+                Frame::Cancel => return Err(Error::Protocol("cancelled by sender".into())),
+                _ => self.tap.drain(Duration::from_millis(500)),
+            }
+        }
+
+        self.cancel("no header received".into())
+    }
+
+    fn recv_blocks(&mut self) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        let mut expected = 1u8;
+        let mut errors = 0;
+
+        self.log("-> C");
+        self.put(&[POLL_CRC])?;
+
+        while errors < RETRIES {
+            match self.recv_frame() {
+                Frame::Block(num, block) if num == expected => {
+                    self.log(format_args!("<- block {num} ({} bytes)", block.len()));
+                    data.extend_from_slice(&block);
+                    expected = expected.wrapping_add(1);
+                    errors = 0;
+                    self.put(&[ACK])?;
+                }
+                Frame::Block(num, _) if num == expected.wrapping_sub(1) => {
+                    self.log(format_args!("<- duplicate block {num}"));
+                    self.put(&[ACK])?;
+                }
+                Frame::Block(num, _) => {
+                    return self.cancel(format!("expected block {expected}, got {num}"));
+                }
+                Frame::Eot => {
+                    self.log("<- EOT");
+                    self.put(&[ACK])?;
+                    return Ok(data);
+                }
+                Frame::Cancel => return Err(Error::Protocol("cancelled by sender".into())),
+                Frame::Bad => {
+                    errors += 1;
+                    self.log("<- bad frame, NAK");
+                    self.tap.drain(Duration::from_millis(500));
+                    self.put(&[NAK])?;
+                }
+                Frame::Timeout => {
+                    errors += 1;
+                    // Until the first block arrives the sender is still waiting for a poll.
+                    let poll = if data.is_empty() { POLL_CRC } else { NAK };
+                    self.log("timed out waiting for block");
+                    self.put(&[poll])?;
+                }
+            }
+        }
+
+        self.cancel("too many errors".into())
+    }
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &b| sum.wrapping_add(b))
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 == 0 {
+                crc << 1
+            } else {
+                (crc << 1) ^ 0x1021
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc16_xmodem() {
+        // The check value of CRC-16/XMODEM.
+        assert_eq!(crc16(b"123456789"), 0x31c3);
+        assert_eq!(crc16(b""), 0);
+        let mut block = b"debug-pty".to_vec();
+        block.extend_from_slice(&crc16(b"debug-pty").to_be_bytes());
+        assert_eq!(crc16(&block), 0);
+    }
+
+    #[test]
+    fn checksum_wraps() {
+        assert_eq!(checksum(b"123456789"), 0xdd);
+        assert_eq!(checksum(&[0xff, 0x02]), 0x01);
+    }
+}